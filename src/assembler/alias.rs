@@ -0,0 +1,104 @@
+//! `.alias name $N`, which lets the rest of the file refer to register `$N` as
+//! `$name`:
+//!
+//! ```text
+//! .alias counter $5
+//!     load $counter #10
+//! ```
+//!
+//! An alias is only known in the file that declares it, from the declaration
+//! on, and declaring it again points it at another register.
+
+use crate::assembler::assembler_errors::{AssemblerError, ErrorKind};
+use crate::assembler::parser::instruction::AssemblerInstruction;
+use crate::assembler::parser::program::Program;
+use crate::assembler::parser::register::register_number;
+use crate::assembler::sources::FileId;
+use crate::assembler::Token;
+use std::collections::HashMap;
+
+/// Replaces every register alias in `program` with the register it stands
+/// for. Aliases that aren't declared and malformed `.alias` directives are
+/// left for encoding to report.
+pub fn resolve_aliases(program: &mut Program) -> Vec<AssemblerError> {
+    let mut errors = vec![];
+    let mut aliases: HashMap<(FileId, String), u8> = HashMap::new();
+
+    for instruction in &mut program.instructions {
+        for (operand, span) in instruction
+            .operands
+            .iter_mut()
+            .zip(instruction.spans.operands.iter())
+        {
+            if let Token::RegisterAlias { name } = operand {
+                if let Some(reg_num) = aliases.get(&(span.file, name.clone())) {
+                    *operand = Token::Register { reg_num: *reg_num };
+                }
+            }
+        }
+
+        if let Some((name, reg_num)) = alias_declaration(instruction) {
+            if register_number(name).is_some() {
+                errors.push(instruction.in_context(AssemblerError::new(
+                    ErrorKind::InvalidOperand,
+                    instruction.spans.operands[0],
+                    format!("`${}` is already the name of a register", name),
+                )));
+            } else {
+                aliases.insert((instruction.spans.head.file, name.to_string()), reg_num);
+            }
+        }
+    }
+
+    errors
+}
+
+/// The name and the register of an `.alias` directive.
+pub fn alias_declaration(instruction: &AssemblerInstruction) -> Option<(&str, u8)> {
+    match (
+        &instruction.opcode,
+        &instruction.directive,
+        &instruction.operands[..],
+    ) {
+        (
+            None,
+            Some(Token::Directive { name }),
+            [Token::Identifier { name: alias }, Token::Register { reg_num }],
+        ) if name == "alias" => Some((alias, *reg_num)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_errors::error_messages;
+    use crate::assembler::Assembler;
+
+    #[test]
+    fn test_aliases() {
+        let mut assembler = Assembler::new();
+        let bytes = assembler
+            .assemble(
+                ".alias counter $5\n.alias limit $sp\nload $counter #1\nadd $limit $counter $zero\n\
+                 .alias counter $6\nload $counter #2\n",
+            )
+            .unwrap();
+        assert_eq!(
+            bytes,
+            vec![0x01, 0x05, 0x00, 0x01, 0x02, 0x1D, 0x05, 0x00, 0x01, 0x06, 0x00, 0x02]
+        );
+
+        let errors = assembler
+            .assemble("load $early #1\n.alias early $1\n.alias sp $2\n.alias x #3\n")
+            .unwrap_err();
+        let messages = error_messages(&errors);
+        assert_eq!(
+            messages,
+            vec![
+                "`$sp` is already the name of a register",
+                "unknown register `$early`, declare it with `.alias early $N` first",
+                "expected a register, found `#3`",
+            ]
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use crate::assembler::sources::SourceMap;
+use crate::assembler::span::Span;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Syntax,
+    UnknownMnemonic,
+    UnknownDirective,
+    InvalidOperand,
+    OperandCount,
+    OutOfRange,
+    DuplicateLabel,
+    UndefinedLabel,
+    /// A `.macro` definition that is malformed or can't be expanded.
+    Macro,
+    /// An `.include` that can't be read or that closes a cycle.
+    Include,
+    /// An `.if`, `.else` or `.endif` that doesn't pair up with the others.
+    Conditional,
+    /// A `.func` that is malformed or doesn't pair up with its `.endfunc`.
+    Function,
+    /// A function changing a register its callers expect it to keep. Only
+    /// ever a warning.
+    Clobber,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssemblerError {
+    pub kind: ErrorKind,
+    pub span: Span,
+    pub message: String,
+    /// Other places in the source that explain the error.
+    pub notes: Vec<Note>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    pub span: Span,
+    pub message: String,
+}
+
+impl AssemblerError {
+    pub fn new(kind: ErrorKind, span: Span, message: String) -> Self {
+        AssemblerError {
+            kind,
+            span,
+            message,
+            notes: vec![],
+        }
+    }
+
+    pub fn with_note(mut self, span: Span, message: String) -> Self {
+        self.notes.push(Note { span, message });
+        self
+    }
+
+    /// Formats the error together with the offending source line and a caret
+    /// under the text the span points at, followed by its notes.
+    pub fn render(&self, source: &str) -> String {
+        let mut result = render_snippet("error", &self.message, self.span, source, None);
+
+        for note in &self.notes {
+            result.push('\n');
+            result.push_str(&render_snippet(
+                "note",
+                &note.message,
+                note.span,
+                source,
+                None,
+            ));
+        }
+
+        result
+    }
+
+    /// Like `render`, for errors whose spans may be in any of the files of
+    /// `sources`. Every location is prefixed with the name of its file.
+    pub fn render_in(&self, sources: &SourceMap) -> String {
+        self.render_level_in("error", sources)
+    }
+
+    /// Like `render_in`, for the assembler's warnings.
+    pub fn render_warning_in(&self, sources: &SourceMap) -> String {
+        self.render_level_in("warning", sources)
+    }
+
+    fn render_level_in(&self, level: &str, sources: &SourceMap) -> String {
+        let snippet = |level: &str, message: &str, span: Span| match sources.get(span.file) {
+            Some(file) => render_snippet(level, message, span, &file.text, Some(&file.name)),
+            None => format!("{}: {}", level, message),
+        };
+        let mut result = snippet(level, &self.message, self.span);
+
+        for note in &self.notes {
+            result.push('\n');
+            result.push_str(&snippet("note", &note.message, note.span));
+        }
+
+        result
+    }
+}
+
+fn render_snippet(
+    level: &str,
+    message: &str,
+    span: Span,
+    source: &str,
+    file_name: Option<&str>,
+) -> String {
+    let (line, column) = span.line_col(source);
+    let location = match file_name {
+        Some(name) => format!("{}:{}:{}", name, line, column),
+        None => format!("{}:{}", line, column),
+    };
+    let text = source.lines().nth(line - 1).unwrap_or("");
+    let gutter = " ".repeat(line.to_string().len());
+    let indent: String = text
+        .chars()
+        .take(column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let width = span.len().min(text.chars().count() + 1 - column).max(1);
+
+    format!(
+        "{}: {}\n{}--> {}\n{} |\n{} | {}\n{} | {}{}",
+        level,
+        message,
+        gutter,
+        location,
+        gutter,
+        line,
+        text,
+        gutter,
+        indent,
+        "^".repeat(width)
+    )
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The messages of `errors`, in order, so tests can compare them all at once.
+#[cfg(test)]
+pub fn error_messages(errors: &[AssemblerError]) -> Vec<&str> {
+    errors.iter().map(|e| e.message.as_str()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_error() {
+        let source = "load $0 #1\nloda $1 #2\n";
+        let error = AssemblerError::new(
+            ErrorKind::UnknownMnemonic,
+            Span::new(11, 15),
+            "unknown mnemonic `loda`, did you mean `load`?".to_string(),
+        );
+        assert_eq!(
+            error.render(source),
+            "error: unknown mnemonic `loda`, did you mean `load`?\n \
+             --> 2:1\n  |\n2 | loda $1 #2\n  | ^^^^"
+        );
+    }
+
+    #[test]
+    fn test_render_error_in_file() {
+        let mut sources = SourceMap::new();
+        sources.add("main.iasm".to_string(), "hlt\n".to_string());
+        sources.add("lib.iasm".to_string(), "load $0 @nowhere\n".to_string());
+        let span = Span {
+            file: 1,
+            ..Span::new(8, 16)
+        };
+        let error = AssemblerError::new(
+            ErrorKind::UndefinedLabel,
+            span,
+            "label `nowhere` is used but never declared".to_string(),
+        )
+        .with_note(Span::new(0, 3), "included here".to_string());
+        assert_eq!(
+            error.render_in(&sources),
+            "error: label `nowhere` is used but never declared\n \
+             --> lib.iasm:1:9\n  |\n1 | load $0 @nowhere\n  |         ^^^^^^^^\n\
+             note: included here\n \
+             --> main.iasm:1:1\n  |\n1 | hlt\n  | ^^^"
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! Conditional assembly, which keeps or drops the statements between a
+//! condition and its `.endif`:
+//!
+//! ```text
+//! .ifdef DEBUG
+//!     load $1 #1
+//! .else
+//!     load $1 #0
+//! .endif
+//! .if BUF_SIZE>64
+//!     ...
+//! .endif
+//! ```
+//!
+//! `.if` keeps its statements when its expression isn't zero, `.ifdef` and
+//! `.ifndef` when a name is, or isn't, defined. Expressions may use symbols
+//! defined through `Assembler::define` and `.equ` constants declared before
+//! them, and names may also be labels declared before them. Conditions are
+//! settled while files are read, so an `.include` in a dropped branch isn't
+//! followed, and before macros are expanded, so they can't depend on macro
+//! arguments.
+
+use crate::assembler::assembler_errors::{AssemblerError, ErrorKind};
+use crate::assembler::expression::{Expression, Scope};
+use crate::assembler::parser::instruction::AssemblerInstruction;
+use crate::assembler::span::Span;
+use crate::assembler::symbols::{Symbol, SymbolTable, SymbolType};
+use crate::assembler::{Section, Token};
+
+/// An `.if`, `.ifdef` or `.ifndef` whose `.endif` hasn't come yet.
+struct Block {
+    /// Whether the statements around the block are kept.
+    outer: bool,
+    condition: bool,
+    in_else: bool,
+    /// Span of the directive that opened the block.
+    span: Span,
+}
+
+impl Block {
+    fn active(&self) -> bool {
+        self.outer && self.condition != self.in_else
+    }
+}
+
+/// Decides which statements are kept, taking them one at a time in the order
+/// they are assembled in.
+pub struct Conditionals {
+    errors: Vec<AssemblerError>,
+    blocks: Vec<Block>,
+    constants: SymbolTable,
+    labels: Vec<String>,
+}
+
+impl Conditionals {
+    /// `defines` are the symbols defined outside of the source.
+    pub fn new(defines: &[(String, i64)]) -> Conditionals {
+        let mut constants = SymbolTable::new();
+
+        for (name, value) in defines {
+            define(&mut constants, name, *value);
+        }
+
+        Conditionals {
+            errors: vec![],
+            blocks: vec![],
+            constants,
+            labels: vec![],
+        }
+    }
+
+    /// What is kept of `instruction`: nothing if its condition doesn't hold,
+    /// and at most its label if it is a conditional directive.
+    pub fn filter(&mut self, instruction: AssemblerInstruction) -> Option<AssemblerInstruction> {
+        let active = self.blocks.last().is_none_or(Block::active);
+        let directive = match (&instruction.opcode, &instruction.directive) {
+            (None, Some(Token::Directive { name })) => name.as_str(),
+            _ => "",
+        };
+
+        match directive {
+            "if" | "ifdef" | "ifndef" => {
+                let condition = if active {
+                    match condition(&instruction, directive, &self.constants, &self.labels) {
+                        Ok(condition) => condition,
+                        Err(e) => {
+                            if instruction.reports(&e) {
+                                self.errors.push(e);
+                            }
+                            false
+                        }
+                    }
+                } else {
+                    false
+                };
+
+                self.blocks.push(Block {
+                    outer: active,
+                    condition,
+                    in_else: false,
+                    span: instruction.spans.head,
+                });
+            }
+            "else" => match self.blocks.last_mut() {
+                Some(block) if block.in_else => self.errors.push(error(
+                    instruction.spans.head,
+                    "`.else` comes after another `.else` of the same `.if`",
+                )),
+                Some(block) => block.in_else = true,
+                None => self
+                    .errors
+                    .push(error(instruction.spans.head, "`.else` without `.if`")),
+            },
+            "endif" => {
+                if self.blocks.pop().is_none() {
+                    self.errors
+                        .push(error(instruction.spans.head, "`.endif` without `.if`"));
+                }
+            }
+            _ if active => {
+                if let Some(name) = instruction.label_name() {
+                    self.labels.push(name.to_string());
+                }
+
+                if directive == "equ" {
+                    self.add_constant(&instruction);
+                }
+
+                return Some(instruction);
+            }
+            _ => return None,
+        }
+
+        // A label in front of a conditional directive belongs to what follows.
+        if let (Some(_), true) = (&instruction.label, active) {
+            self.labels
+                .extend(instruction.label_name().map(str::to_string));
+
+            return Some(AssemblerInstruction {
+                label: instruction.label.clone(),
+                spans: instruction.spans.clone(),
+                leading_comments: instruction.leading_comments.clone(),
+                ..AssemblerInstruction::default()
+            });
+        }
+
+        None
+    }
+
+    /// The errors found along the way, along with the blocks left open.
+    pub fn finish(mut self) -> Vec<AssemblerError> {
+        for block in &self.blocks {
+            self.errors.push(error(
+                block.span,
+                "this block is never closed with `.endif`",
+            ));
+        }
+
+        self.errors
+    }
+
+    fn add_constant(&mut self, instruction: &AssemblerInstruction) {
+        if let [Token::Identifier { name }, value] = &instruction.operands[..] {
+            let value = Expression::from_token(value)
+                .and_then(|expr| expr.evaluate(Scope::Complete(&self.constants)).ok());
+
+            // Errors are reported when the constant is added again along with
+            // the labels.
+            if let (Some(value), false) = (value, self.constants.has_symbol(name)) {
+                define(&mut self.constants, name, value);
+            }
+        }
+    }
+}
+
+fn condition(
+    instruction: &AssemblerInstruction,
+    directive: &str,
+    constants: &SymbolTable,
+    labels: &[String],
+) -> Result<bool, AssemblerError> {
+    let operands: Vec<(&Token, Span)> = instruction.operands_with_spans().collect();
+
+    match (directive, &operands[..]) {
+        ("if", [(operand, span)]) => match Expression::from_token(operand) {
+            Some(expr) => expr
+                .evaluate(Scope::Complete(constants))
+                .map(|value| value != 0)
+                .map_err(|e| e.at(*span, *operand)),
+            None => Err(AssemblerError::new(
+                ErrorKind::InvalidOperand,
+                *span,
+                format!("expected an integer or an expression, found `{}`", operand),
+            )),
+        },
+        (_, [(Token::Identifier { name }, _)]) if directive != "if" => {
+            let defined = constants.has_symbol(name) || labels.contains(name);
+
+            Ok(defined == (directive == "ifdef"))
+        }
+        (_, [(operand, span)]) => Err(AssemblerError::new(
+            ErrorKind::InvalidOperand,
+            *span,
+            format!("expected a name, found `{}`", operand),
+        )),
+        _ => {
+            let expected = if directive == "if" {
+                "an expression"
+            } else {
+                "a name"
+            };
+
+            Err(AssemblerError::new(
+                ErrorKind::OperandCount,
+                instruction.spans.statement,
+                format!("`.{}` takes 1 operand, {}", directive, expected),
+            ))
+        }
+    }
+}
+
+fn define(constants: &mut SymbolTable, name: &str, value: i64) {
+    constants.add_symbol(Symbol::new(
+        name.to_string(),
+        SymbolType::Constant(value),
+        Section::Code,
+        0,
+    ));
+}
+
+fn error(span: Span, message: &str) -> AssemblerError {
+    AssemblerError::new(ErrorKind::Conditional, span, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_errors::error_messages;
+    use crate::assembler::Assembler;
+
+    const SOURCE: &str = "\
+.ifndef SIZE
+.equ SIZE 4
+.endif
+.ifdef DEBUG
+load $1 #1
+.else
+load $1 #0
+.endif
+.if SIZE>8
+load $2 #SIZE
+.if 0
+hlt
+.endif
+.endif
+";
+
+    #[test]
+    fn test_conditionals() {
+        let mut assembler = Assembler::new();
+        assert_eq!(assembler.assemble(SOURCE), Ok(vec![0x01, 0x01, 0x00, 0x00]));
+
+        assembler.define("DEBUG", 1);
+        assembler.define("SIZE", 16);
+        assert_eq!(
+            assembler.assemble(SOURCE),
+            Ok(vec![0x01, 0x01, 0x00, 0x01, 0x01, 0x02, 0x00, 0x10])
+        );
+    }
+
+    #[test]
+    fn test_conditional_errors() {
+        let errors = Assembler::new()
+            .assemble(".else\n.if\n.endif\n.endif\n.ifdef #1\n.endif\n.if 1\n.else\n.else\n")
+            .unwrap_err();
+        let messages = error_messages(&errors);
+        assert_eq!(
+            messages,
+            vec![
+                "`.else` without `.if`",
+                "`.if` takes 1 operand, an expression",
+                "`.endif` without `.if`",
+                "expected a name, found `#1`",
+                "`.else` comes after another `.else` of the same `.if`",
+                "this block is never closed with `.endif`",
+            ]
+        );
+    }
+}
@@ -0,0 +1,486 @@
+//! Operand expressions evaluated at assembly time, such as `#BUF_SIZE*2`,
+//! `#@table+4` or `#(END-START)`, over 64-bit integers.
+
+use crate::assembler::assembler_errors::{AssemblerError, ErrorKind};
+use crate::assembler::span::Span;
+use crate::assembler::symbols::{SymbolTable, SymbolType};
+use crate::assembler::{Section, Token};
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    /// Comparisons give 1 when they hold and 0 otherwise.
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Xor,
+    Or,
+}
+
+impl BinaryOp {
+    /// Binding strength, higher binds tighter, as in C.
+    pub fn precedence(self) -> u8 {
+        use self::BinaryOp::*;
+
+        match self {
+            Mul | Div | Rem => 7,
+            Add | Sub => 6,
+            Shl | Shr => 5,
+            Lt | Gt | Le | Ge => 4,
+            Eq | Ne => 3,
+            And => 2,
+            Xor => 1,
+            Or => 0,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        use self::BinaryOp::*;
+
+        match self {
+            Mul => "*",
+            Div => "/",
+            Rem => "%",
+            Add => "+",
+            Sub => "-",
+            Shl => "<<",
+            Shr => ">>",
+            Lt => "<",
+            Gt => ">",
+            Le => "<=",
+            Ge => ">=",
+            Eq => "==",
+            Ne => "!=",
+            And => "&",
+            Xor => "^",
+            Or => "|",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Integer(i64),
+    /// `@name`, the offset of a label.
+    Label(String),
+    /// A bare name: a `.equ` constant or a label.
+    Name(String),
+    /// `\name`, a macro parameter, replaced when the macro is expanded.
+    Parameter(String),
+    Unary(UnaryOp, Box<Expression>),
+    Binary(BinaryOp, Box<Expression>, Box<Expression>),
+}
+
+/// The symbols an expression is evaluated against.
+#[derive(Debug, Clone, Copy)]
+pub enum Scope<'a> {
+    /// While labels are collected: names not declared yet evaluate to zero,
+    /// only the size of the output matters then.
+    FirstPass(&'a SymbolTable),
+    /// Every name is known, using one that isn't declared is an error.
+    Complete(&'a SymbolTable),
+}
+
+impl<'a> Scope<'a> {
+    pub fn symbols(self) -> &'a SymbolTable {
+        match self {
+            Scope::FirstPass(symbols) | Scope::Complete(symbols) => symbols,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    /// How the name that isn't declared is referred to in messages.
+    Undefined(String),
+    DivisionByZero,
+    Overflow,
+    /// The value depends on where sections are placed in a way the linker
+    /// can't patch, as with a label times two.
+    NotRelocatable,
+}
+
+impl EvalError {
+    /// The error to report for `expression`, found at `span`.
+    pub fn at(self, span: Span, expression: &dyn fmt::Display) -> AssemblerError {
+        match self {
+            EvalError::Undefined(name) => AssemblerError::new(
+                ErrorKind::UndefinedLabel,
+                span,
+                format!("{} is used but never declared", name),
+            ),
+            EvalError::DivisionByZero => AssemblerError::new(
+                ErrorKind::InvalidOperand,
+                span,
+                format!("`{}` divides by zero", expression),
+            ),
+            EvalError::Overflow => AssemblerError::new(
+                ErrorKind::OutOfRange,
+                span,
+                format!("`{}` overflows a 64-bit integer", expression),
+            ),
+            EvalError::NotRelocatable => AssemblerError::new(
+                ErrorKind::InvalidOperand,
+                span,
+                format!(
+                    "`{}` can't be relocated, use a label plus or minus a constant, \
+                     or the distance between two labels",
+                    expression
+                ),
+            ),
+        }
+    }
+}
+
+impl Expression {
+    /// The expression an operand token stands for, if it stands for a number.
+    pub fn from_token(token: &Token) -> Option<Expression> {
+        match token {
+            Token::IntegerOperand { value } => Some(Expression::Integer(*value)),
+            Token::LabelUsabe { name } => Some(Expression::Label(name.clone())),
+            Token::Identifier { name } => Some(Expression::Name(name.clone())),
+            Token::Parameter { name } => Some(Expression::Parameter(name.clone())),
+            Token::Expression { expr } => Some(expr.clone()),
+            _ => None,
+        }
+    }
+
+    /// The simplest token for the expression: a plain integer, label, name or
+    /// parameter gets the token it would have had without the expression.
+    pub fn into_token(self) -> Token {
+        match self {
+            Expression::Integer(value) => Token::IntegerOperand { value },
+            Expression::Label(name) => Token::LabelUsabe { name },
+            Expression::Name(name) => Token::Identifier { name },
+            Expression::Parameter(name) => Token::Parameter { name },
+            expr => Token::Expression { expr },
+        }
+    }
+
+    pub fn evaluate(&self, scope: Scope) -> Result<i64, EvalError> {
+        let symbols = scope.symbols();
+        let undefined = |name: &str| match scope {
+            Scope::FirstPass(_) => Ok(0),
+            Scope::Complete(_) => Err(EvalError::Undefined(name.to_string())),
+        };
+
+        match self {
+            Expression::Integer(value) => Ok(*value),
+            Expression::Label(name) => match symbols.symbol_value(name) {
+                Some(offset) => Ok(i64::from(offset)),
+                None => undefined(&format!("label `{}`", name)),
+            },
+            Expression::Name(name) => match symbols.value(name) {
+                Some(value) => Ok(value),
+                None => undefined(&format!("`{}`", name)),
+            },
+            Expression::Parameter(name) => undefined(&format!("`\\{}`", name)),
+            Expression::Unary(op, operand) => {
+                let operand = operand.evaluate(scope)?;
+
+                match op {
+                    UnaryOp::Neg => operand.checked_neg().ok_or(EvalError::Overflow),
+                    UnaryOp::Not => Ok(!operand),
+                }
+            }
+            Expression::Binary(op, left, right) => {
+                let (left, right) = (left.evaluate(scope)?, right.evaluate(scope)?);
+                let shift = u32::try_from(right).ok();
+
+                let result = match op {
+                    BinaryOp::Mul => left.checked_mul(right),
+                    BinaryOp::Div | BinaryOp::Rem if right == 0 => {
+                        return Err(EvalError::DivisionByZero);
+                    }
+                    BinaryOp::Div => left.checked_div(right),
+                    BinaryOp::Rem => left.checked_rem(right),
+                    BinaryOp::Add => left.checked_add(right),
+                    BinaryOp::Sub => left.checked_sub(right),
+                    BinaryOp::Shl => shift.and_then(|shift| left.checked_shl(shift)),
+                    BinaryOp::Shr => shift.and_then(|shift| left.checked_shr(shift)),
+                    BinaryOp::Lt => Some(i64::from(left < right)),
+                    BinaryOp::Gt => Some(i64::from(left > right)),
+                    BinaryOp::Le => Some(i64::from(left <= right)),
+                    BinaryOp::Ge => Some(i64::from(left >= right)),
+                    BinaryOp::Eq => Some(i64::from(left == right)),
+                    BinaryOp::Ne => Some(i64::from(left != right)),
+                    BinaryOp::And => Some(left & right),
+                    BinaryOp::Xor => Some(left ^ right),
+                    BinaryOp::Or => Some(left | right),
+                };
+
+                result.ok_or(EvalError::Overflow)
+            }
+        }
+    }
+
+    /// Calls `f` with every integer, label, name and parameter of the
+    /// expression, which it may replace.
+    pub fn for_each_leaf(&mut self, f: &mut impl FnMut(&mut Expression)) {
+        match self {
+            Expression::Unary(_, operand) => operand.for_each_leaf(f),
+            Expression::Binary(_, left, right) => {
+                left.for_each_leaf(f);
+                right.for_each_leaf(f);
+            }
+            leaf => f(leaf),
+        }
+    }
+
+    /// Names of the labels and constants the expression uses.
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            Expression::Label(name) | Expression::Name(name) => vec![name],
+            Expression::Unary(_, operand) => operand.names(),
+            Expression::Binary(_, left, right) => {
+                let mut names = left.names();
+                names.extend(right.names());
+                names
+            }
+            Expression::Integer(_) | Expression::Parameter(_) => vec![],
+        }
+    }
+
+    /// The symbol whose address the linker has to add to the value of the
+    /// expression once sections are placed: `None` when the value doesn't
+    /// depend on where the sections go, as with plain numbers or the distance
+    /// between two labels of one section.
+    pub fn relocation(&self, symbols: &SymbolTable) -> Result<Option<String>, EvalError> {
+        let mut terms = vec![];
+
+        if !self.linear_terms(symbols, 1, &mut terms) {
+            return Err(EvalError::NotRelocatable);
+        }
+
+        // Labels of a section move together, every extern label moves on its own.
+        let base = |name: &str| match symbols.symbol(name) {
+            Some(symbol) if symbol.symbol_type == SymbolType::Extern => Err(name.to_string()),
+            Some(symbol) => Ok(symbol.section),
+            None => Ok(Section::Code),
+        };
+        // The first label of each base, with the sum of the factors of its labels.
+        let mut moving: Vec<(&str, i64)> = vec![];
+
+        for (name, factor) in terms {
+            match moving
+                .iter_mut()
+                .find(|(other, _)| base(other) == base(name))
+            {
+                Some((_, sum)) => *sum += factor,
+                None => moving.push((name, factor)),
+            }
+        }
+
+        moving.retain(|(_, sum)| *sum != 0);
+
+        match moving[..] {
+            [] => Ok(None),
+            [(name, 1)] => Ok(Some(name.to_string())),
+            _ => Err(EvalError::NotRelocatable),
+        }
+    }
+
+    /// Collects the labels of the expression with the factor each one is
+    /// multiplied by, or returns false if the expression isn't a sum of
+    /// labels times constants.
+    fn linear_terms<'e>(
+        &'e self,
+        symbols: &SymbolTable,
+        factor: i64,
+        terms: &mut Vec<(&'e str, i64)>,
+    ) -> bool {
+        let constant = |expr: &Expression| -> Option<i64> {
+            if expr.is_constant(symbols) {
+                expr.evaluate(Scope::Complete(symbols)).ok()
+            } else {
+                None
+            }
+        };
+
+        match self {
+            _ if self.is_constant(symbols) => true,
+            Expression::Label(name) | Expression::Name(name) => {
+                terms.push((name, factor));
+                true
+            }
+            Expression::Unary(UnaryOp::Neg, operand) => {
+                operand.linear_terms(symbols, -factor, terms)
+            }
+            Expression::Binary(BinaryOp::Add, left, right) => {
+                left.linear_terms(symbols, factor, terms)
+                    && right.linear_terms(symbols, factor, terms)
+            }
+            Expression::Binary(BinaryOp::Sub, left, right) => {
+                left.linear_terms(symbols, factor, terms)
+                    && right.linear_terms(symbols, -factor, terms)
+            }
+            Expression::Binary(BinaryOp::Mul, left, right) => {
+                match (constant(left), constant(right)) {
+                    (Some(value), _) => right.linear_terms(symbols, factor * value, terms),
+                    (_, Some(value)) => left.linear_terms(symbols, factor * value, terms),
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the expression only uses numbers and `.equ` constants.
+    fn is_constant(&self, symbols: &SymbolTable) -> bool {
+        match self {
+            Expression::Integer(_) => true,
+            Expression::Name(name) => symbols
+                .symbol(name)
+                .is_some_and(|symbol| matches!(symbol.symbol_type, SymbolType::Constant(_))),
+            Expression::Label(_) | Expression::Parameter(_) => false,
+            Expression::Unary(_, operand) => operand.is_constant(symbols),
+            Expression::Binary(_, left, right) => {
+                left.is_constant(symbols) && right.is_constant(symbols)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Integer(value) => write!(f, "{}", value),
+            Expression::Label(name) => write!(f, "@{}", name),
+            Expression::Name(name) => write!(f, "{}", name),
+            Expression::Parameter(name) => write!(f, "\\{}", name),
+            Expression::Unary(op, operand) => {
+                let op = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "~",
+                };
+
+                match **operand {
+                    Expression::Binary(..) => write!(f, "{}({})", op, operand),
+                    _ => write!(f, "{}{}", op, operand),
+                }
+            }
+            Expression::Binary(op, left, right) => {
+                // Parenthesize operands that bind looser than `op`, and a right
+                // operand that binds the same, as operators are left-associative.
+                let wrap = |operand: &Expression, right_side: bool| match operand {
+                    Expression::Binary(inner, ..) => {
+                        inner.precedence() < op.precedence()
+                            || (right_side && inner.precedence() == op.precedence())
+                    }
+                    _ => false,
+                };
+
+                if wrap(left, false) {
+                    write!(f, "({})", left)?;
+                } else {
+                    write!(f, "{}", left)?;
+                }
+
+                write!(f, "{}", op.symbol())?;
+
+                if wrap(right, true) {
+                    write!(f, "({})", right)
+                } else {
+                    write!(f, "{}", right)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::parser::expression::parse_expression;
+    use crate::assembler::symbols::Symbol;
+    use nom::types::CompleteStr;
+
+    fn symbols() -> SymbolTable {
+        let mut symbols = SymbolTable::new();
+        let symbol = |name: &str, symbol_type, section, offset| {
+            Symbol::new(name.to_string(), symbol_type, section, offset)
+        };
+
+        symbols.add_symbol(symbol("SIZE", SymbolType::Constant(16), Section::Code, 0));
+        symbols.add_symbol(symbol("start", SymbolType::Label, Section::Code, 4));
+        symbols.add_symbol(symbol("end", SymbolType::Label, Section::Code, 20));
+        symbols.add_symbol(symbol("table", SymbolType::Label, Section::Data, 8));
+        symbols.add_symbol(symbol("print", SymbolType::Extern, Section::Code, 0));
+        symbols
+    }
+
+    fn parse(source: &str) -> Expression {
+        let (rest, expr) = parse_expression(CompleteStr(source)).unwrap();
+        assert_eq!(rest, CompleteStr(""), "{}", source);
+        expr
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let symbols = symbols();
+        let evaluate = |source: &str| parse(source).evaluate(Scope::Complete(&symbols));
+
+        assert_eq!(evaluate("SIZE*2"), Ok(32));
+        assert_eq!(evaluate("@table+4"), Ok(12));
+        assert_eq!(evaluate("(end-start)/2"), Ok(8));
+        assert_eq!(evaluate("1+2*3-4"), Ok(3));
+        assert_eq!(evaluate("1<<4|1"), Ok(17));
+        assert_eq!(evaluate("-(2+3)%3"), Ok(-2));
+        assert_eq!(evaluate("~0&0xFF^0x0F"), Ok(0xF0));
+        assert_eq!(evaluate("SIZE>8==1"), Ok(1));
+        assert_eq!(evaluate("SIZE+1<=16|SIZE!=16"), Ok(0));
+        assert_eq!(
+            evaluate("missing+1"),
+            Err(EvalError::Undefined("`missing`".to_string()))
+        );
+        assert_eq!(evaluate("1/(SIZE-16)"), Err(EvalError::DivisionByZero));
+        assert_eq!(evaluate("1<<64"), Err(EvalError::Overflow));
+        assert_eq!(
+            parse("missing+1").evaluate(Scope::FirstPass(&symbols)),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        for source in &[
+            "SIZE*2", "@table+4", "(1+2)*3", "1-(2-3)", "-(1+2)", "\\n<<1",
+        ] {
+            assert_eq!(parse(source).to_string(), *source);
+        }
+    }
+
+    #[test]
+    fn test_relocation() {
+        let symbols = symbols();
+        let relocation = |source: &str| parse(source).relocation(&symbols);
+
+        assert_eq!(relocation("SIZE*2"), Ok(None));
+        assert_eq!(relocation("end-start"), Ok(None));
+        assert_eq!(relocation("@table+SIZE"), Ok(Some("table".to_string())));
+        assert_eq!(relocation("2*start-end"), Ok(Some("start".to_string())));
+        assert_eq!(relocation("print-4"), Ok(Some("print".to_string())));
+        assert_eq!(relocation("start*2"), Err(EvalError::NotRelocatable));
+        assert_eq!(relocation("start+table"), Err(EvalError::NotRelocatable));
+        assert_eq!(relocation("print-start"), Err(EvalError::NotRelocatable));
+        assert_eq!(relocation("start&0xFF"), Err(EvalError::NotRelocatable));
+    }
+}
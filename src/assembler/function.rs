@@ -0,0 +1,392 @@
+//! `.func` / `.endfunc`, which wrap a function in the code the calling
+//! convention asks for:
+//!
+//! ```text
+//! .func sum3 #8
+//!     add $a0 $a1 $v0
+//!     add $v0 $a2 $v0
+//! .endfunc
+//! ```
+//!
+//! A function is called with `call`, takes its arguments in `$a0` to `$a3` and
+//! returns its results in `$v0` and `$v1`. It may change any other register
+//! except `$s0` to `$s7`, `$fp` and `$sp`, which its caller expects to find as
+//! it left them.
+//!
+//! `.func` takes the name of the function, the registers it saves and the size
+//! of its frame, all but the name optional. It declares the name as a label,
+//! pushes the registers and moves `$sp` down by the frame size. `.endfunc`
+//! moves it back, pops the registers and returns, and `jmp @.return` does the
+//! same from anywhere in the body.
+//!
+//! The frame is the bytes from `$sp` up, which `lws` and `sws` reach, as in
+//! `sws $a0 $sp #4`, as long as the body leaves `$sp` where the prologue put
+//! it. Moving `$sp` goes through `$at`, so a function with a frame can't keep
+//! anything in `$at` across its prologue, nor return anything in it.
+
+use crate::assembler::assembler_errors::{AssemblerError, ErrorKind};
+use crate::assembler::parser::instruction::{AssemblerInstruction, InstructionSpans};
+use crate::assembler::parser::program::Program;
+use crate::assembler::parser::register::{register_number, REGISTER_NAMES};
+use crate::assembler::span::Span;
+use crate::assembler::Token;
+use crate::instruction::Opcode::{self, *};
+use std::mem;
+
+/// Registers a function has to leave as it found them, besides `$sp`, which
+/// the epilogue restores.
+const CALLEE_SAVED: [&str; 9] = ["s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "fp"];
+
+/// A `.func` whose `.endfunc` hasn't come yet.
+struct Function {
+    name: String,
+    saved: Vec<(Token, Span)>,
+    frame: Option<(Token, Span)>,
+    /// Span of the `.func` directive.
+    span: Span,
+}
+
+/// Adds the prologue and the epilogue of every function in `program`.
+/// Malformed `.func` operands are left to encoding to report.
+pub fn expand_functions(program: &mut Program) -> Vec<AssemblerError> {
+    let mut errors = vec![];
+    let mut result = vec![];
+    let mut function: Option<Function> = None;
+
+    for mut instruction in mem::take(&mut program.instructions) {
+        match directive(&instruction) {
+            Some("func") => {
+                let name = match instruction.operands.first() {
+                    Some(Token::Identifier { name }) => name.clone(),
+                    _ => {
+                        result.push(instruction);
+                        continue;
+                    }
+                };
+
+                if let Some(outer) = &function {
+                    errors.push(
+                        AssemblerError::new(
+                            ErrorKind::Function,
+                            instruction.spans.head,
+                            format!("`{}` is declared inside of `{}`", name, outer.name),
+                        )
+                        .with_note(outer.span, format!("`{}` starts here", outer.name)),
+                    );
+                    result.push(instruction);
+                    continue;
+                }
+
+                let saved: Vec<(Token, Span)> = instruction
+                    .operands_with_spans()
+                    .skip(1)
+                    .filter(|(operand, _)| is_register(operand))
+                    .map(|(operand, span)| (operand.clone(), span))
+                    .collect();
+                let frame = match instruction.operands_with_spans().last() {
+                    Some((operand @ Token::IntegerOperand { .. }, span))
+                    | Some((operand @ Token::Expression { .. }, span)) => {
+                        Some((operand.clone(), span))
+                    }
+                    _ => None,
+                };
+
+                // A label in front of `.func` stays where it is.
+                if let Some(label) = instruction.label.take() {
+                    result.push(AssemblerInstruction {
+                        label: Some(label),
+                        spans: instruction.spans.clone(),
+                        leading_comments: mem::take(&mut instruction.leading_comments),
+                        ..AssemblerInstruction::default()
+                    });
+                }
+
+                instruction.label = Some(Token::LabelDeclaration { name: name.clone() });
+
+                let mut prologue: Vec<AssemblerInstruction> = saved
+                    .iter()
+                    .map(|register| generated(&instruction, PUSH, &[Given(register)]))
+                    .collect();
+
+                if let Some(frame) = &frame {
+                    prologue.push(generated(&instruction, LOAD, &[Fixed("at"), Given(frame)]));
+                    prologue.push(generated(
+                        &instruction,
+                        SUB,
+                        &[Fixed("sp"), Fixed("at"), Fixed("sp")],
+                    ));
+                }
+
+                function = Some(Function {
+                    name,
+                    saved,
+                    frame,
+                    span: instruction.spans.head,
+                });
+                result.push(instruction);
+                result.extend(prologue);
+            }
+            Some("endfunc") => match function.take() {
+                Some(function) => {
+                    let mut epilogue = match &function.frame {
+                        Some(frame) => vec![
+                            generated(&instruction, LOAD, &[Fixed("at"), Given(frame)]),
+                            generated(&instruction, ADD, &[Fixed("sp"), Fixed("at"), Fixed("sp")]),
+                        ],
+                        None => vec![],
+                    };
+
+                    epilogue.extend(
+                        function
+                            .saved
+                            .iter()
+                            .rev()
+                            .map(|register| generated(&instruction, POP, &[Given(register)])),
+                    );
+                    epilogue.push(generated(&instruction, RET, &[]));
+                    epilogue[0].label = Some(Token::LabelDeclaration {
+                        name: format!("{}.return", function.name),
+                    });
+                    result.extend(epilogue);
+                    result.push(instruction);
+                }
+                None => {
+                    errors.push(AssemblerError::new(
+                        ErrorKind::Function,
+                        instruction.spans.head,
+                        "`.endfunc` without `.func`".to_string(),
+                    ));
+                    result.push(instruction);
+                }
+            },
+            _ => result.push(instruction),
+        }
+    }
+
+    if let Some(function) = function {
+        errors.push(AssemblerError::new(
+            ErrorKind::Function,
+            function.span,
+            format!("`{}` is never closed with `.endfunc`", function.name),
+        ));
+    }
+
+    program.instructions = result;
+    errors
+}
+
+/// Warns about every function in `program` that changes a register its
+/// caller expects it to keep without saving it. Runs once aliases and
+/// pseudo-instructions are replaced with the registers and opcodes they stand
+/// for.
+pub fn check_clobbers(program: &Program) -> Vec<AssemblerError> {
+    let mut warnings = vec![];
+    let mut function: Option<(&str, Span)> = None;
+    // Saved registers count as reported already.
+    let mut reported: Vec<&str> = vec![];
+
+    for instruction in &program.instructions {
+        match (directive(instruction), &instruction.opcode) {
+            (Some("func"), _) => {
+                function = instruction
+                    .label_name()
+                    .map(|name| (name, instruction.spans.head));
+                reported = instruction
+                    .operands
+                    .iter()
+                    .filter_map(|operand| match operand {
+                        Token::Register { reg_num } => REGISTER_NAMES.get(usize::from(*reg_num)),
+                        _ => None,
+                    })
+                    .cloned()
+                    .collect();
+            }
+            (Some("endfunc"), _) => function = None,
+            (_, Some(Token::Op { code })) => {
+                let (name, span) = match function {
+                    Some(function) => function,
+                    None => continue,
+                };
+                let written = code
+                    .destination()
+                    .and_then(|index| instruction.operands.get(index));
+                let register = match written {
+                    Some(Token::Register { reg_num }) => REGISTER_NAMES.get(usize::from(*reg_num)),
+                    _ => None,
+                };
+
+                if let Some(register) = register {
+                    if !CALLEE_SAVED.contains(register) || reported.contains(register) {
+                        continue;
+                    }
+
+                    reported.push(register);
+                    warnings.push(
+                        instruction.in_context(
+                            AssemblerError::new(
+                                ErrorKind::Clobber,
+                                instruction.spans.statement,
+                                format!(
+                                    "`{}` changes `${}`, which its callers expect it to keep",
+                                    name, register
+                                ),
+                            )
+                            .with_note(span, format!("`{}` starts here", name)),
+                        ),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    warnings
+}
+
+fn directive(instruction: &AssemblerInstruction) -> Option<&str> {
+    match (&instruction.opcode, &instruction.directive) {
+        (None, Some(Token::Directive { name })) => Some(name),
+        _ => None,
+    }
+}
+
+fn is_register(operand: &Token) -> bool {
+    matches!(
+        operand,
+        Token::Register { .. } | Token::RegisterAlias { .. }
+    )
+}
+
+/// An operand of a prologue or epilogue instruction.
+enum Arg<'a> {
+    /// A register, by its conventional name.
+    Fixed(&'static str),
+    /// An operand of the `.func` directive.
+    Given(&'a (Token, Span)),
+}
+
+use self::Arg::*;
+
+/// An instruction of the prologue or the epilogue, with the spans of the
+/// directive it comes from.
+fn generated(directive: &AssemblerInstruction, code: Opcode, args: &[Arg]) -> AssemblerInstruction {
+    let (operands, spans) = args
+        .iter()
+        .map(|arg| match arg {
+            Fixed(name) => (
+                Token::Register {
+                    reg_num: register_number(name).unwrap_or_default(),
+                },
+                directive.spans.head,
+            ),
+            Given((token, span)) => (token.clone(), *span),
+        })
+        .unzip();
+
+    AssemblerInstruction {
+        opcode: Some(Token::Op { code }),
+        operands,
+        spans: InstructionSpans {
+            label: Span::default(),
+            operands: spans,
+            ..directive.spans.clone()
+        },
+        expansion: directive.expansion.clone(),
+        ..AssemblerInstruction::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_errors::error_messages;
+    use crate::assembler::Assembler;
+    use crate::vm::{ExitStatus, VM};
+
+    #[test]
+    fn test_functions() {
+        let mut assembler = Assembler::new();
+        let bytes = assembler
+            .assemble(".func double #8\nadd $a0 $a0 $v0\n.endfunc\n.func noop\n.endfunc\n")
+            .unwrap();
+        assert_eq!(
+            bytes,
+            vec![
+                0x01, 0x01, 0x00, 0x08, 0x03, 0x1D, 0x01, 0x1D, // prologue
+                0x02, 0x04, 0x04, 0x02, // add
+                0x01, 0x01, 0x00, 0x08, 0x02, 0x1D, 0x01, 0x1D, 0x20, // epilogue
+                0x20, // noop
+            ]
+        );
+        assert!(assembler.warnings.is_empty());
+
+        let double = assembler.symbols.symbol("double").unwrap();
+        assert_eq!((double.offset, double.size), (0, Some(21)));
+        assert_eq!(assembler.symbols.symbol_value("double.return"), Some(12));
+        assert_eq!(assembler.symbols.symbol("noop").unwrap().size, Some(1));
+        assert_eq!(
+            assembler.assemble(
+                ".func keep $s0 $s1
+load $s0 #1
+.endfunc
+"
+            ),
+            Ok(vec![
+                0x1C, 0x10, 0x1C, 0x11, // prologue
+                0x01, 0x10, 0x00, 0x01, // load
+                0x1D, 0x11, 0x1D, 0x10, 0x20, // epilogue
+            ])
+        );
+        assert!(assembler.warnings.is_empty());
+        assert!(assembler
+            .assemble(".func f\nbeq $a0 $zero @.return\n.endfunc\n")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_frame() {
+        let mut test_vm = VM::new();
+        test_vm.program = Assembler::new()
+            .assemble(
+                "load $a0 #5\ncall @f\nhlt\n\
+                 .func f #8\nsws $a0 $sp #4\nload $a0 #0\nlws $v0 $sp #4\n.endfunc\n",
+            )
+            .unwrap();
+        assert_eq!(test_vm.run(), ExitStatus::Halted { code: 5 });
+    }
+
+    #[test]
+    fn test_clobbers() {
+        let mut assembler = Assembler::new();
+        assembler
+            .assemble(".func f\nload $s0 #1\ninc $s0\nmov $fp $t0\n.endfunc\nload $s1 #1\n")
+            .unwrap();
+        let warnings = error_messages(&assembler.warnings);
+        assert_eq!(
+            warnings,
+            vec![
+                "`f` changes `$s0`, which its callers expect it to keep",
+                "`f` changes `$fp`, which its callers expect it to keep",
+            ]
+        );
+        assert!(assembler.warnings[0]
+            .render_warning_in(&assembler.sources)
+            .starts_with("warning: `f` changes `$s0`"));
+    }
+
+    #[test]
+    fn test_function_errors() {
+        let errors = Assembler::new()
+            .assemble(".endfunc\n.func f #8 $s0\n.func g\n.endfunc\n.func $1\n")
+            .unwrap_err();
+        let messages = error_messages(&errors);
+        assert_eq!(
+            messages,
+            vec![
+                "`.endfunc` without `.func`",
+                "`g` is declared inside of `f`",
+                "expected a register to save or the frame size, found `#8`",
+                "expected a function name, found `$1`",
+            ]
+        );
+    }
+}
@@ -0,0 +1,304 @@
+//! `.include "path"`, which assembles another file in place of the directive.
+//!
+//! Paths are relative to the directory of the including file. A file is read
+//! at most once per assembly, so a library pulled in by several files doesn't
+//! declare its labels twice, and including a file from itself, directly or
+//! not, is an error.
+
+use crate::assembler::assembler_errors::{AssemblerError, ErrorKind};
+use crate::assembler::conditional::Conditionals;
+use crate::assembler::parser::instruction::AssemblerInstruction;
+use crate::assembler::parser::program::{parse_source, Program};
+use crate::assembler::sources::{FileId, SourceMap};
+use crate::assembler::span::Span;
+use crate::assembler::Token;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// Parses `file` of `sources` with every `.include` replaced by the statements
+/// of the included file, which is read and added to `sources`. `path` is
+/// where `file` was read from, if it was read from disk at all. Statements
+/// whose conditions don't hold are dropped on the way, given the symbols in
+/// `defines`, and includes among them aren't followed.
+pub fn parse_with_includes(
+    sources: &mut SourceMap,
+    file: FileId,
+    path: Option<&Path>,
+    defines: &[(String, i64)],
+) -> (Program, Vec<AssemblerError>) {
+    let mut includer = Includer {
+        sources,
+        stack: vec![],
+        seen: vec![],
+        conditionals: Conditionals::new(defines),
+        errors: vec![],
+    };
+    let dir = match path {
+        Some(path) => {
+            if let Ok(canonical) = path.canonicalize() {
+                includer.stack.push(canonical.clone());
+                includer.seen.push(canonical);
+            }
+
+            path.parent().unwrap_or_else(|| Path::new("")).to_path_buf()
+        }
+        None => PathBuf::new(),
+    };
+
+    let program = includer.parse(file, &dir);
+    let mut errors = includer.errors;
+
+    errors.append(&mut includer.conditionals.finish());
+    (program, errors)
+}
+
+struct Includer<'a> {
+    sources: &'a mut SourceMap,
+    /// Canonical paths of the files being parsed, the innermost one last.
+    stack: Vec<PathBuf>,
+    /// Canonical paths of every file read so far.
+    seen: Vec<PathBuf>,
+    conditionals: Conditionals,
+    errors: Vec<AssemblerError>,
+}
+
+impl<'a> Includer<'a> {
+    fn parse(&mut self, file: FileId, dir: &Path) -> Program {
+        let text = match self.sources.get(file) {
+            Some(source) => source.text.clone(),
+            None => String::new(),
+        };
+        let (mut program, errors) = parse_source(&text);
+
+        for mut error in errors {
+            error.span.file = file;
+            self.errors.push(error);
+        }
+
+        for comment in &mut program.trailing_comments {
+            comment.span.file = file;
+        }
+
+        for mut instruction in mem::take(&mut program.instructions) {
+            instruction.for_each_span(|span| span.file = file);
+
+            let instruction = match self.conditionals.filter(instruction) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+
+            match &instruction.directive {
+                Some(Token::Directive { name }) if name == "include" => {
+                    let mut included = self.include(&instruction, dir);
+                    program.instructions.append(&mut included);
+                }
+                _ => program.instructions.push(instruction),
+            }
+        }
+
+        program
+    }
+
+    fn include(&mut self, include: &AssemblerInstruction, dir: &Path) -> Vec<AssemblerInstruction> {
+        let mut result = vec![];
+
+        if include.label.is_some() {
+            result.push(AssemblerInstruction {
+                label: include.label.clone(),
+                spans: include.spans.clone(),
+                leading_comments: include.leading_comments.clone(),
+                ..AssemblerInstruction::default()
+            });
+        }
+
+        let (path, span) = match include.operands_with_spans().collect::<Vec<_>>()[..] {
+            [(Token::StringOperand { value }, span)] => (dir.join(value), span),
+            [(token, span)] => {
+                self.error(
+                    span,
+                    format!("expected a path in quotes, found `{}`", token),
+                );
+                return result;
+            }
+            _ => {
+                let span = include.spans.statement;
+                self.error(span, "`.include` takes 1 operand, a path".to_string());
+                return result;
+            }
+        };
+
+        let canonical = match path.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(e) => {
+                self.error(span, format!("can't include `{}`: {}", path.display(), e));
+                return result;
+            }
+        };
+
+        if let Some(position) = self.stack.iter().position(|p| *p == canonical) {
+            let cycle: Vec<String> = self.stack[position..]
+                .iter()
+                .chain(Some(&canonical))
+                .map(|p| format!("`{}`", p.display()))
+                .collect();
+
+            self.error(span, format!("include cycle: {}", cycle.join(" includes ")));
+            return result;
+        }
+
+        if self.seen.contains(&canonical) {
+            return result;
+        }
+
+        let text = match fs::read_to_string(&canonical) {
+            Ok(text) => text,
+            Err(e) => {
+                self.error(span, format!("can't include `{}`: {}", path.display(), e));
+                return result;
+            }
+        };
+
+        let file = self.sources.add(path.display().to_string(), text);
+        let dir = canonical
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .to_path_buf();
+
+        self.seen.push(canonical.clone());
+        self.stack.push(canonical);
+
+        let mut program = self.parse(file, &dir);
+
+        self.stack.pop();
+        result.append(&mut program.instructions);
+        result
+    }
+
+    fn error(&mut self, span: Span, message: String) {
+        self.errors
+            .push(AssemblerError::new(ErrorKind::Include, span, message));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use std::env;
+
+    /// A fresh directory under the system temporary directory with `files`
+    /// written into it.
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("iridium-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        for (name, text) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = write_files(
+            "include",
+            &[
+                (
+                    "main.iasm",
+                    ".include \"lib/math.iasm\"\nload $0 @double\nhlt\n",
+                ),
+                (
+                    "lib/math.iasm",
+                    ".include \"util.iasm\"\ndouble: add $0 $0 $0\n",
+                ),
+                ("lib/util.iasm", ".include \"math.iasm\"\nnop: hlt\n"),
+            ],
+        );
+        let path = dir.join("main.iasm");
+        let source = fs::read_to_string(&path).unwrap();
+        let mut assembler = Assembler::new();
+        let errors = assembler.assemble_file(&path, &source).unwrap_err();
+
+        // util.iasm including math.iasm closes a cycle.
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ErrorKind::Include);
+        assert!(errors[0].message.starts_with("include cycle: "));
+        assert_eq!(errors[0].span.file, 2);
+        assert!(errors[0]
+            .render_in(&assembler.sources)
+            .contains("util.iasm:1:10"));
+
+        fs::write(dir.join("lib/util.iasm"), "nop: hlt\n").unwrap();
+        let binary = assembler.assemble_file(&path, &source).unwrap();
+        assert_eq!(
+            binary.code,
+            vec![0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x01, 0x00]
+        );
+        assert_eq!(assembler.symbols.symbol_value("double"), Some(1));
+        assert_eq!(assembler.sources.files().len(), 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let dir = write_files(
+            "include-errors",
+            &[
+                (
+                    "main.iasm",
+                    ".include \"missing.iasm\"\n.include 42\n.include \"lib.iasm\"\n",
+                ),
+                ("lib.iasm", "load $0 @nowhere\n.include \"lib.iasm\"\n"),
+            ],
+        );
+        let path = dir.join("main.iasm");
+        let source = fs::read_to_string(&path).unwrap();
+        let mut assembler = Assembler::new();
+        let errors = assembler.assemble_file(&path, &source).unwrap_err();
+        let kinds: Vec<ErrorKind> = errors.iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ErrorKind::Include,
+                ErrorKind::Include,
+                ErrorKind::Include,
+                ErrorKind::UndefinedLabel
+            ]
+        );
+        assert!(errors[0].message.starts_with("can't include `"));
+        assert_eq!(errors[1].message, "expected a path in quotes, found `#42`");
+        assert_eq!(errors[3].span.file, 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_conditional_include() {
+        let dir = write_files(
+            "conditional-include",
+            &[(
+                "main.iasm",
+                ".ifdef X\n.include \"nonexistent.iasm\"\n.endif\nhlt\n",
+            )],
+        );
+        let path = dir.join("main.iasm");
+        let source = fs::read_to_string(&path).unwrap();
+        let mut assembler = Assembler::new();
+        assert_eq!(
+            assembler.assemble_file(&path, &source).map(|b| b.code),
+            Ok(vec![0x00])
+        );
+
+        assembler.define("X", 1);
+        let errors = assembler.assemble_file(&path, &source).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.starts_with("can't include `"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
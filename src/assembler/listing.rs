@@ -0,0 +1,208 @@
+//! Listing of an assembled program: every statement with the section and the
+//! offset it was placed at, the bytes it turned into and its source line,
+//! followed by the symbol table.
+
+use crate::assembler::parser::instruction::AssemblerInstruction;
+use crate::assembler::parser::program::Program;
+use crate::assembler::sources::SourceMap;
+use crate::assembler::symbols::{Symbol, SymbolTable, SymbolType};
+use crate::assembler::Section;
+use std::fmt;
+
+/// Bytes shown on one line of the listing, the rest go on continuation lines.
+const BYTES_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingLine {
+    pub section: Section,
+    /// Offset of the statement from the start of `section`.
+    pub offset: u32,
+    pub bytes: Vec<u8>,
+    /// `file:line` of the statement.
+    pub location: String,
+    /// The whole source line the statement starts on.
+    pub source: String,
+    /// Whether the statement comes from a macro expansion, in which case
+    /// `location` and `source` point into the macro definition.
+    pub expanded: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+    pub lines: Vec<ListingLine>,
+    pub symbols: Vec<Symbol>,
+}
+
+impl Listing {
+    /// Lists `program`, whose labels are in `symbols` and whose source files
+    /// are in `sources`. Statements that fail to encode are listed without
+    /// bytes, and the instructions a pseudo-instruction expands to are listed
+    /// together on its line.
+    pub fn new(program: &Program, symbols: &SymbolTable, sources: &SourceMap) -> Listing {
+        let mut lines: Vec<ListingLine> = vec![];
+        let mut section = Section::Code;
+        let (mut code_offset, mut data_offset) = (0, 0);
+        let mut previous: Option<&AssemblerInstruction> = None;
+
+        for instruction in &program.instructions {
+            section = instruction.section().unwrap_or(section);
+
+            let offset = match section {
+                Section::Code => &mut code_offset,
+                Section::Data => &mut data_offset,
+            };
+            let start = *offset;
+            let bytes = instruction.to_bytes(symbols, start).unwrap_or_default();
+            let span = instruction.spans.statement;
+
+            *offset += instruction.encoded_len(symbols, start);
+
+            // Only the instructions of one pseudo-instruction share a statement.
+            if let (Some(previous), Some(line)) = (previous.replace(instruction), lines.last_mut())
+            {
+                if previous.spans.statement == span && previous.expansion == instruction.expansion {
+                    line.bytes.extend(bytes);
+                    continue;
+                }
+            }
+
+            let (location, source) = match sources.get(span.file) {
+                Some(file) => {
+                    let (line, _) = span.line_col(&file.text);
+                    let text = file.text.lines().nth(line - 1).unwrap_or("");
+
+                    (
+                        format!("{}:{}", file.name, line),
+                        text.trim_end().to_string(),
+                    )
+                }
+                None => (String::new(), String::new()),
+            };
+
+            lines.push(ListingLine {
+                section,
+                offset: start,
+                bytes,
+                location,
+                source,
+                expanded: instruction.expansion.is_some(),
+            });
+        }
+
+        Listing {
+            lines,
+            symbols: symbols.symbols().to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .lines
+            .iter()
+            .map(|line| line.location.len())
+            .max()
+            .unwrap_or(0);
+
+        for line in &self.lines {
+            let mut rows = line.bytes.chunks(BYTES_PER_LINE);
+            let marker = if line.expanded { "+" } else { " " };
+
+            writeln!(
+                f,
+                "{:<width$}  {} {:04X}  {:<hex$} {}{}",
+                line.location,
+                section_letter(line.section),
+                line.offset,
+                hex(rows.next().unwrap_or(&[])),
+                marker,
+                line.source,
+                width = width,
+                hex = BYTES_PER_LINE * 3 - 1
+            )?;
+
+            for (i, row) in rows.enumerate() {
+                writeln!(
+                    f,
+                    "{:<width$}  {} {:04X}  {}",
+                    "",
+                    section_letter(line.section),
+                    line.offset as usize + (i + 1) * BYTES_PER_LINE,
+                    hex(row),
+                    width = width
+                )?;
+            }
+        }
+
+        writeln!(f, "\nSymbols:")?;
+
+        for symbol in &self.symbols {
+            match symbol.symbol_type {
+                SymbolType::Label => write!(
+                    f,
+                    "  {} {:04X}  {}",
+                    section_letter(symbol.section),
+                    symbol.offset,
+                    symbol.name
+                )?,
+                SymbolType::Extern => write!(f, "  extern  {}", symbol.name)?,
+                SymbolType::Constant(value) => write!(f, "  const   {} = {}", symbol.name, value)?,
+            }
+
+            if symbol.global {
+                write!(f, " (global)")?;
+            }
+
+            if let Some(size) = symbol.size {
+                write!(f, " (function, {} bytes)", size)?;
+            }
+
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn section_letter(section: Section) -> char {
+    match section {
+        Section::Code => 'C',
+        Section::Data => 'D',
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+
+    bytes.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::Assembler;
+
+    #[test]
+    fn test_listing() {
+        let mut assembler = Assembler::new();
+        assembler.emit_listing = true;
+        assembler
+            .assemble(".macro two\nhlt\nhlt\n.endm\nstart: load $0 @msg ; go\ntwo\ninc $2\n.data\nmsg: .asciiz \"hello!!\"\n")
+            .unwrap();
+
+        let listing = assembler.listing.unwrap().to_string();
+        let expected = "\
+<input>:5  C 0000  01 00 00 00              start: load $0 @msg ; go
+<input>:2  C 0004  00                      +hlt
+<input>:3  C 0005  00                      +hlt
+<input>:7  C 0006  01 01 00 01 02 02 01 02  inc $2
+<input>:8  D 0000                           .data
+<input>:9  D 0000  68 65 6C 6C 6F 21 21 00  msg: .asciiz \"hello!!\"
+
+Symbols:
+  C 0000  start
+  D 0000  msg
+";
+        assert_eq!(listing, expected);
+    }
+}
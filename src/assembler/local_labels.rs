@@ -0,0 +1,219 @@
+//! Labels that don't need a name of their own:
+//!
+//! ```text
+//! main:
+//! 1:  dec $1
+//!     jmp @1b
+//! .done:
+//!     jmp @.done
+//! ```
+//!
+//! A numeric label such as `1:` can be declared any number of times, `@1b`
+//! refers to the closest one before it, or on the same line, and `@1f` to the
+//! closest one after it. A label starting with `.` belongs to the last other
+//! label declared before it, so `.done` above is `main.done`, and another
+//! `.done` can follow another label. Both are only known in the file that
+//! declares them. Labels with a `.` in them, as the ones renamed in macro
+//! expansions, don't start a new scope.
+
+use crate::assembler::assembler_errors::{AssemblerError, ErrorKind};
+use crate::assembler::expression::Expression;
+use crate::assembler::parser::instruction::AssemblerInstruction;
+use crate::assembler::parser::program::Program;
+use crate::assembler::sources::FileId;
+use crate::assembler::span::Span;
+use crate::assembler::Token;
+use std::collections::HashMap;
+
+/// Gives every numeric and scoped label in `program` a unique name, and
+/// points their uses at it.
+pub fn resolve_local_labels(program: &mut Program) -> Vec<AssemblerError> {
+    let mut errors = vec![];
+    // Where each numeric label is declared, and the name it gets there.
+    let mut numeric: HashMap<(FileId, String), Vec<(usize, String)>> = HashMap::new();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+
+    for (index, instruction) in program.instructions.iter().enumerate() {
+        if let Some(name) = instruction.label_name().filter(|name| is_numeric(name)) {
+            let count = counts.entry(name).or_insert(0);
+
+            numeric
+                .entry((file(instruction), name.to_string()))
+                .or_default()
+                .push((index, format!("{}.{}", name, count)));
+            *count += 1;
+        }
+    }
+
+    let mut scopes: HashMap<FileId, String> = HashMap::new();
+
+    for (index, instruction) in program.instructions.iter_mut().enumerate() {
+        let file = file(instruction);
+        let mut renamed = None;
+
+        if let Some(Token::LabelDeclaration { name }) = &instruction.label {
+            if is_numeric(name) {
+                renamed = numeric[&(file, name.clone())]
+                    .iter()
+                    .find(|(declared, _)| *declared == index)
+                    .map(|(_, unique)| unique.clone());
+            } else if name.starts_with('.') {
+                renamed = Some(format!("{}{}", scopes.get(&file).map_or("", |s| s), name));
+            } else if !name.contains('.') {
+                scopes.insert(file, name.clone());
+            }
+        }
+
+        if let Some(name) = renamed {
+            instruction.label = Some(Token::LabelDeclaration { name });
+        }
+
+        let scope = scopes.get(&file).map_or("", |scope| scope.as_str());
+        let resolve = |name: &str, span: Span| resolve(name, span, (file, index), scope, &numeric);
+
+        // A label that can't be resolved becomes 0, so it isn't reported
+        // again as undeclared.
+        for (operand, span) in instruction
+            .operands
+            .iter_mut()
+            .zip(instruction.spans.operands.iter())
+        {
+            match operand {
+                Token::LabelUsabe { name } => match resolve(name, *span) {
+                    Ok(resolved) => *name = resolved,
+                    Err(e) => {
+                        errors.push(e);
+                        *operand = Token::IntegerOperand { value: 0 };
+                    }
+                },
+                Token::Expression { expr } => expr.for_each_leaf(&mut |leaf| {
+                    if let Expression::Label(name) = leaf {
+                        match resolve(name, *span) {
+                            Ok(resolved) => *name = resolved,
+                            Err(e) => {
+                                errors.push(e);
+                                *leaf = Expression::Integer(0);
+                            }
+                        }
+                    }
+                }),
+                _ => {}
+            }
+        }
+    }
+
+    errors
+}
+
+/// Whether `name` is a numeric label, which can be declared more than once.
+pub fn is_numeric(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// The file an instruction counts as written in: the one invoking the macro
+/// it was expanded from, if any.
+fn file(instruction: &AssemblerInstruction) -> FileId {
+    match &instruction.expansion {
+        Some(expansion) => expansion.invocation.file,
+        None => instruction.spans.statement.file,
+    }
+}
+
+/// The unique name of the label `name` used at `span`, by the instruction at
+/// `index` of `file`.
+fn resolve(
+    name: &str,
+    span: Span,
+    (file, index): (FileId, usize),
+    scope: &str,
+    numeric: &HashMap<(FileId, String), Vec<(usize, String)>>,
+) -> Result<String, AssemblerError> {
+    if name.starts_with('.') {
+        return Ok(format!("{}{}", scope, name));
+    }
+
+    if is_numeric(name) {
+        return Err(AssemblerError::new(
+            ErrorKind::UndefinedLabel,
+            span,
+            format!(
+                "`{0}:` can be declared more than once, use `@{0}b` or `@{0}f` to pick one",
+                name
+            ),
+        ));
+    }
+
+    let (number, backward) = match name.split_at(name.len() - 1) {
+        (number, "b") if is_numeric(number) => (number, true),
+        (number, "f") if is_numeric(number) => (number, false),
+        _ => return Ok(name.to_string()),
+    };
+    let declarations = numeric
+        .get(&(file, number.to_string()))
+        .map_or(&[][..], |declarations| &declarations[..]);
+    let found = if backward {
+        declarations
+            .iter()
+            .rev()
+            .find(|(declared, _)| *declared <= index)
+    } else {
+        declarations.iter().find(|(declared, _)| *declared > index)
+    };
+
+    match found {
+        Some((_, unique)) => Ok(unique.clone()),
+        None => Err(AssemblerError::new(
+            ErrorKind::UndefinedLabel,
+            span,
+            format!(
+                "no `{}:` comes {} `@{}`",
+                number,
+                if backward { "before" } else { "after" },
+                name
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_errors::error_messages;
+    use crate::assembler::Assembler;
+
+    #[test]
+    fn test_numeric_labels() {
+        let bytes = Assembler::new()
+            .assemble("1: load $0 @1f\n1: load $0 @1b\nload $0 @1b\n2: load $0 @2b\n1: hlt\n")
+            .unwrap();
+        let targets: Vec<u8> = bytes.chunks(4).take(4).map(|chunk| chunk[3]).collect();
+        assert_eq!(targets, vec![4, 4, 4, 12]);
+
+        let errors = Assembler::new()
+            .assemble("load $0 @1b\n1: hlt\nload $0 @1f\nload $0 @1\n")
+            .unwrap_err();
+        let messages = error_messages(&errors);
+        assert_eq!(
+            messages,
+            vec![
+                "no `1:` comes before `@1b`",
+                "no `1:` comes after `@1f`",
+                "`1:` can be declared more than once, use `@1b` or `@1f` to pick one",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scoped_labels() {
+        let mut assembler = Assembler::new();
+        let bytes = assembler
+            .assemble(
+                ".macro spin\n.wait: load $0 @.wait\n.endm\n\
+                 first: load $0 @.end\n.end: load $0 @.end\nsecond: spin\nload $0 @.end\n.end: hlt\n",
+            )
+            .unwrap();
+        let targets: Vec<u8> = bytes.chunks(4).take(4).map(|chunk| chunk[3]).collect();
+        assert_eq!(targets, vec![4, 4, 8, 16]);
+        assert!(assembler.symbols.has_symbol("first.end"));
+        assert!(assembler.symbols.has_symbol("second.end"));
+    }
+}
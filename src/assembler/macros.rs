@@ -0,0 +1,572 @@
+//! `.macro` / `.endm` definitions, expanded in place of their invocations
+//! before labels are collected:
+//!
+//! ```text
+//! .macro swap a, b
+//!     add \a $0 $31
+//!     add \b $0 \a
+//!     add $31 $0 \b
+//! .endm
+//!
+//!     swap $1, $2
+//! ```
+//!
+//! Labels declared in a macro body are renamed on every expansion, so a macro
+//! with a loop can be used more than once. Numeric labels are left alone, as
+//! they can be declared again anyway.
+
+use crate::assembler::assembler_errors::{AssemblerError, ErrorKind};
+use crate::assembler::expression::Expression;
+use crate::assembler::local_labels::is_numeric;
+use crate::assembler::parser::instruction::AssemblerInstruction;
+use crate::assembler::parser::program::Program;
+use crate::assembler::span::Span;
+use crate::assembler::Token;
+use crate::instruction::Opcode;
+use nom::types::CompleteStr;
+use std::collections::HashMap;
+use std::mem;
+
+/// How deep macros can invoke other macros, which stops a macro that invokes
+/// itself.
+const MAX_DEPTH: usize = 32;
+
+/// Where an instruction produced by a macro expansion came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expansion {
+    pub name: String,
+    /// Span of the invocation in the source.
+    pub invocation: Span,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<AssemblerInstruction>,
+    /// Span of the `.macro` line.
+    span: Span,
+}
+
+/// Replaces the `.macro` definitions in `program` with nothing and every
+/// invocation with the body of the macro.
+pub fn expand_macros(program: &mut Program) -> Vec<AssemblerError> {
+    let mut errors = vec![];
+    let instructions = mem::take(&mut program.instructions);
+    let (macros, instructions) = collect_definitions(instructions, &mut errors);
+    let mut expander = Expander {
+        macros,
+        expansions: 0,
+        errors,
+    };
+
+    program.instructions = expander.expand(instructions, 0);
+    expander.errors
+}
+
+fn collect_definitions(
+    instructions: Vec<AssemblerInstruction>,
+    errors: &mut Vec<AssemblerError>,
+) -> (HashMap<String, Macro>, Vec<AssemblerInstruction>) {
+    let mut macros: HashMap<String, Macro> = HashMap::new();
+    let mut rest = vec![];
+    let mut current: Option<(String, Macro)> = None;
+    // Span of a `.macro` line that was rejected, whose body is skipped up to
+    // its `.endm` rather than assembled.
+    let mut rejected: Option<Span> = None;
+
+    for instruction in instructions {
+        if rejected.is_some() {
+            if directive_name(&instruction) == Some("endm") {
+                rejected = None;
+            }
+            continue;
+        }
+
+        match directive_name(&instruction) {
+            Some("macro") => {
+                if let Some((name, _)) = &current {
+                    errors.push(AssemblerError::new(
+                        ErrorKind::Macro,
+                        instruction.spans.statement,
+                        format!("macros can't be defined inside of macro `{}`", name),
+                    ));
+                    continue;
+                }
+
+                match parse_definition(&instruction) {
+                    Ok((name, params)) => {
+                        let definition = Macro {
+                            params,
+                            body: vec![],
+                            span: instruction.spans.statement,
+                        };
+
+                        current = Some((name, definition));
+                    }
+                    Err(error) => {
+                        if instruction.reports(&error) {
+                            errors.push(error);
+                        }
+                        rejected = Some(instruction.spans.statement);
+                    }
+                }
+            }
+            Some("endm") => match current.take() {
+                Some((name, definition)) => {
+                    if let Some(existing) = macros.get(&name) {
+                        errors.push(
+                            AssemblerError::new(
+                                ErrorKind::Macro,
+                                definition.span,
+                                format!("macro `{}` is defined more than once", name),
+                            )
+                            .with_note(existing.span, "first defined here".to_string()),
+                        );
+                    } else {
+                        macros.insert(name, definition);
+                    }
+                }
+                None => errors.push(AssemblerError::new(
+                    ErrorKind::Macro,
+                    instruction.spans.statement,
+                    "`.endm` without a `.macro` to close".to_string(),
+                )),
+            },
+            _ => match &mut current {
+                Some((name, definition)) => {
+                    check_parameters(name, &definition.params, &instruction, errors);
+                    definition.body.push(instruction);
+                }
+                None => rest.push(instruction),
+            },
+        }
+    }
+
+    if let Some((name, definition)) = current {
+        errors.push(AssemblerError::new(
+            ErrorKind::Macro,
+            definition.span,
+            format!("macro `{}` is never closed with `.endm`", name),
+        ));
+    }
+
+    if let Some(span) = rejected {
+        errors.push(AssemblerError::new(
+            ErrorKind::Macro,
+            span,
+            "`.macro` is never closed with `.endm`".to_string(),
+        ));
+    }
+
+    (macros, rest)
+}
+
+/// Name and parameters of a `.macro name param, param` line.
+fn parse_definition(
+    instruction: &AssemblerInstruction,
+) -> Result<(String, Vec<String>), AssemblerError> {
+    let mut operands = instruction.operands_with_spans();
+
+    let name = match operands.next() {
+        Some((Token::Identifier { name }, span)) => {
+            if !Opcode::from(CompleteStr(name)).is_illegal() {
+                return Err(AssemblerError::new(
+                    ErrorKind::Macro,
+                    span,
+                    format!("`{}` is an instruction and can't be a macro name", name),
+                ));
+            }
+
+            name.clone()
+        }
+        Some((token, span)) => {
+            return Err(AssemblerError::new(
+                ErrorKind::InvalidOperand,
+                span,
+                format!("expected a macro name, found `{}`", token),
+            ));
+        }
+        None => {
+            return Err(AssemblerError::new(
+                ErrorKind::OperandCount,
+                instruction.spans.statement,
+                "`.macro` needs a name".to_string(),
+            ));
+        }
+    };
+
+    let mut params = vec![];
+
+    for (token, span) in operands {
+        match token {
+            Token::Identifier { name } if !params.contains(name) => params.push(name.clone()),
+            Token::Identifier { name } => {
+                return Err(AssemblerError::new(
+                    ErrorKind::InvalidOperand,
+                    span,
+                    format!("parameter `{}` is declared more than once", name),
+                ));
+            }
+            _ => {
+                return Err(AssemblerError::new(
+                    ErrorKind::InvalidOperand,
+                    span,
+                    format!("expected a parameter name, found `{}`", token),
+                ));
+            }
+        }
+    }
+
+    Ok((name, params))
+}
+
+fn check_parameters(
+    name: &str,
+    params: &[String],
+    instruction: &AssemblerInstruction,
+    errors: &mut Vec<AssemblerError>,
+) {
+    for (token, span) in instruction.operands_with_spans() {
+        let mut used = vec![];
+
+        match token {
+            Token::Parameter { name: param } => used.push(param.clone()),
+            Token::Expression { expr } => expr.clone().for_each_leaf(&mut |leaf| {
+                if let Expression::Parameter(param) = leaf {
+                    used.push(param.clone());
+                }
+            }),
+            _ => {}
+        }
+
+        for param in used {
+            if !params.contains(&param) {
+                errors.push(AssemblerError::new(
+                    ErrorKind::InvalidOperand,
+                    span,
+                    format!("`\\{}` is not a parameter of macro `{}`", param, name),
+                ));
+            }
+        }
+    }
+}
+
+fn directive_name(instruction: &AssemblerInstruction) -> Option<&str> {
+    match (&instruction.opcode, &instruction.directive) {
+        (None, Some(Token::Directive { name })) => Some(name),
+        _ => None,
+    }
+}
+
+struct Expander {
+    macros: HashMap<String, Macro>,
+    /// Number of expansions so far, used to make labels in macro bodies unique.
+    expansions: usize,
+    errors: Vec<AssemblerError>,
+}
+
+impl Expander {
+    fn expand(
+        &mut self,
+        instructions: Vec<AssemblerInstruction>,
+        depth: usize,
+    ) -> Vec<AssemblerInstruction> {
+        let mut result = vec![];
+
+        for instruction in instructions {
+            let name = match &instruction.opcode {
+                Some(Token::Mnemonic { name }) if self.macros.contains_key(name) => name.clone(),
+                _ => {
+                    result.push(instruction);
+                    continue;
+                }
+            };
+
+            if depth >= MAX_DEPTH {
+                self.errors.push(AssemblerError::new(
+                    ErrorKind::Macro,
+                    instruction.spans.statement,
+                    format!(
+                        "macro `{}` is nested more than {} levels deep, does it invoke itself?",
+                        name, MAX_DEPTH
+                    ),
+                ));
+                continue;
+            }
+
+            let body = match self.substitute(&name, &instruction) {
+                Ok(body) => body,
+                Err(error) => {
+                    if instruction.reports(&error) {
+                        self.errors.push(error);
+                    }
+                    continue;
+                }
+            };
+
+            if instruction.label.is_some() {
+                result.push(AssemblerInstruction {
+                    label: instruction.label.clone(),
+                    spans: instruction.spans.clone(),
+                    leading_comments: instruction.leading_comments.clone(),
+                    ..AssemblerInstruction::default()
+                });
+            }
+
+            for mut expanded in self.expand(body, depth + 1) {
+                if expanded.expansion.is_none() {
+                    expanded.expansion = Some(Expansion {
+                        name: name.clone(),
+                        invocation: instruction.spans.statement,
+                    });
+                }
+
+                result.push(expanded);
+            }
+        }
+
+        result
+    }
+
+    /// The body of macro `name` with the arguments of `invocation` in place
+    /// of its parameters and its labels renamed for this expansion.
+    fn substitute(
+        &mut self,
+        name: &str,
+        invocation: &AssemblerInstruction,
+    ) -> Result<Vec<AssemblerInstruction>, AssemblerError> {
+        let definition = &self.macros[name];
+        let args: Vec<(&Token, Span)> = invocation.operands_with_spans().collect();
+
+        if args.len() != definition.params.len() {
+            let span = match args.get(definition.params.len()) {
+                Some((_, span)) => *span,
+                None => invocation.spans.statement,
+            };
+
+            return Err(AssemblerError::new(
+                ErrorKind::OperandCount,
+                span,
+                format!(
+                    "macro `{}` takes {} argument{}, found {}",
+                    name,
+                    definition.params.len(),
+                    if definition.params.len() == 1 {
+                        ""
+                    } else {
+                        "s"
+                    },
+                    args.len()
+                ),
+            )
+            .with_note(definition.span, format!("`{}` is defined here", name)));
+        }
+
+        self.expansions += 1;
+
+        let locals: Vec<&str> = definition
+            .body
+            .iter()
+            .filter_map(|instruction| instruction.label_name())
+            .filter(|label| !is_numeric(label))
+            .collect();
+        let rename = |label: &str| format!("{}.{}.{}", name, self.expansions, label);
+        let mut body = vec![];
+
+        for instruction in &definition.body {
+            let mut instruction = instruction.clone();
+
+            if let Some(Token::LabelDeclaration { name }) = &instruction.label {
+                instruction.label = Some(Token::LabelDeclaration { name: rename(name) });
+            }
+
+            for (operand, span) in instruction
+                .operands
+                .iter_mut()
+                .zip(instruction.spans.operands.iter_mut())
+            {
+                match operand {
+                    Token::Parameter { name } => {
+                        let index = definition.params.iter().position(|p| p == name);
+
+                        if let Some((arg, arg_span)) = index.map(|i| args[i]) {
+                            *operand = arg.clone();
+                            *span = arg_span;
+                        }
+                    }
+                    Token::LabelUsabe { name } if locals.contains(&name.as_str()) => {
+                        *operand = Token::LabelUsabe { name: rename(name) };
+                    }
+                    Token::Expression { expr } => {
+                        let mut invalid = None;
+
+                        expr.for_each_leaf(&mut |leaf| match leaf {
+                            Expression::Parameter(param) => {
+                                let index = definition.params.iter().position(|p| p == param);
+
+                                if let Some((arg, arg_span)) = index.map(|i| args[i]) {
+                                    match Expression::from_token(arg) {
+                                        Some(value) => *leaf = value,
+                                        None => invalid = Some((arg, arg_span)),
+                                    }
+                                }
+                            }
+                            Expression::Label(label) | Expression::Name(label)
+                                if locals.contains(&label.as_str()) =>
+                            {
+                                *label = rename(label);
+                            }
+                            _ => {}
+                        });
+
+                        if let Some((arg, arg_span)) = invalid {
+                            return Err(AssemblerError::new(
+                                ErrorKind::InvalidOperand,
+                                arg_span,
+                                format!("`{}` can't be used in an expression", arg),
+                            )
+                            .with_note(*span, "the macro uses it here".to_string()));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            body.push(instruction);
+        }
+
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assembler_errors::error_messages;
+    use crate::assembler::parser::program::parse_source;
+    use crate::assembler::Assembler;
+
+    #[test]
+    fn test_expand_macros() {
+        let source = ".macro swap a, b\nadd \\a $0 $31\nadd \\b $0 \\a\nadd $31 $0 \\b\n.endm\nstart: swap $1 $2\nhlt\n";
+        let (mut program, errors) = parse_source(source);
+        assert!(errors.is_empty());
+        assert!(expand_macros(&mut program).is_empty());
+
+        let instructions = &program.instructions;
+        assert_eq!(instructions.len(), 5);
+        assert_eq!(instructions[0].label_name(), Some("start"));
+        assert_eq!(
+            instructions[2].operands,
+            vec![
+                Token::Register { reg_num: 2 },
+                Token::Register { reg_num: 0 },
+                Token::Register { reg_num: 1 }
+            ]
+        );
+        assert_eq!(
+            &source[instructions[2].spans.operands[0].start..instructions[2].spans.operands[0].end],
+            "$2"
+        );
+        let start = source.find("start:").unwrap();
+        assert_eq!(
+            instructions[1].expansion,
+            Some(Expansion {
+                name: "swap".to_string(),
+                invocation: Span::new(start, start + "start: swap $1 $2".len()),
+            })
+        );
+        assert_eq!(instructions[4].expansion, None);
+    }
+
+    #[test]
+    fn test_macro_commas() {
+        let source = ".macro put r, v\nload \\r \\v\n.endm\nput $1, #5\nput $2,#6\nput $3 #7\n";
+        assert_eq!(
+            Assembler::new().assemble(source),
+            Ok(vec![
+                0x01, 0x01, 0x00, 0x05, 0x01, 0x02, 0x00, 0x06, 0x01, 0x03, 0x00, 0x07
+            ])
+        );
+    }
+
+    #[test]
+    fn test_macro_names() {
+        let source = ".macro swap2 a, b\nadd \\a $0 \\b\n.endm\n\
+                      .macro swap_regs a\nload \\a #1\n.endm\n\
+                      swap2 $1, $2\nswap_regs $3\n";
+        assert_eq!(
+            Assembler::new().assemble(source),
+            Ok(vec![0x02, 0x01, 0x00, 0x02, 0x01, 0x03, 0x00, 0x01])
+        );
+    }
+
+    #[test]
+    fn test_macro_local_labels() {
+        let source = ".macro wait r\nagain: jmpb \\r\nload \\r @again\n.endm\nwait $1\nwait $2\n";
+        let bytes = Assembler::new().assemble(source).unwrap();
+        assert_eq!(
+            bytes,
+            vec![0x08, 0x01, 0x01, 0x01, 0x00, 0x00, 0x08, 0x02, 0x01, 0x02, 0x00, 0x06]
+        );
+    }
+
+    #[test]
+    fn test_macro_errors() {
+        let errors = |source: &str| {
+            let (mut program, _) = parse_source(source);
+            expand_macros(&mut program)
+        };
+
+        let found = errors(".macro one a\nload \\a #1\n.endm\none $1 $2\n");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, ErrorKind::OperandCount);
+        assert_eq!(found[0].notes[0].span, Span::new(0, 12));
+
+        let found = errors(".macro one a\nload \\b #1\n.endm\n.endm\n.macro add\n.endm\n");
+        let messages = error_messages(&found);
+        assert_eq!(
+            messages,
+            vec![
+                "`\\b` is not a parameter of macro `one`",
+                "`.endm` without a `.macro` to close",
+                "`add` is an instruction and can't be a macro name",
+            ]
+        );
+
+        let found = errors(".macro add a\nload \\a #1\n.endm\nhlt\n.macro $1\n");
+        assert_eq!(
+            error_messages(&found),
+            vec![
+                "`add` is an instruction and can't be a macro name",
+                "expected a macro name, found `$1`",
+                "`.macro` is never closed with `.endm`",
+            ]
+        );
+
+        let found = errors(".macro loop\nloop\n.endm\nloop\n");
+        assert_eq!(found[0].kind, ErrorKind::Macro);
+
+        let found = errors(".macro open\nhlt\n");
+        assert_eq!(
+            found[0].message,
+            "macro `open` is never closed with `.endm`"
+        );
+    }
+
+    #[test]
+    fn test_errors_in_expansion() {
+        let source = ".macro set r v\nload \\r \\v\n.endm\nset $1 #-1\n";
+        let errors = Assembler::new().assemble(source).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ErrorKind::OutOfRange);
+        let invocation = source.find("set $1").unwrap();
+        assert_eq!(errors[0].span, Span::new(invocation + 7, invocation + 10));
+        assert_eq!(
+            errors[0].notes[0].span,
+            Span::new(invocation, invocation + 10)
+        );
+        assert!(errors[0]
+            .render(source)
+            .contains("note: in this expansion of `set`"));
+    }
+}
@@ -1,13 +1,646 @@
+use crate::assembler::alias::resolve_aliases;
+use crate::assembler::assembler_errors::{AssemblerError, ErrorKind};
+use crate::assembler::expression::{Expression, Scope};
+use crate::assembler::function::{check_clobbers, expand_functions};
+use crate::assembler::include::parse_with_includes;
+use crate::assembler::listing::Listing;
+use crate::assembler::local_labels::resolve_local_labels;
+use crate::assembler::macros::expand_macros;
+use crate::assembler::parser::instruction::AssemblerInstruction;
+use crate::assembler::parser::program::Program;
+use crate::assembler::pseudo::expand_pseudo_instructions;
+use crate::assembler::sources::{FileId, SourceMap};
+use crate::assembler::symbols::{Symbol, SymbolTable, SymbolType};
+use crate::binary::Binary;
+use crate::debug_info::DebugInfo;
 use crate::instruction::Opcode;
+use crate::object::ObjectFile;
+use std::fmt;
+use std::path::Path;
 
+pub mod alias;
+pub mod assembler_errors;
+pub mod conditional;
+pub mod expression;
+pub mod function;
+pub mod include;
+pub mod listing;
+pub mod local_labels;
+pub mod macros;
 pub mod parser;
+pub mod pseudo;
+pub mod sources;
+pub mod span;
+pub mod symbols;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Op { code: Opcode },
-    Register { reg_num: u8 },
-    IntegerOperand { value: i32 },
-    LabelDeclaration { name: String },
-    LabelUsabe { name: String },
-    Directive { name: String },
+    Op {
+        code: Opcode,
+    },
+    Mnemonic {
+        name: String,
+    },
+    Register {
+        reg_num: u8,
+    },
+    /// A register by a name declared with `.alias`, not looked up yet.
+    RegisterAlias {
+        name: String,
+    },
+    IntegerOperand {
+        value: i64,
+    },
+    StringOperand {
+        value: String,
+    },
+    LabelDeclaration {
+        name: String,
+    },
+    LabelUsabe {
+        name: String,
+    },
+    Directive {
+        name: String,
+    },
+    Identifier {
+        name: String,
+    },
+    Parameter {
+        name: String,
+    },
+    Expression {
+        expr: Expression,
+    },
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Token::*;
+
+        match self {
+            Op { code } => write!(f, "{}", code),
+            Mnemonic { name } => write!(f, "{}", name),
+            Register { reg_num } => write!(f, "${}", reg_num),
+            RegisterAlias { name } => write!(f, "${}", name),
+            IntegerOperand { value } => write!(f, "#{}", value),
+            StringOperand { value } => write!(f, "{:?}", value),
+            LabelDeclaration { name } => write!(f, "{}:", name),
+            LabelUsabe { name } => write!(f, "@{}", name),
+            Directive { name } => write!(f, ".{}", name),
+            // A bare name, such as the name and the parameters of a `.macro`.
+            Identifier { name } => write!(f, "{}", name),
+            // A reference to a macro parameter inside the macro body.
+            Parameter { name } => write!(f, "\\{}", name),
+            Expression { expr } => write!(f, "#{}", expr),
+        }
+    }
+}
+
+/// Part of the output a statement's bytes go to, selected with the `.code` and
+/// `.data` directives.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Section {
+    #[default]
+    Code,
+    /// Read-only data, loaded by the VM apart from the code.
+    Data,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub enum AssemblerPhase {
+    #[default]
+    First,
+    Second,
+}
+
+/// Two-pass assembler: the first pass records the offset of every label
+/// declaration, the second one emits bytecode with label usages resolved.
+#[derive(Debug, Default)]
+pub struct Assembler {
+    pub phase: AssemblerPhase,
+    pub symbols: SymbolTable,
+    /// Files read by the last assembly, to render its errors with.
+    pub sources: SourceMap,
+    /// Whether to produce a `listing` of every assembly.
+    pub emit_listing: bool,
+    /// Listing of the last assembly, when `emit_listing` is set.
+    pub listing: Option<Listing>,
+    /// Constants defined outside of the source, with `define`.
+    pub defines: Vec<(String, i64)>,
+    /// Warnings of the last assembly, which don't keep it from succeeding.
+    pub warnings: Vec<AssemblerError>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Assembler {
+            phase: AssemblerPhase::First,
+            symbols: SymbolTable::new(),
+            sources: SourceMap::new(),
+            emit_listing: false,
+            listing: None,
+            defines: vec![],
+            warnings: vec![],
+        }
+    }
+
+    /// Defines the constant `name` for every assembly from now on, as if the
+    /// source started with `.equ name value`. Conditional directives can test
+    /// for it, as in `.ifdef DEBUG`.
+    pub fn define(&mut self, name: &str, value: i64) {
+        self.defines.retain(|(other, _)| other != name);
+        self.defines.push((name.to_string(), value));
+    }
+
+    /// Assembles `raw` into the bytecode of its code section. Errors from every
+    /// phase are collected and returned together.
+    pub fn assemble(&mut self, raw: &str) -> Result<Vec<u8>, Vec<AssemblerError>> {
+        self.assemble_binary(raw).map(|binary| binary.code)
+    }
+
+    /// Assembles `raw` into a `Binary` holding both sections, ready to be
+    /// loaded into the VM or written to disk with `Binary::to_bytes`.
+    /// `.include` paths are relative to the current directory.
+    pub fn assemble_binary(&mut self, raw: &str) -> Result<Binary, Vec<AssemblerError>> {
+        self.sources = SourceMap::new();
+
+        let file = self.sources.add("<input>".to_string(), raw.to_string());
+
+        self.assemble_sources(file, None)
+    }
+
+    /// Assembles `source`, the contents of the file at `path`, into a `Binary`.
+    /// `.include` paths are relative to the directory of `path`.
+    pub fn assemble_file(
+        &mut self,
+        path: &Path,
+        source: &str,
+    ) -> Result<Binary, Vec<AssemblerError>> {
+        self.sources = SourceMap::new();
+
+        let file = self
+            .sources
+            .add(path.display().to_string(), source.to_string());
+
+        self.assemble_sources(file, Some(path))
+    }
+
+    /// Assembles `source`, the contents of the file at `path`, into an object
+    /// file for the linker. Labels declared `.extern` may be used without being
+    /// declared here, and those declared `.global` are exported.
+    pub fn assemble_object(
+        &mut self,
+        path: &Path,
+        source: &str,
+    ) -> Result<ObjectFile, Vec<AssemblerError>> {
+        self.sources = SourceMap::new();
+
+        let file = self
+            .sources
+            .add(path.display().to_string(), source.to_string());
+        let (program, errors) = self.analyze(file, Some(path));
+
+        Assembler::finish(errors, program.to_object(&self.symbols))
+    }
+
+    fn assemble_sources(
+        &mut self,
+        file: FileId,
+        path: Option<&Path>,
+    ) -> Result<Binary, Vec<AssemblerError>> {
+        let (program, mut errors) = self.analyze(file, path);
+
+        for instruction in &program.instructions {
+            if let (None, Some(Token::Directive { name })) =
+                (&instruction.opcode, &instruction.directive)
+            {
+                if name == "extern" {
+                    errors.push(AssemblerError::new(
+                        ErrorKind::UndefinedLabel,
+                        instruction.spans.head,
+                        "`.extern` labels are only resolved by the linker, \
+                         assemble an object file instead"
+                            .to_string(),
+                    ));
+                }
+            }
+        }
+
+        let result = self.process_second_phase(&program).map(|binary| Binary {
+            debug: Some(DebugInfo::new(&program, &self.symbols, &self.sources)),
+            ..binary
+        });
+
+        Assembler::finish(errors, result)
+    }
+
+    /// Everything up to the second phase: parsing, includes, conditionals,
+    /// macros, functions, local labels and labels, and the listing, which only
+    /// needs the labels.
+    fn analyze(&mut self, file: FileId, path: Option<&Path>) -> (Program, Vec<AssemblerError>) {
+        let (mut program, mut errors) =
+            parse_with_includes(&mut self.sources, file, path, &self.defines);
+
+        errors.append(&mut expand_macros(&mut program));
+        errors.append(&mut expand_functions(&mut program));
+        errors.append(&mut resolve_local_labels(&mut program));
+        errors.append(&mut resolve_aliases(&mut program));
+        errors.append(&mut expand_pseudo_instructions(&mut program));
+        self.warnings = check_clobbers(&program);
+        errors.append(&mut self.process_first_phase(&program));
+
+        self.listing = if self.emit_listing {
+            Some(Listing::new(&program, &self.symbols, &self.sources))
+        } else {
+            None
+        };
+
+        (program, errors)
+    }
+
+    fn finish<T>(
+        mut errors: Vec<AssemblerError>,
+        result: Result<T, Vec<AssemblerError>>,
+    ) -> Result<T, Vec<AssemblerError>> {
+        match result {
+            Ok(output) if errors.is_empty() => Ok(output),
+            Ok(_) => Err(errors),
+            Err(mut second_phase_errors) => {
+                errors.append(&mut second_phase_errors);
+                Err(errors)
+            }
+        }
+    }
+
+    fn process_first_phase(&mut self, program: &Program) -> Vec<AssemblerError> {
+        self.phase = AssemblerPhase::First;
+        self.symbols = SymbolTable::new();
+
+        for (name, value) in &self.defines {
+            let symbol = Symbol::new(name.clone(), SymbolType::Constant(*value), Section::Code, 0);
+            self.symbols.add_symbol(symbol);
+        }
+
+        let errors = self.extract_labels(program);
+
+        self.phase = AssemblerPhase::Second;
+
+        errors
+    }
+
+    fn process_second_phase(&mut self, program: &Program) -> Result<Binary, Vec<AssemblerError>> {
+        program.to_binary(&self.symbols)
+    }
+
+    fn extract_labels(&mut self, program: &Program) -> Vec<AssemblerError> {
+        let mut errors = vec![];
+        let mut exports = vec![];
+        let mut function: Option<(&str, u32)> = None;
+        let mut section = Section::Code;
+        let (mut code_offset, mut data_offset) = (0, 0);
+
+        for instruction in &program.instructions {
+            section = instruction.section().unwrap_or(section);
+
+            let offset = match section {
+                Section::Code => &mut code_offset,
+                Section::Data => &mut data_offset,
+            };
+
+            match (&instruction.opcode, &instruction.directive) {
+                (None, Some(Token::Directive { name })) if name == "global" => {
+                    exports.push(instruction);
+                }
+                (None, Some(Token::Directive { name })) if name == "extern" => {
+                    for (operand, span) in instruction.operands_with_spans() {
+                        if let Token::Identifier { name } = operand {
+                            if self.symbols.has_symbol(name) {
+                                errors.push(instruction.in_context(AssemblerError::new(
+                                    ErrorKind::DuplicateLabel,
+                                    span,
+                                    format!("label `{}` is declared more than once", name),
+                                )));
+                            } else {
+                                let symbol =
+                                    Symbol::new(name.clone(), SymbolType::Extern, Section::Code, 0);
+                                self.symbols.add_symbol(symbol);
+                            }
+                        }
+                    }
+                }
+                (None, Some(Token::Directive { name })) if name == "func" => {
+                    function = instruction.label_name().map(|name| (name, *offset));
+                }
+                (None, Some(Token::Directive { name })) if name == "endfunc" => {
+                    if let Some((name, start)) = function.take() {
+                        self.symbols.set_size(name, *offset - start);
+                    }
+                }
+                (None, Some(Token::Directive { name })) if name == "equ" => {
+                    if let Err(e) = self.add_constant(instruction) {
+                        errors.push(instruction.in_context(e));
+                    }
+                }
+                (None, Some(Token::Directive { name })) if name == "space" || name == "align" => {
+                    // The first pass takes labels not declared yet to be at zero,
+                    // which a size can't be.
+                    for (operand, span) in instruction.operands_with_spans() {
+                        if let Some(name) = self.first_undeclared(operand) {
+                            errors.push(instruction.in_context(AssemblerError::new(
+                                ErrorKind::UndefinedLabel,
+                                span,
+                                format!("`{}` must be declared before it is used as a size", name),
+                            )));
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            if let Some(name) = instruction.label_name() {
+                if self.symbols.has_symbol(name) {
+                    errors.push(instruction.in_context(AssemblerError::new(
+                        ErrorKind::DuplicateLabel,
+                        instruction.spans.label,
+                        format!("label `{}` is declared more than once", name),
+                    )));
+                } else {
+                    let symbol = Symbol::new(name.to_string(), SymbolType::Label, section, *offset);
+                    self.symbols.add_symbol(symbol);
+                }
+            }
+
+            *offset += instruction.encoded_len(&self.symbols, *offset);
+        }
+
+        // `.global` may come before the label it exports.
+        for instruction in exports {
+            for (operand, span) in instruction.operands_with_spans() {
+                if let Token::Identifier { name } = operand {
+                    if !self.symbols.export(name) {
+                        errors.push(instruction.in_context(AssemblerError::new(
+                            ErrorKind::UndefinedLabel,
+                            span,
+                            format!("label `{}` is exported but never declared", name),
+                        )));
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+
+    /// Adds the constant declared by `.equ NAME value`, whose value may only
+    /// use what is declared before it. Malformed operands are left to
+    /// `directive_bytes` to report.
+    fn add_constant(&mut self, instruction: &AssemblerInstruction) -> Result<(), AssemblerError> {
+        let (name, (value, span)) = match instruction.operands_with_spans().collect::<Vec<_>>()[..]
+        {
+            [(Token::Identifier { name }, _), value] => (name, value),
+            _ => return Ok(()),
+        };
+
+        if let Some(undeclared) = self.first_undeclared(value) {
+            return Err(AssemblerError::new(
+                ErrorKind::UndefinedLabel,
+                span,
+                format!(
+                    "`{}` must be declared before `{}`, which uses it",
+                    undeclared, name
+                ),
+            ));
+        }
+
+        let value = match Expression::from_token(value) {
+            Some(expr) => expr
+                .evaluate(Scope::Complete(&self.symbols))
+                .map_err(|e| e.at(span, value))?,
+            None => return Ok(()),
+        };
+
+        if self.symbols.has_symbol(name) {
+            return Err(AssemblerError::new(
+                ErrorKind::DuplicateLabel,
+                instruction.spans.operands[0],
+                format!("`{}` is declared more than once", name),
+            ));
+        }
+
+        let symbol = Symbol::new(name.clone(), SymbolType::Constant(value), Section::Code, 0);
+        self.symbols.add_symbol(symbol);
+
+        Ok(())
+    }
+
+    /// The first label or constant `operand` uses that isn't declared yet.
+    fn first_undeclared(&self, operand: &Token) -> Option<String> {
+        let expr = Expression::from_token(operand)?;
+
+        expr.names()
+            .into_iter()
+            .find(|name| !self.symbols.has_symbol(name))
+            .map(str::to_string)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assembler_errors::error_messages;
+    use crate::assembler::span::Span;
+    use crate::object::Binding;
+
+    #[test]
+    fn test_assemble_program() {
+        let mut assembler = Assembler::new();
+        let program = "load $0 #100\nload $1 #1\nload $2 #0\ntest: sub $0 $0 $1\nneq $0 $2\nload $3 @test\njeq $3\nhlt";
+        let result = assembler.assemble(program);
+        assert!(result.is_ok());
+        assert_eq!(assembler.phase, AssemblerPhase::Second);
+        assert_eq!(assembler.symbols.symbol_value("test"), Some(12));
+    }
+
+    #[test]
+    fn test_resolve_forward_label() {
+        let mut assembler = Assembler::new();
+        let bytes = assembler
+            .assemble("load $0 @end\njmp $0\nhlt\nend: hlt\n")
+            .unwrap();
+        assert_eq!(bytes, vec![0x01, 0x00, 0x00, 0x07, 0x06, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_assemble_data() {
+        let mut assembler = Assembler::new();
+        let bytes = assembler
+            .assemble(
+                "load $0 @msg\nhlt\nmsg: .asciiz \"hi!\"\n.align 4\ntable: .half @msg @table\n",
+            )
+            .unwrap();
+        assert_eq!(assembler.symbols.symbol_value("msg"), Some(5));
+        assert_eq!(assembler.symbols.symbol_value("table"), Some(12));
+        assert_eq!(
+            bytes,
+            vec![
+                0x01, 0x00, 0x00, 0x05, 0x00, b'h', b'i', b'!', 0x00, 0x00, 0x00, 0x00, 0x00, 0x05,
+                0x00, 0x0C
+            ]
+        );
+    }
+
+    #[test]
+    fn test_constants_and_expressions() {
+        let mut assembler = Assembler::new();
+        let bytes = assembler
+            .assemble(
+                ".equ BUF_SIZE 8
+.equ TWICE BUF_SIZE*2
+start: load $0 #TWICE+1
+load $1 #@table+4
+load $2 #(end-start)
+loads $3 #-BUF_SIZE
+end: hlt
+table: .space BUF_SIZE
+",
+            )
+            .unwrap();
+        assert_eq!(assembler.symbols.value("TWICE"), Some(16));
+        assert_eq!(
+            &bytes[..16],
+            &[
+                0x01, 0x00, 0x00, 0x11, 0x01, 0x01, 0x00, 0x15, 0x01, 0x02, 0x00, 0x10, 0x12, 0x03,
+                0xFF, 0xF8
+            ]
+        );
+        assert_eq!(bytes.len(), 25);
+
+        let errors = assembler
+            .assemble(
+                ".equ A B+1
+.equ B 2
+.equ B 3
+load $0 #B*0x8000
+.space C
+.equ C 1
+",
+            )
+            .unwrap_err();
+        let messages = error_messages(&errors);
+        assert_eq!(
+            messages,
+            vec![
+                "`B` must be declared before `A`, which uses it",
+                "`B` is declared more than once",
+                "`C` must be declared before it is used as a size",
+                "`#B*32768` evaluates to 65536, which does not fit in a 16-bit immediate, \
+                 which goes from 0 to 65535",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_binary() {
+        let mut assembler = Assembler::new();
+        let binary = assembler.assemble_binary("load $0 #1\nhlt\n").unwrap();
+        let bytes = binary.to_bytes();
+        assert_eq!(Binary::from_bytes(&bytes), Ok(binary));
+        assert_eq!(
+            Binary::from_bytes(&bytes).unwrap().code,
+            vec![0x01, 0x00, 0x00, 0x01, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_assemble_object() {
+        let mut assembler = Assembler::new();
+        let object = assembler
+            .assemble_object(
+                Path::new("main.iasm"),
+                ".global start\n.extern print\nstart: load $0 @print\njmp $0\n",
+            )
+            .unwrap();
+        let bindings: Vec<(&str, Binding)> = object
+            .symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.binding))
+            .collect();
+        assert_eq!(
+            bindings,
+            vec![("print", Binding::Extern), ("start", Binding::Global)]
+        );
+        assert_eq!(object.relocations.len(), 1);
+
+        let errors = assembler
+            .assemble(".extern print\n.global nowhere\nload $0 @print\n")
+            .unwrap_err();
+        let messages = error_messages(&errors);
+        assert_eq!(
+            messages,
+            vec![
+                "label `nowhere` is exported but never declared",
+                "`.extern` labels are only resolved by the linker, assemble an object file instead"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_label() {
+        let mut assembler = Assembler::new();
+        let errors = assembler.assemble("test: hlt\ntest: hlt\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ErrorKind::DuplicateLabel);
+        assert_eq!(errors[0].span, Span::new(10, 15));
+    }
+
+    #[test]
+    fn test_undefined_label() {
+        let mut assembler = Assembler::new();
+        let errors = assembler
+            .assemble("load $0 @nowhere\nload $1 @elsewhere\n")
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].kind, ErrorKind::UndefinedLabel);
+        assert_eq!(errors[0].span, Span::new(8, 16));
+        assert_eq!(errors[1].span, Span::new(25, 35));
+    }
+
+    #[test]
+    fn test_errors_are_collected() {
+        let mut assembler = Assembler::new();
+        let errors = assembler
+            .assemble("loda $0 #1\nload $1 @nowhere\n???\n")
+            .unwrap_err();
+        let kinds: Vec<ErrorKind> = errors.iter().map(|e| e.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ErrorKind::Syntax,
+                ErrorKind::UnknownMnemonic,
+                ErrorKind::UndefinedLabel
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unparsed_operands_are_not_counted() {
+        let errors = Assembler::new()
+            .assemble(
+                "load $0 #'ab'\n.asciiz \"\\q\"\nmov $2 'x'\n\
+                 .macro put r, v\nload \\r \\v\n.endm\nput $1 ?\n.if ?\n.endif\n",
+            )
+            .unwrap_err();
+        let messages = error_messages(&errors);
+        assert_eq!(
+            messages,
+            vec![
+                "unexpected `#'ab'`",
+                "unexpected `\"\\q\"`",
+                "unexpected `'x'`",
+                "unexpected `?`",
+                "unexpected `?`",
+            ]
+        );
+    }
 }
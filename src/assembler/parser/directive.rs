@@ -1,37 +1,74 @@
-use super::instruction::AssemblerInstruction;
+use super::expression::parse_expression;
+use super::instruction::{unknown_alias, unzip_spanned, AssemblerInstruction, InstructionSpans};
 use super::integer_operand::parse_integer_operand;
+use super::label::parse_label_declaration;
+use super::register::parse_register;
+use super::spanned;
+use super::string_operand::parse_string_operand;
+use super::trivia::{same_line, skip_trivia};
+use crate::assembler::assembler_errors::{AssemblerError, ErrorKind};
+use crate::assembler::expression::{Expression, Scope};
+use crate::assembler::span::Span;
 use crate::assembler::Token;
+use crate::vm::REGISTER_COUNT;
 use nom::alpha1;
 use nom::types::CompleteStr;
 
 named!(pub parse_directive_declaration<CompleteStr, Token>,
-    do_parse!(
-        tag!(".") >>
-        name: alpha1 >>
-        (
-            Token::Directive { name: name.to_string() }
+    token!(
+        do_parse!(
+            tag!(".") >>
+            name: alpha1 >>
+            (
+                Token::Directive { name: name.to_string() }
+            )
         )
     )
 );
 
+// Directive operands may be separated by commas, and integers and expressions
+// don't need the `#` that instruction operands have, as in `.byte 1, 2, SIZE*2`.
+// They end with the line, so a bare name on the next line is not taken for one.
+named!(parse_directive_operand<CompleteStr, Token>,
+    alt!(
+        parse_integer_operand |
+        parse_register |
+        parse_string_operand |
+        token!(map!(parse_expression, Expression::into_token))
+    )
+);
+
 named!(parse_directive_combined<CompleteStr, AssemblerInstruction>,
-    ws!(
-        do_parse!(
-            tag!(".") >>
-            name: parse_directive_declaration >>
-            o1: opt!(parse_integer_operand) >>
-            o2: opt!(parse_integer_operand) >>
-            o3: opt!(parse_integer_operand) >>
-            (
+    do_parse!(
+        l: opt!(call!(spanned, parse_label_declaration)) >>
+        name: call!(spanned, parse_directive_declaration) >>
+        operands: many0!(
+            delimited!(
+                same_line,
+                call!(spanned, parse_directive_operand),
+                opt!(preceded!(skip_trivia, tag!(",")))
+            )
+        ) >>
+        call!(skip_trivia) >>
+        (
+            {
+                let (label, label_span) = unzip_spanned(l);
+                let (operands, operand_spans) = operands.into_iter().unzip();
+
                 AssemblerInstruction {
                     opcode: None,
-                    directive: Some(name),
-                    label: None,
-                    operand1: o1,
-                    operand2: o2,
-                    operand3: o3
+                    directive: Some(name.0),
+                    label,
+                    operands,
+                    spans: InstructionSpans {
+                        label: label_span,
+                        head: name.1,
+                        operands: operand_spans,
+                        ..InstructionSpans::default()
+                    },
+                    ..AssemblerInstruction::default()
                 }
-            )
+            }
         )
     )
 );
@@ -46,3 +83,373 @@ named!(pub parse_directive<CompleteStr, AssemblerInstruction>,
         )
     )
 );
+
+/// Encodes the data the `.name` directive places at `offset` in the program,
+/// with the labels and constants its operands use looked up in `scope`.
+pub fn directive_bytes(
+    instruction: &AssemblerInstruction,
+    name: &str,
+    scope: Scope,
+    offset: u32,
+) -> Result<Vec<u8>, AssemblerError> {
+    let operands: Vec<(&Token, Span)> = instruction.operands_with_spans().collect();
+    let mut result = vec![];
+
+    match name {
+        "byte" | "half" | "word" => {
+            let width = match name {
+                "byte" => 1,
+                "half" => 2,
+                _ => 4,
+            };
+            let min = -(1i64 << (width * 8 - 1));
+            let max = (1i64 << (width * 8)) - 1;
+
+            check_count(instruction, name, operands.len(), 1, None)?;
+
+            for (operand, span) in operands {
+                let value = evaluate((operand, span), scope, "an integer or a label")?;
+
+                if value < min || value > max {
+                    return Err(AssemblerError::new(
+                        ErrorKind::OutOfRange,
+                        span,
+                        format!(
+                            "{} does not fit in a {}-bit value, which goes from {} to {}",
+                            describe_value(operand, value),
+                            width * 8,
+                            min,
+                            max
+                        ),
+                    ));
+                }
+
+                // Big-endian, like the immediates in the bytecode.
+                result.extend_from_slice(&value.to_be_bytes()[8 - width..]);
+            }
+        }
+        "asciiz" => {
+            check_count(instruction, name, operands.len(), 1, None)?;
+
+            for (operand, span) in operands {
+                match operand {
+                    Token::StringOperand { value } => {
+                        result.extend_from_slice(value.as_bytes());
+                        result.push(0);
+                    }
+                    _ => return Err(invalid_operand(span, "a string", operand)),
+                }
+            }
+        }
+        "space" => {
+            check_count(instruction, name, operands.len(), 1, Some(2))?;
+
+            let count = integer_operand(operands[0], scope, 0, i64::from(u16::MAX))?;
+            let fill = match operands.get(1) {
+                Some(operand) => integer_operand(*operand, scope, -128, 255)?,
+                None => 0,
+            };
+
+            result.resize(count as usize, fill as u8);
+        }
+        "code" | "data" | "endfunc" => check_count(instruction, name, operands.len(), 0, Some(0))?,
+        // The prologue and the epilogue are added before anything is encoded,
+        // with the frame size as the operand of a `load`, which checks it.
+        "func" => {
+            check_count(instruction, name, operands.len(), 1, None)?;
+
+            let last = operands.len() - 1;
+
+            for (i, (operand, span)) in operands.into_iter().enumerate() {
+                match operand {
+                    Token::Identifier { .. } if i == 0 => {}
+                    _ if i == 0 => return Err(invalid_operand(span, "a function name", operand)),
+                    Token::Register { .. } => {}
+                    Token::RegisterAlias { name } => {
+                        return Err(AssemblerError::new(
+                            ErrorKind::InvalidOperand,
+                            span,
+                            unknown_alias(name),
+                        ));
+                    }
+                    Token::IntegerOperand { .. } | Token::Expression { .. } if i == last => {}
+                    _ => {
+                        return Err(invalid_operand(
+                            span,
+                            "a register to save or the frame size",
+                            operand,
+                        ))
+                    }
+                }
+            }
+        }
+        "global" | "extern" => {
+            check_count(instruction, name, operands.len(), 1, None)?;
+
+            for (operand, span) in operands {
+                if let Token::Identifier { .. } = operand {
+                    continue;
+                }
+
+                return Err(invalid_operand(span, "a label name", operand));
+            }
+        }
+        // The constant is added to the symbols along with the labels, which
+        // is where its value gets checked.
+        "equ" => {
+            check_count(instruction, name, operands.len(), 2, Some(2))?;
+
+            let (operand, span) = operands[0];
+
+            if !matches!(operand, Token::Identifier { .. }) {
+                return Err(invalid_operand(span, "a constant name", operand));
+            }
+        }
+        // Aliases are replaced with their register before anything is encoded.
+        "alias" => {
+            check_count(instruction, name, operands.len(), 2, Some(2))?;
+
+            match operands[0] {
+                (Token::Identifier { .. }, _) => {}
+                (operand, span) => return Err(invalid_operand(span, "an alias name", operand)),
+            }
+
+            match operands[1] {
+                (Token::Register { reg_num }, _) if usize::from(*reg_num) < REGISTER_COUNT => {}
+                (operand @ Token::Register { .. }, span) => {
+                    return Err(AssemblerError::new(
+                        ErrorKind::OutOfRange,
+                        span,
+                        format!(
+                            "register `{}` does not exist, registers go from `$0` to `${}`",
+                            operand,
+                            REGISTER_COUNT - 1
+                        ),
+                    ));
+                }
+                (Token::RegisterAlias { name }, span) => {
+                    return Err(AssemblerError::new(
+                        ErrorKind::InvalidOperand,
+                        span,
+                        unknown_alias(name),
+                    ));
+                }
+                (operand, span) => return Err(invalid_operand(span, "a register", operand)),
+            }
+        }
+        "align" => {
+            check_count(instruction, name, operands.len(), 1, Some(1))?;
+
+            let (_, span) = operands[0];
+            let alignment = integer_operand(operands[0], scope, 1, i64::from(u16::MAX) + 1)? as u32;
+
+            if !alignment.is_power_of_two() {
+                return Err(AssemblerError::new(
+                    ErrorKind::InvalidOperand,
+                    span,
+                    format!("alignment must be a power of two, found {}", alignment),
+                ));
+            }
+
+            result.resize(((alignment - offset % alignment) % alignment) as usize, 0);
+        }
+        _ => {
+            return Err(AssemblerError::new(
+                ErrorKind::UnknownDirective,
+                instruction.spans.head,
+                format!("unknown directive `.{}`", name),
+            ));
+        }
+    }
+
+    Ok(result)
+}
+
+fn check_count(
+    instruction: &AssemblerInstruction,
+    name: &str,
+    found: usize,
+    min: usize,
+    max: Option<usize>,
+) -> Result<(), AssemblerError> {
+    if found >= min && max.is_none_or(|max| found <= max) {
+        return Ok(());
+    }
+
+    let count = |n: usize| format!("{} operand{}", n, if n == 1 { "" } else { "s" });
+    let expected = match max {
+        None => format!("at least {}", count(min)),
+        Some(max) if max == min => count(min),
+        Some(max) => format!("{} to {} operands", min, max),
+    };
+    let span = match max.and_then(|max| instruction.spans.operands.get(max)) {
+        Some(span) => *span,
+        None => instruction.spans.statement,
+    };
+
+    Err(AssemblerError::new(
+        ErrorKind::OperandCount,
+        span,
+        format!("`.{}` takes {}, found {}", name, expected, found),
+    ))
+}
+
+fn integer_operand(
+    operand: (&Token, Span),
+    scope: Scope,
+    min: i64,
+    max: i64,
+) -> Result<i64, AssemblerError> {
+    let value = evaluate(operand, scope, "an integer")?;
+
+    if value < min || value > max {
+        return Err(AssemblerError::new(
+            ErrorKind::OutOfRange,
+            operand.1,
+            format!(
+                "{} is out of range, expected a value from {} to {}",
+                describe_value(operand.0, value),
+                min,
+                max
+            ),
+        ));
+    }
+
+    Ok(value)
+}
+
+/// Value of an integer, label, constant or expression operand.
+pub fn evaluate(
+    operand: (&Token, Span),
+    scope: Scope,
+    expected: &str,
+) -> Result<i64, AssemblerError> {
+    let (token, span) = operand;
+
+    match Expression::from_token(token) {
+        Some(expr) => expr.evaluate(scope).map_err(|e| e.at(span, token)),
+        None => Err(invalid_operand(span, expected, token)),
+    }
+}
+
+/// How an operand is referred to in range errors: a literal by itself, anything
+/// else along with what it evaluates to.
+pub fn describe_value(operand: &Token, value: i64) -> String {
+    match operand {
+        Token::IntegerOperand { .. } => format!("`{}`", operand),
+        _ => format!("`{}` evaluates to {}, which", operand, value),
+    }
+}
+
+fn invalid_operand(span: Span, expected: &str, found: &Token) -> AssemblerError {
+    AssemblerError::new(
+        ErrorKind::InvalidOperand,
+        span,
+        format!("expected {}, found `{}`", expected, found),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::parser::instruction::parse_instruction;
+    use crate::assembler::symbols::{Symbol, SymbolTable, SymbolType};
+    use crate::assembler::Section;
+
+    fn assemble(source: &str, offset: u32) -> Result<Vec<u8>, AssemblerError> {
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol(Symbol::new(
+            "table".to_string(),
+            SymbolType::Label,
+            Section::Code,
+            0x1234,
+        ));
+        symbols.add_symbol(Symbol::new(
+            "SIZE".to_string(),
+            SymbolType::Constant(3),
+            Section::Code,
+            0,
+        ));
+
+        let (rest, instruction) = parse_instruction(CompleteStr(source)).unwrap();
+        assert_eq!(rest, CompleteStr(""));
+        instruction.to_bytes(&symbols, offset)
+    }
+
+    #[test]
+    fn test_parse_directive() {
+        let (rest, instruction) =
+            parse_directive(CompleteStr("msg: .byte #1, 0x02 'c' @table\n")).unwrap();
+        assert_eq!(rest, CompleteStr(""));
+        assert_eq!(instruction.label_name(), Some("msg"));
+        assert_eq!(
+            instruction.directive,
+            Some(Token::Directive {
+                name: "byte".to_string()
+            })
+        );
+        assert_eq!(instruction.operands.len(), 4);
+        assert_eq!(instruction.spans.operands.len(), 4);
+    }
+
+    #[test]
+    fn test_data_directives() {
+        assert_eq!(assemble(".byte 1, -1, 'A'", 0), Ok(vec![0x01, 0xFF, 0x41]));
+        assert_eq!(
+            assemble(".half 0x1234 -2", 0),
+            Ok(vec![0x12, 0x34, 0xFF, 0xFE])
+        );
+        assert_eq!(
+            assemble(".word 0xDEADBEEF, @table", 0),
+            Ok(vec![0xDE, 0xAD, 0xBE, 0xEF, 0x00, 0x00, 0x12, 0x34])
+        );
+        assert_eq!(
+            assemble(r#".asciiz "hi\n", "" "#, 0),
+            Ok(vec![b'h', b'i', b'\n', 0, 0])
+        );
+        assert_eq!(assemble(".space 3", 0), Ok(vec![0, 0, 0]));
+        assert_eq!(assemble(".space 2, 0xFF", 0), Ok(vec![0xFF, 0xFF]));
+        assert_eq!(assemble(".align 4", 5), Ok(vec![0, 0, 0]));
+        assert_eq!(assemble(".align 4", 8), Ok(vec![]));
+        assert_eq!(
+            assemble(".byte SIZE, SIZE*2+1, (SIZE - 1)<<4", 0),
+            Ok(vec![0x03, 0x07, 0x20])
+        );
+        assert_eq!(assemble(".half @table+SIZE", 0), Ok(vec![0x12, 0x37]));
+        assert_eq!(assemble(".space SIZE-1", 0), Ok(vec![0, 0]));
+        assert_eq!(assemble(".equ TWO SIZE-1", 0), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_data_directive_errors() {
+        let kind = |source: &str| assemble(source, 0).unwrap_err().kind;
+
+        assert_eq!(kind(".byte 256"), ErrorKind::OutOfRange);
+        assert_eq!(kind(".half 65536"), ErrorKind::OutOfRange);
+        assert_eq!(kind(".byte"), ErrorKind::OperandCount);
+        assert_eq!(kind(".byte \"text\""), ErrorKind::InvalidOperand);
+        assert_eq!(kind(".asciiz 1"), ErrorKind::InvalidOperand);
+        assert_eq!(kind(".space 1 2 3"), ErrorKind::OperandCount);
+        assert_eq!(kind(".align 3"), ErrorKind::InvalidOperand);
+        assert_eq!(kind(".word @nowhere"), ErrorKind::UndefinedLabel);
+        assert_eq!(kind(".frobnicate"), ErrorKind::UnknownDirective);
+        assert_eq!(kind(".global"), ErrorKind::OperandCount);
+        assert_eq!(kind(".extern @table"), ErrorKind::InvalidOperand);
+        assert_eq!(
+            assemble(".byte 256", 0).unwrap_err().message,
+            "`#256` does not fit in a 8-bit value, which goes from -128 to 255"
+        );
+        assert_eq!(kind(".equ 5 5"), ErrorKind::InvalidOperand);
+        assert_eq!(kind(".equ SIZE"), ErrorKind::OperandCount);
+        assert_eq!(kind(".byte SIZE/0"), ErrorKind::InvalidOperand);
+        assert_eq!(
+            assemble(".byte SIZE*100", 0).unwrap_err().message,
+            "`#SIZE*100` evaluates to 300, which does not fit in a 8-bit value, \
+             which goes from -128 to 255"
+        );
+        assert_eq!(
+            assemble(".space 1 2 3", 0).unwrap_err().message,
+            "`.space` takes 1 to 2 operands, found 3"
+        );
+    }
+}
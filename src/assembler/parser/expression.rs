@@ -0,0 +1,200 @@
+use crate::assembler::expression::{BinaryOp, Expression, UnaryOp};
+use crate::assembler::parser::integer_operand::parse_integer_literal;
+use nom::types::CompleteStr;
+use nom::{ErrorKind, IResult};
+
+/// Parses an expression such as `BUF_SIZE*2`, `@table+4` or `(END - START)`.
+/// Operators bind as in C, and spaces are only allowed inside parentheses, as
+/// outside of them they separate operands.
+pub fn parse_expression(input: CompleteStr) -> IResult<CompleteStr, Expression> {
+    parse_binary(input, 0, false)
+}
+
+/// Precedence climbing: parses operands joined by operators that bind at least
+/// as tightly as `min_precedence`.
+fn parse_binary(
+    input: CompleteStr,
+    min_precedence: u8,
+    nested: bool,
+) -> IResult<CompleteStr, Expression> {
+    let (mut rest, mut left) = parse_unary(input, nested)?;
+
+    loop {
+        let (after_op, op) = match parse_binary_op(skip_spaces(rest, nested)) {
+            Some((after_op, op)) if op.precedence() >= min_precedence => (after_op, op),
+            _ => break,
+        };
+        let (after_right, right) =
+            parse_binary(skip_spaces(after_op, nested), op.precedence() + 1, nested)?;
+
+        left = Expression::Binary(op, Box::new(left), Box::new(right));
+        rest = after_right;
+    }
+
+    Ok((rest, left))
+}
+
+fn parse_unary(input: CompleteStr, nested: bool) -> IResult<CompleteStr, Expression> {
+    // `-5` is a literal, `-SIZE` and `-(1+2)` are negations.
+    if let Ok((rest, value)) = parse_integer_literal(input) {
+        return Ok((rest, Expression::Integer(value)));
+    }
+
+    let op = match input.chars().next() {
+        Some('-') => UnaryOp::Neg,
+        Some('~') => UnaryOp::Not,
+        _ => return parse_primary(input),
+    };
+    let (rest, operand) = parse_unary(skip_spaces(CompleteStr(&input[1..]), nested), nested)?;
+
+    Ok((rest, Expression::Unary(op, Box::new(operand))))
+}
+
+fn parse_primary(input: CompleteStr) -> IResult<CompleteStr, Expression> {
+    let error = || {
+        Err(nom::Err::Error(error_position!(
+            input,
+            ErrorKind::Custom(0)
+        )))
+    };
+
+    match input.chars().next() {
+        Some('(') => {
+            let (rest, expr) = parse_binary(skip_spaces(CompleteStr(&input[1..]), true), 0, true)?;
+            let rest = skip_spaces(rest, true);
+
+            match rest.chars().next() {
+                Some(')') => Ok((CompleteStr(&rest[1..]), expr)),
+                _ => error(),
+            }
+        }
+        Some('@') => {
+            // `.loop` belongs to the label before it.
+            let dot = if input[1..].starts_with('.') { "." } else { "" };
+
+            match name(&input[1 + dot.len()..], false) {
+                Some((name, rest)) => Ok((rest, Expression::Label(format!("{}{}", dot, name)))),
+                None => error(),
+            }
+        }
+        Some('\\') => match name(&input[1..], false) {
+            Some((name, rest)) => Ok((rest, Expression::Parameter(name))),
+            None => error(),
+        },
+        _ => match name(&input, true) {
+            Some((name, rest)) => Ok((rest, Expression::Name(name))),
+            None => error(),
+        },
+    }
+}
+
+fn parse_binary_op(input: CompleteStr) -> Option<(CompleteStr, BinaryOp)> {
+    let ops = [
+        ("<<", BinaryOp::Shl),
+        (">>", BinaryOp::Shr),
+        ("<=", BinaryOp::Le),
+        (">=", BinaryOp::Ge),
+        ("==", BinaryOp::Eq),
+        ("!=", BinaryOp::Ne),
+        ("<", BinaryOp::Lt),
+        (">", BinaryOp::Gt),
+        ("*", BinaryOp::Mul),
+        ("/", BinaryOp::Div),
+        ("%", BinaryOp::Rem),
+        ("+", BinaryOp::Add),
+        ("-", BinaryOp::Sub),
+        ("&", BinaryOp::And),
+        ("^", BinaryOp::Xor),
+        ("|", BinaryOp::Or),
+    ];
+
+    ops.iter()
+        .find(|(symbol, _)| input.starts_with(symbol))
+        .map(|(symbol, op)| (CompleteStr(&input[symbol.len()..]), *op))
+}
+
+/// The letters, digits and underscores `input` starts with. A `bare` name, one
+/// without `@` or `\` in front, can't start with a digit or it would be a number.
+fn name(input: &str, bare: bool) -> Option<(String, CompleteStr<'_>)> {
+    let len = input
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(input.len());
+
+    match input.chars().next() {
+        Some(c) if len > 0 && !(bare && c.is_ascii_digit()) => {
+            Some((input[..len].to_string(), CompleteStr(&input[len..])))
+        }
+        _ => None,
+    }
+}
+
+fn skip_spaces(input: CompleteStr, nested: bool) -> CompleteStr {
+    if nested {
+        CompleteStr(input.trim_start_matches([' ', '\t']))
+    } else {
+        input
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn integer(value: i64) -> Box<Expression> {
+        Box::new(Expression::Integer(value))
+    }
+
+    #[test]
+    fn test_parse_expression() {
+        assert_eq!(
+            parse_expression(CompleteStr("1+2*3 $0")),
+            Ok((
+                CompleteStr(" $0"),
+                Expression::Binary(
+                    BinaryOp::Add,
+                    integer(1),
+                    Box::new(Expression::Binary(BinaryOp::Mul, integer(2), integer(3)))
+                )
+            ))
+        );
+        assert_eq!(
+            parse_expression(CompleteStr("( END - START )")),
+            Ok((
+                CompleteStr(""),
+                Expression::Binary(
+                    BinaryOp::Sub,
+                    Box::new(Expression::Name("END".to_string())),
+                    Box::new(Expression::Name("START".to_string()))
+                )
+            ))
+        );
+        assert_eq!(
+            parse_expression(CompleteStr("-BUF_SIZE")),
+            Ok((
+                CompleteStr(""),
+                Expression::Unary(
+                    UnaryOp::Neg,
+                    Box::new(Expression::Name("BUF_SIZE".to_string()))
+                )
+            ))
+        );
+        assert_eq!(
+            parse_expression(CompleteStr("-0x10")),
+            Ok((CompleteStr(""), Expression::Integer(-16)))
+        );
+        assert_eq!(
+            parse_expression(CompleteStr("@.loop-@1b")),
+            Ok((
+                CompleteStr(""),
+                Expression::Binary(
+                    BinaryOp::Sub,
+                    Box::new(Expression::Label(".loop".to_string())),
+                    Box::new(Expression::Label("1b".to_string()))
+                )
+            ))
+        );
+        assert!(parse_expression(CompleteStr("(1+2")).is_err());
+        assert!(parse_expression(CompleteStr("1+")).is_err());
+        assert!(parse_expression(CompleteStr("$1")).is_err());
+    }
+}
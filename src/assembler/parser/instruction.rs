@@ -1,70 +1,477 @@
-use super::directive::parse_directive;
+use super::directive::{describe_value, directive_bytes, parse_directive};
 use super::integer_operand::parse_operand;
 use super::label::parse_label_declaration;
 use super::opcode::parse_opcode;
-use crate::assembler::Token;
+use super::spanned;
+use super::trivia::{skip_trivia, Comment};
+use crate::assembler::assembler_errors::{AssemblerError, ErrorKind};
+use crate::assembler::expression::{Expression, Scope};
+use crate::assembler::macros::Expansion;
+use crate::assembler::span::Span;
+use crate::assembler::symbols::SymbolTable;
+use crate::assembler::{Section, Token};
+use crate::instruction::{Opcode, OperandKind};
+use crate::vm::REGISTER_COUNT;
 use nom::types::CompleteStr;
+use nom::IResult;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Default)]
 pub struct AssemblerInstruction {
     pub opcode: Option<Token>,
     pub label: Option<Token>,
     pub directive: Option<Token>,
-    pub operand1: Option<Token>,
-    pub operand2: Option<Token>,
-    pub operand3: Option<Token>,
+    pub operands: Vec<Token>,
+    pub spans: InstructionSpans,
+    /// Comments on the lines before the instruction.
+    pub leading_comments: Vec<Comment>,
+    /// Comments between the tokens of the instruction and after it on the same line.
+    pub trailing_comments: Vec<Comment>,
+    /// The macro invocation the instruction was expanded from, if any.
+    pub expansion: Option<Expansion>,
+    /// Whether the rest of the line after the instruction couldn't be parsed,
+    /// which is reported already, so its operands may be missing.
+    pub unparsed_operands: bool,
+}
+
+/// Where an instruction and each of its tokens sit in the source.
+#[derive(Debug, Clone, Default)]
+pub struct InstructionSpans {
+    pub statement: Span,
+    pub label: Span,
+    /// Span of the opcode or of the directive name.
+    pub head: Span,
+    pub operands: Vec<Span>,
+}
+
+impl InstructionSpans {
+    pub fn resolve(&mut self, source_len: usize) {
+        self.statement.resolve(source_len);
+        self.label.resolve(source_len);
+        self.head.resolve(source_len);
+
+        for span in &mut self.operands {
+            span.resolve(source_len);
+        }
+    }
+}
+
+// Source positions, comments and expansions don't take part in equality, so parsed instructions can be
+// compared against hand-written ones.
+impl PartialEq for AssemblerInstruction {
+    fn eq(&self, other: &Self) -> bool {
+        self.opcode == other.opcode
+            && self.label == other.label
+            && self.directive == other.directive
+            && self.operands == other.operands
+    }
 }
 
 impl AssemblerInstruction {
-    pub fn to_bytes(&self) -> Vec<u8> {
+    pub fn resolve_spans(&mut self, source_len: usize) {
+        self.for_each_span(|span| span.resolve(source_len));
+    }
+
+    /// Calls `f` with every span of the instruction, those of its comments and
+    /// of its macro expansion included.
+    pub fn for_each_span(&mut self, mut f: impl FnMut(&mut Span)) {
+        f(&mut self.spans.statement);
+        f(&mut self.spans.label);
+        f(&mut self.spans.head);
+        self.spans.operands.iter_mut().for_each(&mut f);
+
+        for comment in self
+            .leading_comments
+            .iter_mut()
+            .chain(self.trailing_comments.iter_mut())
+        {
+            f(&mut comment.span);
+        }
+
+        if let Some(expansion) = &mut self.expansion {
+            f(&mut expansion.invocation);
+        }
+    }
+
+    /// Spans of every token of the instruction, in source order.
+    pub fn token_spans(&self) -> Vec<Span> {
+        let mut spans = vec![];
+
+        if self.label.is_some() {
+            spans.push(self.spans.label);
+        }
+
+        if self.opcode.is_some() || self.directive.is_some() {
+            spans.push(self.spans.head);
+        }
+
+        spans.extend(self.operands_with_spans().map(|(_, span)| span));
+        spans
+    }
+
+    /// Encodes the instruction, or the data of the directive, placed `offset`
+    /// bytes into the program.
+    pub fn to_bytes(&self, symbols: &SymbolTable, offset: u32) -> Result<Vec<u8>, AssemblerError> {
+        match self.encode(symbols, offset) {
+            Err(e) if !self.reports(&e) => Ok(vec![]),
+            result => result,
+        }
+    }
+
+    fn encode(&self, symbols: &SymbolTable, offset: u32) -> Result<Vec<u8>, AssemblerError> {
         let mut result = vec![];
 
-        match &self.opcode {
-            Some(Token::Op { code }) => {
-                result.push(code.to_u8());
+        let code = match &self.opcode {
+            Some(Token::Op { code }) => code,
+            Some(Token::Mnemonic { name }) => {
+                return Err(self.unknown_mnemonic(name));
             }
-            _ => {
-                println!("Non-opcode found in opcode field");
-                std::process::exit(1);
+            _ => match &self.directive {
+                Some(Token::Directive { name }) => {
+                    return directive_bytes(self, name, Scope::Complete(symbols), offset);
+                }
+                _ => return Ok(result),
+            },
+        };
+
+        self.check_operands(code)?;
+        result.push(code.to_u8());
+
+        let mut operands = self.operands_with_spans();
+
+        for kind in code.operands() {
+            if *kind == OperandKind::Padding {
+                result.push(0);
+            } else if let Some((operand, span)) = operands.next() {
+                AssemblerInstruction::extract_operand(operand, *kind, span, symbols, &mut result)?;
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Number of bytes `to_bytes` will produce at `offset`, known before every
+    /// label is: only the constants in `symbols` are needed, and labels not
+    /// declared yet are taken to be at offset zero. Statements that won't
+    /// encode take no room.
+    pub fn encoded_len(&self, symbols: &SymbolTable, offset: u32) -> u32 {
+        match (&self.opcode, &self.directive) {
+            (Some(Token::Op { code }), _) => code.encoded_len() as u32,
+            (None, Some(Token::Directive { name })) => {
+                directive_bytes(self, name, Scope::FirstPass(symbols), offset)
+                    .map_or(0, |bytes| bytes.len() as u32)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Every field that `to_bytes` writes an address into, as the label the
+    /// linker has to add the final address of, the offset of the field in the
+    /// encoded bytes and its size. Fails on an expression that can't be
+    /// relocated, such as a label times two.
+    pub fn label_fields(
+        &self,
+        symbols: &SymbolTable,
+    ) -> Result<Vec<(String, u32, u32)>, AssemblerError> {
+        let mut fields = vec![];
+        let mut push = |operand: &Token, span: Span, offset: u32, size: u32| {
+            if let Some(expr) = Expression::from_token(operand) {
+                if let Some(name) = expr.relocation(symbols).map_err(|e| e.at(span, operand))? {
+                    fields.push((name, offset, size));
+                }
             }
+
+            Ok(())
+        };
+
+        match (&self.opcode, &self.directive) {
+            (Some(Token::Op { code }), _) => {
+                // The opcode itself takes the first byte.
+                let mut offset = 1;
+                let mut operands = self.operands_with_spans();
+
+                for kind in code.operands() {
+                    if *kind != OperandKind::Padding {
+                        if let Some((operand, span)) = operands.next() {
+                            push(operand, span, offset, kind.size() as u32)?;
+                        }
+                    }
+
+                    offset += kind.size() as u32;
+                }
+            }
+            (None, Some(Token::Directive { name })) => {
+                let width = match name.as_str() {
+                    "byte" => 1,
+                    "half" => 2,
+                    "word" => 4,
+                    _ => return Ok(fields),
+                };
+
+                for (i, (operand, span)) in self.operands_with_spans().enumerate() {
+                    push(operand, span, i as u32 * width, width)?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(fields)
+    }
+
+    /// Checks the operands against the signature of `code`: their number, their
+    /// kinds and that registers and immediates are in range.
+    fn check_operands(&self, code: &Opcode) -> Result<(), AssemblerError> {
+        let expected: Vec<OperandKind> = code.source_operands().collect();
+        let operands: Vec<(&Token, Span)> = self.operands_with_spans().collect();
+
+        if operands.len() != expected.len() {
+            let span = match operands.get(expected.len()) {
+                Some((_, span)) => *span,
+                None => self.spans.statement,
+            };
+
+            return Err(AssemblerError::new(
+                ErrorKind::OperandCount,
+                span,
+                format!(
+                    "`{}` takes {}, found {}",
+                    code,
+                    describe_operands(&expected),
+                    operands.len()
+                ),
+            ));
         }
 
-        for operand in [&self.operand1, &self.operand2, &self.operand3].iter() {
-            match operand {
-                Some(t) => AssemblerInstruction::extract_operand(t, &mut result),
-                None => {}
+        for ((operand, span), kind) in operands.into_iter().zip(expected) {
+            if let Err((error_kind, message)) = check_operand(operand, kind) {
+                return Err(AssemblerError::new(error_kind, span, message));
             }
         }
 
-        result
+        Ok(())
     }
 
-    fn extract_operand(t: &Token, result: &mut Vec<u8>) {
+    /// The section a `.code` or `.data` directive switches to.
+    pub fn section(&self) -> Option<Section> {
+        match (&self.opcode, &self.directive) {
+            (None, Some(Token::Directive { name })) if name == "code" => Some(Section::Code),
+            (None, Some(Token::Directive { name })) if name == "data" => Some(Section::Data),
+            _ => None,
+        }
+    }
+
+    /// Adds a note pointing at the macro invocation to errors in an expanded
+    /// instruction, whose spans point into the macro definition.
+    pub fn in_context(&self, error: AssemblerError) -> AssemblerError {
+        match &self.expansion {
+            Some(expansion) => error.with_note(
+                expansion.invocation,
+                format!("in this expansion of `{}`", expansion.name),
+            ),
+            None => error,
+        }
+    }
+
+    /// Whether `error` about the instruction is worth reporting: an error
+    /// about the number of operands isn't when some of them couldn't be
+    /// parsed.
+    pub fn reports(&self, error: &AssemblerError) -> bool {
+        !(self.unparsed_operands && error.kind == ErrorKind::OperandCount)
+    }
+
+    pub fn label_name(&self) -> Option<&str> {
+        match &self.label {
+            Some(Token::LabelDeclaration { name }) => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn operands_with_spans(&self) -> impl Iterator<Item = (&Token, Span)> {
+        self.operands
+            .iter()
+            .zip(self.spans.operands.iter().cloned())
+    }
+
+    fn unknown_mnemonic(&self, name: &str) -> AssemblerError {
+        let message = match Opcode::closest(name) {
+            Some(opcode) => format!("unknown mnemonic `{}`, did you mean `{}`?", name, opcode),
+            None => format!("unknown mnemonic `{}`", name),
+        };
+
+        AssemblerError::new(ErrorKind::UnknownMnemonic, self.spans.head, message)
+    }
+
+    fn extract_operand(
+        t: &Token,
+        kind: OperandKind,
+        span: Span,
+        symbols: &SymbolTable,
+        result: &mut Vec<u8>,
+    ) -> Result<(), AssemblerError> {
         match t {
             Token::Register { reg_num } => {
                 result.push(*reg_num);
             }
+            // `check_operands` made sure the value fits, negative values are
+            // written in two's complement.
             Token::IntegerOperand { value } => {
-                let converted = *value as u16;
-                let byte1 = converted;
-                let byte2 = converted >> 8;
+                AssemblerInstruction::push_16_bits(*value as u16, result);
+            }
+            Token::LabelUsabe { name } => match symbols.symbol_value(name) {
+                Some(offset) if !fits(i64::from(offset), kind) => {
+                    return Err(AssemblerError::new(
+                        ErrorKind::OutOfRange,
+                        span,
+                        format!(
+                            "label `{}` is at offset {}, which does not fit in a {}",
+                            name, offset, kind
+                        ),
+                    ));
+                }
+                Some(offset) => AssemblerInstruction::push_16_bits(offset as u16, result),
+                None => {
+                    return Err(AssemblerError::new(
+                        ErrorKind::UndefinedLabel,
+                        span,
+                        format!("label `{}` is used but never declared", name),
+                    ));
+                }
+            },
+            Token::Expression { expr } => {
+                let value = expr
+                    .evaluate(Scope::Complete(symbols))
+                    .map_err(|e| e.at(span, t))?;
+
+                if !fits(value, kind) {
+                    let (min, max) = kind.range().unwrap_or_default();
 
-                result.push(byte2 as u8);
-                result.push(byte1 as u8);
+                    return Err(AssemblerError::new(
+                        ErrorKind::OutOfRange,
+                        span,
+                        format!(
+                            "{} does not fit in a {}, which goes from {} to {}",
+                            describe_value(t, value),
+                            kind,
+                            min,
+                            max
+                        ),
+                    ));
+                }
+
+                AssemblerInstruction::push_16_bits(value as u16, result);
             }
             _ => {
-                println!("Opcode found in operand field");
-                std::process::exit(1);
+                return Err(AssemblerError::new(
+                    ErrorKind::InvalidOperand,
+                    span,
+                    "expected a register, an integer or a label".to_string(),
+                ));
             }
         }
+
+        Ok(())
+    }
+
+    fn push_16_bits(value: u16, result: &mut Vec<u8>) {
+        let byte1 = value;
+        let byte2 = value >> 8;
+
+        result.push(byte2 as u8);
+        result.push(byte1 as u8);
     }
 }
 
-named!(pub parse_instruction<CompleteStr, AssemblerInstruction>,
+fn check_operand(operand: &Token, kind: OperandKind) -> Result<(), (ErrorKind, String)> {
+    match (operand, kind) {
+        (Token::Register { reg_num }, OperandKind::Register) => {
+            if usize::from(*reg_num) >= REGISTER_COUNT {
+                return Err((
+                    ErrorKind::OutOfRange,
+                    format!(
+                        "register `{}` does not exist, registers go from `$0` to `${}`",
+                        operand,
+                        REGISTER_COUNT - 1
+                    ),
+                ));
+            }
+        }
+        (Token::IntegerOperand { value }, OperandKind::Immediate)
+        | (Token::IntegerOperand { value }, OperandKind::SignedImmediate) => {
+            if !fits(*value, kind) {
+                let (min, max) = kind.range().unwrap_or_default();
+                let mut message = format!(
+                    "`{}` does not fit in a {}, which goes from {} to {}",
+                    operand, kind, min, max
+                );
+
+                if kind == OperandKind::Immediate && fits(*value, OperandKind::SignedImmediate) {
+                    message.push_str(", use `loads` to load a negative value");
+                }
+
+                return Err((ErrorKind::OutOfRange, message));
+            }
+        }
+        (Token::RegisterAlias { name }, OperandKind::Register) => {
+            return Err((ErrorKind::InvalidOperand, unknown_alias(name)));
+        }
+        // Checked once their value is known, when encoded.
+        (Token::LabelUsabe { .. }, OperandKind::Immediate)
+        | (Token::LabelUsabe { .. }, OperandKind::SignedImmediate)
+        | (Token::Expression { .. }, OperandKind::Immediate)
+        | (Token::Expression { .. }, OperandKind::SignedImmediate) => {}
+        _ => {
+            return Err((
+                ErrorKind::InvalidOperand,
+                format!("expected a {}, found `{}`", kind, operand),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The message for a register alias that no `.alias` before it declares.
+pub fn unknown_alias(name: &str) -> String {
+    format!(
+        "unknown register `${}`, declare it with `.alias {} $N` first",
+        name, name
+    )
+}
+
+fn fits(value: i64, kind: OperandKind) -> bool {
+    match kind.range() {
+        Some((min, max)) => min <= value && value <= max,
+        None => false,
+    }
+}
+
+pub fn describe_operands(kinds: &[OperandKind]) -> String {
+    let names: Vec<String> = kinds.iter().map(|kind| kind.to_string()).collect();
+
+    match names.len() {
+        0 => "no operands".to_string(),
+        1 => format!("1 operand ({})", names[0]),
+        n => format!("{} operands ({})", n, names.join(", ")),
+    }
+}
+
+/// Parses a single statement: an instruction, a directive or a lone label.
+pub fn parse_instruction(input: CompleteStr) -> IResult<CompleteStr, AssemblerInstruction> {
+    let (rest, mut instruction) = parse_statement(input)?;
+    let tokens = instruction.token_spans();
+
+    if let (Some(first), Some(last)) = (tokens.first(), tokens.last()) {
+        instruction.spans.statement = Span::new(first.start, last.end);
+    }
+
+    Ok((rest, instruction))
+}
+
+named!(parse_statement<CompleteStr, AssemblerInstruction>,
     do_parse!(
         ins: alt!(
             parse_instruction_combined |
-            parse_directive
+            parse_directive |
+            parse_label_only
         ) >>
         (
             ins
@@ -72,30 +479,73 @@ named!(pub parse_instruction<CompleteStr, AssemblerInstruction>,
     )
 );
 
+// Operands may be separated by commas, as those of directives, so macros are
+// invoked as their parameters are declared: `swap $1, $2`.
 named!(parse_instruction_combined<CompleteStr, AssemblerInstruction>,
     do_parse!(
-        l: opt!(parse_label_declaration) >>
-        o: parse_opcode >>
-        o1: opt!(parse_operand) >>
-        o2: opt!(parse_operand) >>
-        o3: opt!(parse_operand) >>
+        l: opt!(call!(spanned, parse_label_declaration)) >>
+        o: call!(spanned, parse_opcode) >>
+        operands: many0!(
+            terminated!(
+                call!(spanned, parse_operand),
+                opt!(preceded!(skip_trivia, tag!(",")))
+            )
+        ) >>
+        call!(skip_trivia) >>
+        (
+            {
+                let (label, label_span) = unzip_spanned(l);
+                let (opcode, head) = unzip_spanned(Some(o));
+                let (operands, operand_spans) = operands.into_iter().unzip();
+
+                AssemblerInstruction {
+                    opcode,
+                    label,
+                    directive: None,
+                    operands,
+                    spans: InstructionSpans {
+                        label: label_span,
+                        head,
+                        operands: operand_spans,
+                        ..InstructionSpans::default()
+                    },
+                    ..AssemblerInstruction::default()
+                }
+            }
+        )
+    )
+);
+
+named!(parse_label_only<CompleteStr, AssemblerInstruction>,
+    do_parse!(
+        l: call!(spanned, parse_label_declaration) >>
+        call!(skip_trivia) >>
         (
             AssemblerInstruction {
-                opcode: Some(o),
-                label: l,
-                directive: None,
-                operand1: o1,
-                operand2: o2,
-                operand3: o3
+                label: Some(l.0),
+                spans: InstructionSpans {
+                    label: l.1,
+                    ..InstructionSpans::default()
+                },
+                ..AssemblerInstruction::default()
             }
         )
     )
 );
 
+pub fn unzip_spanned(token: Option<(Token, Span)>) -> (Option<Token>, Span) {
+    match token {
+        Some((token, span)) => (Some(token), span),
+        None => (None, Span::default()),
+    }
+}
+
 #[allow(unused_imports)]
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::assembler::symbols::{Symbol, SymbolType};
+    use crate::assembler::Section;
     use crate::assembler::Token;
     use crate::instruction::Opcode;
 
@@ -108,11 +558,13 @@ mod tests {
                 CompleteStr(""),
                 AssemblerInstruction {
                     opcode: Some(Token::Op { code: Opcode::LOAD }),
-                    operand1: Some(Token::Register { reg_num: 0 }),
-                    operand2: Some(Token::IntegerOperand { value: 100 }),
-                    operand3: None,
+                    operands: vec![
+                        Token::Register { reg_num: 0 },
+                        Token::IntegerOperand { value: 100 }
+                    ],
                     label: None,
-                    directive: None
+                    directive: None,
+                    ..AssemblerInstruction::default()
                 }
             ))
         );
@@ -127,13 +579,126 @@ mod tests {
                 CompleteStr(""),
                 AssemblerInstruction {
                     opcode: Some(Token::Op { code: Opcode::HLT }),
-                    operand1: None,
-                    operand2: None,
-                    operand3: None,
+                    operands: vec![],
                     label: None,
-                    directive: None
+                    directive: None,
+                    ..AssemblerInstruction::default()
                 }
             ))
         )
     }
+
+    #[test]
+    fn test_label_usage_to_bytes() {
+        let (_, instruction) = parse_instruction_combined(CompleteStr("load $1 @end")).unwrap();
+        let mut symbols = SymbolTable::new();
+        let error = instruction.to_bytes(&symbols, 0).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UndefinedLabel);
+        symbols.add_symbol(Symbol::new(
+            "end".to_string(),
+            SymbolType::Label,
+            Section::Code,
+            0x0102,
+        ));
+        assert_eq!(
+            instruction.to_bytes(&symbols, 0),
+            Ok(vec![0x01, 0x01, 0x01, 0x02])
+        );
+        assert_eq!(instruction.encoded_len(&symbols, 0), 4);
+        assert_eq!(
+            instruction.label_fields(&symbols),
+            Ok(vec![("end".to_string(), 2, 2)])
+        );
+    }
+
+    #[test]
+    fn test_instruction_spans() {
+        let source = "test: loda $1 #10\n";
+        let (_, mut instruction) = parse_instruction(CompleteStr(source)).unwrap();
+        instruction.spans.resolve(source.len());
+        assert_eq!(instruction.spans.statement, Span::new(0, 17));
+        assert_eq!(instruction.spans.label, Span::new(0, 5));
+        assert_eq!(instruction.spans.head, Span::new(6, 10));
+        assert_eq!(instruction.spans.operands[1], Span::new(14, 17));
+
+        let error = instruction.to_bytes(&SymbolTable::new(), 0).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UnknownMnemonic);
+        assert_eq!(error.span, Span::new(6, 10));
+        assert_eq!(
+            error.message,
+            "unknown mnemonic `loda`, did you mean `load`?"
+        );
+    }
+
+    #[test]
+    fn test_operand_signatures() {
+        let symbols = SymbolTable::new();
+        let assemble = |source: &str| {
+            let (_, instruction) = parse_instruction(CompleteStr(source)).unwrap();
+            instruction.to_bytes(&symbols, 0)
+        };
+
+        assert_eq!(assemble("eq $1 $2"), Ok(vec![0x09, 0x01, 0x02, 0x00]));
+        assert_eq!(
+            assemble("add $0 #5").unwrap_err().kind,
+            ErrorKind::OperandCount
+        );
+        assert_eq!(
+            assemble("add $0 #5 $1").unwrap_err().kind,
+            ErrorKind::InvalidOperand
+        );
+        assert_eq!(
+            assemble("hlt $1 $2").unwrap_err().kind,
+            ErrorKind::OperandCount
+        );
+        assert_eq!(
+            assemble("load $1").unwrap_err().kind,
+            ErrorKind::OperandCount
+        );
+        assert_eq!(
+            assemble("load $100 #100").unwrap_err().kind,
+            ErrorKind::OutOfRange
+        );
+        assert_eq!(
+            assemble("load $1 #65536").unwrap_err().kind,
+            ErrorKind::OutOfRange
+        );
+        assert_eq!(
+            assemble("add $0 #5 $1").unwrap_err().message,
+            "expected a register, found `#5`"
+        );
+        assert_eq!(
+            assemble("hlt $1 $2").unwrap_err().message,
+            "`hlt` takes no operands, found 2"
+        );
+    }
+
+    #[test]
+    fn test_integer_literal_ranges() {
+        let symbols = SymbolTable::new();
+        let assemble = |source: &str| {
+            let (_, instruction) = parse_instruction(CompleteStr(source)).unwrap();
+            instruction.to_bytes(&symbols, 0)
+        };
+
+        assert_eq!(
+            assemble("load $1 #0xFFFF"),
+            Ok(vec![0x01, 0x01, 0xFF, 0xFF])
+        );
+        assert_eq!(assemble("load $1 #'A'"), Ok(vec![0x01, 0x01, 0x00, 0x41]));
+        assert_eq!(assemble("loads $1 #-2"), Ok(vec![0x12, 0x01, 0xFF, 0xFE]));
+        assert_eq!(
+            assemble("loads $1 #-0b1000_0000_0000_0000"),
+            Ok(vec![0x12, 0x01, 0x80, 0x00])
+        );
+        assert_eq!(
+            assemble("load $1 #-1").unwrap_err().message,
+            "`#-1` does not fit in a 16-bit immediate, which goes from 0 to 65535, \
+             use `loads` to load a negative value"
+        );
+        assert_eq!(
+            assemble("loads $1 #0x8000").unwrap_err().message,
+            "`#32768` does not fit in a signed 16-bit immediate, which goes from -32768 to 32767"
+        );
+    }
 }
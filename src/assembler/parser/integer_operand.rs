@@ -1,28 +1,86 @@
+use crate::assembler::expression::Expression;
+use crate::assembler::parser::expression::parse_expression;
+use crate::assembler::parser::label::{parse_label_usage, parse_parameter};
+use crate::assembler::parser::register::parse_register;
+use crate::assembler::parser::string_operand::unescape;
 use crate::assembler::Token;
-use nom::digit;
+use nom::anychar;
 use nom::types::CompleteStr;
-use crate::assembler::parser::register::parse_register;
 
+// An immediate: `#` followed by an integer, or by an expression evaluated once
+// the labels and constants it uses are known, as in `#BUF_SIZE*2`.
 named!(pub parse_integer_operand<CompleteStr, Token>,
-    ws!(
+    token!(
         do_parse!(
             tag!("#") >>
-            reg_num: digit >>
+            expr: parse_expression >>
             (
-                Token::IntegerOperand { value: reg_num.parse::<i32>().unwrap()}
+                match expr {
+                    Expression::Integer(value) => Token::IntegerOperand { value },
+                    expr => Token::Expression { expr },
+                }
             )
         )
     )
 );
 
+// An integer written as an optional `-`, then decimal digits, digits after a
+// `0x`, `0b` or `0o` prefix, or a character in single quotes. Digits can be
+// grouped with underscores, as in `1_000`. Whether the value fits is up to
+// whatever the literal ends up encoded as.
+named!(pub parse_integer_literal<CompleteStr, i64>,
+    do_parse!(
+        sign: opt!(tag!("-")) >>
+        magnitude: alt!(
+            preceded!(tag_no_case!("0x"), call!(parse_digits, 16)) |
+            preceded!(tag_no_case!("0b"), call!(parse_digits, 2)) |
+            preceded!(tag_no_case!("0o"), call!(parse_digits, 8)) |
+            parse_char_literal |
+            call!(parse_digits, 10)
+        ) >>
+        (
+            if sign.is_some() { -magnitude } else { magnitude }
+        )
+    )
+);
+
+named_args!(parse_digits(radix: u32)<CompleteStr, i64>,
+    map_res!(
+        recognize!(
+            pair!(
+                verify!(anychar, |c: char| c.is_digit(radix)),
+                take_while!(|c: char| c.is_digit(radix) || c == '_')
+            )
+        ),
+        |d: CompleteStr| i64::from_str_radix(&d.replace('_', ""), radix)
+    )
+);
+
+named!(parse_char_literal<CompleteStr, i64>,
+    delimited!(
+        tag!("'"),
+        map!(
+            alt!(
+                preceded!(tag!("\\"), map_opt!(anychar, unescape)) |
+                none_of!("\\'")
+            ),
+            |c: char| i64::from(u32::from(c))
+        ),
+        tag!("'")
+    )
+);
+
 named!(pub parse_operand<CompleteStr, Token>,
     alt!(
         parse_integer_operand |
-        parse_register
+        parse_register |
+        parse_label_usage |
+        parse_parameter
     )
 );
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     #[allow(unused_imports)]
     use super::*;
@@ -37,5 +95,56 @@ mod tests {
 
         let result = parse_integer_operand(CompleteStr("10"));
         assert_eq!(result.is_ok(), false);
+
+        let result = parse_integer_operand(CompleteStr("#SIZE*2"));
+        assert_eq!(
+            result.map(|(_, token)| token.to_string()),
+            Ok("#SIZE*2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_integer_literal() {
+        let cases = [
+            ("-1", -1),
+            ("0xFF", 255),
+            ("0Xff", 255),
+            ("-0x8000", -32768),
+            ("0b1010", 10),
+            ("0o17", 15),
+            ("1_000", 1000),
+            ("0xFFFF_FFFF", 0xFFFF_FFFF),
+            ("'A'", 65),
+            ("'\\n'", 10),
+            ("'\\''", 39),
+        ];
+
+        for (literal, value) in cases.iter() {
+            assert_eq!(
+                parse_integer_literal(CompleteStr(literal)),
+                Ok((CompleteStr(""), *value)),
+                "{}",
+                literal
+            );
+        }
+
+        assert!(parse_integer_literal(CompleteStr("_1")).is_err());
+        assert!(parse_integer_literal(CompleteStr("''")).is_err());
+        assert!(parse_integer_literal(CompleteStr("'\\q'")).is_err());
+        assert!(parse_integer_literal(CompleteStr("99999999999999999999")).is_err());
+    }
+
+    #[test]
+    fn test_parse_operand() {
+        let result = parse_operand(CompleteStr("@loop"));
+        assert_eq!(
+            result,
+            Ok((
+                CompleteStr(""),
+                Token::LabelUsabe {
+                    name: "loop".to_string()
+                }
+            ))
+        );
     }
 }
@@ -1,13 +1,17 @@
 use crate::assembler::Token;
+use nom::alphanumeric;
 use nom::types::CompleteStr;
-use nom::{alphanumeric, multispace};
+
+// `1` and `1f` are numeric labels, `.loop` belongs to the label before it.
+named!(label_name<CompleteStr, CompleteStr>,
+    recognize!(pair!(opt!(tag!(".")), alphanumeric))
+);
 
 named!(pub parse_label_declaration<CompleteStr, Token>,
-    ws!(
+    token!(
         do_parse!(
-            name: alphanumeric >>
+            name: label_name >>
             tag!(":") >>
-            opt!(multispace) >>
             (
                 Token::LabelDeclaration { name: name.to_string() }
             )
@@ -16,11 +20,10 @@ named!(pub parse_label_declaration<CompleteStr, Token>,
 );
 
 named!(pub parse_label_usage<CompleteStr, Token>,
-    ws!(
+    token!(
         do_parse!(
             tag!("@") >>
-            name: alphanumeric >>
-            opt!(multispace) >>
+            name: label_name >>
             (
                 Token::LabelUsabe { name: name.to_string() }
             )
@@ -28,7 +31,31 @@ named!(pub parse_label_usage<CompleteStr, Token>,
     )
 );
 
+named!(pub parse_identifier<CompleteStr, Token>,
+    token!(
+        do_parse!(
+            name: alphanumeric >>
+            (
+                Token::Identifier { name: name.to_string() }
+            )
+        )
+    )
+);
+
+named!(pub parse_parameter<CompleteStr, Token>,
+    token!(
+        do_parse!(
+            tag!("\\") >>
+            name: alphanumeric >>
+            (
+                Token::Parameter { name: name.to_string() }
+            )
+        )
+    )
+);
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     #[allow(unused_imports)]
     use super::*;
@@ -46,5 +73,31 @@ mod tests {
         );
         let result = parse_label_declaration(CompleteStr("test"));
         assert_eq!(result.is_err(), true);
+
+        for name in &["1", ".loop"] {
+            let source = format!("{}:", name);
+            let result = parse_label_declaration(CompleteStr(&source));
+            assert_eq!(
+                result.map(|(_, token)| token),
+                Ok(Token::LabelDeclaration {
+                    name: name.to_string()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_label_usage() {
+        for name in &["loop", "1f", "2b", ".loop"] {
+            let source = format!("@{}", name);
+            let result = parse_label_usage(CompleteStr(&source));
+            assert_eq!(
+                result.map(|(_, token)| token),
+                Ok(Token::LabelUsabe {
+                    name: name.to_string()
+                })
+            );
+        }
+        assert!(parse_label_usage(CompleteStr("@.")).is_err());
     }
 }
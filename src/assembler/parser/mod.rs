@@ -1,7 +1,40 @@
+use crate::assembler::span::Span;
+use crate::assembler::Token;
+use nom::types::CompleteStr;
+use nom::IResult;
+
+/// Skips leading whitespace and comments, then runs the token parser. This is
+/// the comment-aware stand-in for `ws!` used by every token parser; trailing
+/// trivia is left to the next token so that spans stay tight.
+macro_rules! token (
+    ($i:expr, $submac:ident!( $($args:tt)* )) => (
+        preceded!(
+            $i,
+            call!($crate::assembler::parser::trivia::skip_trivia),
+            $submac!($($args)*)
+        )
+    );
+);
+
 pub mod directive;
+pub mod expression;
 pub mod instruction;
 pub mod integer_operand;
 pub mod label;
 pub mod opcode;
 pub mod program;
 pub mod register;
+pub mod string_operand;
+pub mod trivia;
+
+/// Runs `parser` and records the span of the token it produced, leaving out
+/// the whitespace and comments in front of it.
+pub fn spanned(
+    input: CompleteStr,
+    parser: fn(CompleteStr) -> IResult<CompleteStr, Token>,
+) -> IResult<CompleteStr, (Token, Span)> {
+    let (start, _) = trivia::skip_trivia(input)?;
+    let (rest, token) = parser(start)?;
+
+    Ok((rest, (token, Span::from_remaining(start.len(), rest.len()))))
+}
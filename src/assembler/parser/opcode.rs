@@ -1,20 +1,36 @@
 use crate::assembler::Token;
 use crate::instruction::Opcode;
-use nom::alpha1;
 use nom::types::CompleteStr;
 
+// Letters, digits and underscores, not starting with a digit, like the names
+// `.macro` declares, so every macro can be invoked.
+named!(mnemonic<CompleteStr, CompleteStr>,
+    recognize!(
+        pair!(
+            take_while1!(|c: char| c.is_ascii_alphabetic() || c == '_'),
+            take_while!(|c: char| c.is_ascii_alphanumeric() || c == '_')
+        )
+    )
+);
+
 named!(pub parse_opcode<CompleteStr, Token>,
-    do_parse!(
-        opcode: alpha1 >>
-        (
-            {
-                Token::Op{code: Opcode::from(opcode)}
-            }
+    token!(
+        do_parse!(
+            opcode: mnemonic >>
+            (
+                {
+                    match Opcode::from(opcode) {
+                        Opcode::IGL(_) => Token::Mnemonic { name: opcode.to_string() },
+                        code => Token::Op { code },
+                    }
+                }
+            )
         )
     )
 );
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     #[allow(unused_imports)]
     use super::*;
@@ -32,9 +48,20 @@ mod tests {
         let (_, token) = result.unwrap();
         assert_eq!(
             token,
-            Token::Op {
-                code: Opcode::IGL(0xFF)
+            Token::Mnemonic {
+                name: "loda".to_string()
             }
-        )
+        );
+
+        let result = parse_opcode(CompleteStr("swap_2 $1"));
+        let (rest, token) = result.unwrap();
+        assert_eq!(
+            token,
+            Token::Mnemonic {
+                name: "swap_2".to_string()
+            }
+        );
+        assert_eq!(rest, CompleteStr(" $1"));
+        assert!(parse_opcode(CompleteStr("2x")).is_err());
     }
 }
@@ -1,43 +1,310 @@
 use super::instruction::{parse_instruction, AssemblerInstruction};
-use nom::many1;
+use super::trivia::{parse_trivia, skip_trivia, Comment};
+use crate::assembler::assembler_errors::{AssemblerError, ErrorKind};
+use crate::assembler::expression::{Expression, Scope};
+use crate::assembler::span::Span;
+use crate::assembler::symbols::{SymbolTable, SymbolType};
+use crate::assembler::{Section, Token};
+use crate::binary::Binary;
+use crate::object::{Binding, ObjectFile, ObjectSymbol, Relocation};
 use nom::types::CompleteStr;
+use nom::IResult;
+use std::mem;
 
 #[derive(Debug, PartialEq)]
 pub struct Program {
-    instructions: Vec<AssemblerInstruction>,
+    pub instructions: Vec<AssemblerInstruction>,
+    /// Comments after the last instruction.
+    pub trailing_comments: Vec<Comment>,
 }
 
 impl Program {
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut program = vec![];
+    /// Encodes every statement into the section selected by the last `.code`
+    /// or `.data` directive before it, starting in the code section.
+    pub fn to_binary(&self, symbols: &SymbolTable) -> Result<Binary, Vec<AssemblerError>> {
+        let mut binary = Binary::default();
+        let mut section = Section::Code;
+        let mut errors = vec![];
 
         for instruction in &self.instructions {
-            program.append(&mut instruction.to_bytes());
+            section = instruction.section().unwrap_or(section);
+
+            let output = match section {
+                Section::Code => &mut binary.code,
+                Section::Data => &mut binary.ro_data,
+            };
+
+            match instruction.to_bytes(symbols, output.len() as u32) {
+                Ok(mut bytes) => output.append(&mut bytes),
+                Err(e) => errors.push(instruction.in_context(e)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(binary)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Encodes the program into a relocatable object file, with a relocation
+    /// for every operand that depends on a label so the linker can move the
+    /// sections around and fill in the labels declared `.extern`.
+    pub fn to_object(&self, symbols: &SymbolTable) -> Result<ObjectFile, Vec<AssemblerError>> {
+        let binary = self.to_binary(symbols)?;
+        let mut object = ObjectFile {
+            code: binary.code,
+            ro_data: binary.ro_data,
+            ..ObjectFile::default()
+        };
+
+        // Constants are gone once the program is encoded, so relocations refer
+        // to labels by their position among the labels only.
+        let labels: Vec<&str> = symbols
+            .symbols()
+            .iter()
+            .filter_map(|symbol| {
+                let binding = match symbol.symbol_type {
+                    SymbolType::Extern => Binding::Extern,
+                    SymbolType::Label if symbol.global => Binding::Global,
+                    SymbolType::Label => Binding::Local,
+                    SymbolType::Constant(_) => return None,
+                };
+
+                object.symbols.push(ObjectSymbol {
+                    name: symbol.name.clone(),
+                    binding,
+                    section: symbol.section,
+                    offset: symbol.offset,
+                });
+
+                Some(symbol.name.as_str())
+            })
+            .collect();
+        let mut errors = vec![];
+        let mut section = Section::Code;
+        let (mut code_offset, mut data_offset) = (0, 0);
+
+        for instruction in &self.instructions {
+            section = instruction.section().unwrap_or(section);
+
+            let (offset, align) = match section {
+                Section::Code => (&mut code_offset, &mut object.code_align),
+                Section::Data => (&mut data_offset, &mut object.ro_data_align),
+            };
+
+            if let (Some(Token::Directive { name }), [operand]) =
+                (&instruction.directive, &instruction.operands[..])
+            {
+                let value = Expression::from_token(operand)
+                    .and_then(|expr| expr.evaluate(Scope::Complete(symbols)).ok());
+
+                if let (true, Some(value)) = (name == "align", value) {
+                    *align = (*align).max(value as u32);
+                }
+            }
+
+            match instruction.label_fields(symbols) {
+                Ok(fields) => {
+                    for (name, field, size) in fields {
+                        // `to_binary` has made sure every label used is declared.
+                        if let Some(index) = labels.iter().position(|label| *label == name) {
+                            object.relocations.push(Relocation {
+                                section,
+                                offset: *offset + field,
+                                size: size as u8,
+                                symbol: index as u32,
+                            });
+                        }
+                    }
+                }
+                Err(e) => errors.push(instruction.in_context(e)),
+            }
+
+            *offset += instruction.encoded_len(symbols, *offset);
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
         }
 
-        program
+        Ok(object)
+    }
+
+    /// Bytecode of the code section.
+    pub fn to_bytes(&self, symbols: &SymbolTable) -> Result<Vec<u8>, Vec<AssemblerError>> {
+        self.to_binary(symbols).map(|binary| binary.code)
+    }
+
+    /// Builds a program out of freshly parsed instructions, whose spans are
+    /// still counted from the end of `source`: comments are handed to the
+    /// instructions they belong to and every span is resolved.
+    fn new(source: &str, mut instructions: Vec<AssemblerInstruction>) -> Program {
+        let mut trailing_comments = attach_comments(source, &mut instructions);
+
+        for instruction in &mut instructions {
+            instruction.resolve_spans(source.len());
+        }
+
+        for comment in &mut trailing_comments {
+            comment.span.resolve(source.len());
+        }
+
+        Program {
+            instructions,
+            trailing_comments,
+        }
+    }
+}
+
+pub fn parse_program(input: CompleteStr) -> IResult<CompleteStr, Program> {
+    let (rest, instructions) = parse_instructions(input)?;
+
+    Ok((rest, Program::new(&input, instructions)))
+}
+
+/// Parses a whole source file statement by statement. A statement that fails
+/// to parse is reported and skipped up to the end of its line, so every
+/// syntax error in the file is collected rather than only the first one.
+pub fn parse_source(source: &str) -> (Program, Vec<AssemblerError>) {
+    let mut instructions = vec![];
+    let mut errors = vec![];
+    let mut input = CompleteStr(source);
+
+    loop {
+        if let Ok((rest, _)) = skip_trivia(input) {
+            input = rest;
+        }
+
+        if input.is_empty() {
+            break;
+        }
+
+        let start = source.len() - input.len();
+        let line_end = input.find('\n').unwrap_or(input.len());
+
+        if input.starts_with("/*") {
+            errors.push(AssemblerError::new(
+                ErrorKind::Syntax,
+                Span::new(start, start + 2),
+                "unterminated block comment".to_string(),
+            ));
+            break;
+        }
+
+        match parse_instruction(input) {
+            Ok((rest, mut instruction)) => {
+                // Spans are still counted from the end of the source here.
+                let end = source.len() - instruction.spans.statement.end;
+
+                if ends_line(source, end) {
+                    instructions.push(instruction);
+                    input = rest;
+                    continue;
+                }
+
+                instruction.unparsed_operands = true;
+                instructions.push(instruction);
+
+                let junk_start = source.len() - rest.len();
+                let junk_len = rest.find('\n').unwrap_or(rest.len());
+                let junk = rest[..junk_len].trim_end();
+
+                errors.push(AssemblerError::new(
+                    ErrorKind::Syntax,
+                    Span::new(junk_start, junk_start + junk.len()),
+                    format!("unexpected `{}`", junk),
+                ));
+                input = CompleteStr(&rest[junk_len..]);
+            }
+            Err(_) => {
+                let line = input[..line_end].trim_end();
+
+                errors.push(AssemblerError::new(
+                    ErrorKind::Syntax,
+                    Span::new(start, start + line.len()),
+                    format!(
+                        "expected an instruction, a directive or a label, found `{}`",
+                        line
+                    ),
+                ));
+                input = CompleteStr(&input[line_end..]);
+            }
+        }
     }
+
+    (Program::new(source, instructions), errors)
 }
 
-named!(pub parse_program<CompleteStr, Program>,
+named!(parse_instructions<CompleteStr, Vec<AssemblerInstruction>>,
     do_parse!(
-        instructions: many1!(parse_instruction) >>
+        instructions: many1!(preceded!(skip_trivia, parse_instruction)) >>
+        call!(skip_trivia) >>
         (
-            Program {
-                instructions
-            }
+            instructions
         )
     )
 );
 
+fn trivia(input: &str) -> (CompleteStr<'_>, Vec<Comment>) {
+    parse_trivia(CompleteStr(input)).unwrap_or((CompleteStr(input), vec![]))
+}
+
+/// Whether a line break follows the statement ending at `end`, comments aside.
+fn ends_line(source: &str, end: usize) -> bool {
+    let (rest, comments) = trivia(&source[end..]);
+    let gap_end = source.len() - rest.len();
+    let in_comment = |offset: usize| {
+        comments.iter().any(|comment| {
+            source.len() - comment.span.start <= offset && offset < source.len() - comment.span.end
+        })
+    };
+
+    rest.is_empty()
+        || source[end..gap_end]
+            .char_indices()
+            .any(|(i, c)| c == '\n' && !in_comment(end + i))
+}
+
+/// Hands every comment to an instruction: comments on the same line as an
+/// instruction trail it, the others lead the next one. Returns the comments
+/// that come after the last instruction.
+fn attach_comments(source: &str, instructions: &mut [AssemblerInstruction]) -> Vec<Comment> {
+    let (_, mut pending) = trivia(source);
+
+    for instruction in instructions.iter_mut() {
+        instruction.leading_comments = mem::take(&mut pending);
+
+        let end = source.len() - instruction.spans.statement.end;
+
+        for span in instruction.token_spans() {
+            let (_, comments) = trivia(&source[source.len() - span.end..]);
+
+            for comment in comments {
+                let start = source.len() - comment.span.start;
+
+                if start < end || !source[end..start].contains('\n') {
+                    instruction.trailing_comments.push(comment);
+                } else {
+                    pending.push(comment);
+                }
+            }
+        }
+    }
+
+    pending
+}
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test {
     #[allow(unused_imports)]
     use super::*;
+    use crate::assembler::symbols::{Symbol, SymbolType};
 
     #[test]
     fn test_parse_program() {
-        let result = parse_program(CompleteStr("load $100 #100\n"));
+        let result = parse_program(CompleteStr("load $1 #100\n"));
         assert_eq!(result.is_ok(), true);
         let (leftover, prog) = result.unwrap();
         assert_eq!(leftover, CompleteStr(""));
@@ -49,7 +316,122 @@ mod test {
         let result = parse_program(CompleteStr("load $0 #100\n"));
         assert_eq!(result.is_ok(), true);
         let (_, program) = result.unwrap();
-        let bytecode = program.to_bytes();
+        let bytecode = program.to_bytes(&SymbolTable::new()).unwrap();
         assert_eq!(bytecode.len(), 4);
     }
+
+    #[test]
+    fn test_program_to_binary() {
+        let source = ".data\nanswer: .byte 42\n.code\nload $0 @answer\n.data\n.byte 7\n";
+        let (_, program) = parse_program(CompleteStr(source)).unwrap();
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol(Symbol::new(
+            "answer".to_string(),
+            SymbolType::Label,
+            Section::Data,
+            0,
+        ));
+        let binary = program.to_binary(&symbols).unwrap();
+        assert_eq!(binary.code, vec![0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(binary.ro_data, vec![42, 7]);
+    }
+
+    #[test]
+    fn test_program_to_object() {
+        let source = "load $0 @msg\nload $1 @print\n.data\n.align 4\nmsg: .byte 1 @msg\n";
+        let (_, program) = parse_program(CompleteStr(source)).unwrap();
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol(Symbol::new(
+            "msg".to_string(),
+            SymbolType::Label,
+            Section::Data,
+            0,
+        ));
+        symbols.add_symbol(Symbol::new(
+            "print".to_string(),
+            SymbolType::Extern,
+            Section::Code,
+            0,
+        ));
+        let object = program.to_object(&symbols).unwrap();
+        assert_eq!(object.code.len(), 8);
+        assert_eq!(object.ro_data, vec![1, 0]);
+        assert_eq!(object.ro_data_align, 4);
+        assert_eq!(object.symbols[1].binding, Binding::Extern);
+        let relocations: Vec<(Section, u32, u8, u32)> = object
+            .relocations
+            .iter()
+            .map(|r| (r.section, r.offset, r.size, r.symbol))
+            .collect();
+        assert_eq!(
+            relocations,
+            vec![
+                (Section::Code, 2, 2, 0),
+                (Section::Code, 6, 2, 1),
+                (Section::Data, 1, 1, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_multiline_program() {
+        let result = parse_program(CompleteStr("load $0 #100\nhlt\nstart:\nadd $0 $0 $1\n"));
+        let (leftover, program) = result.unwrap();
+        assert_eq!(leftover, CompleteStr(""));
+        assert_eq!(3, program.instructions.len());
+        assert_eq!(program.instructions[2].label_name(), Some("start"));
+    }
+
+    #[test]
+    fn test_parse_comments() {
+        let source = "; header\nload $0 #1 ; one\n// before hlt\nhlt /* two */\nstart: /* inner */ add $0 $0 $1\n; end\n";
+        let (leftover, program) = parse_program(CompleteStr(source)).unwrap();
+        assert_eq!(leftover, CompleteStr(""));
+        assert_eq!(program.instructions.len(), 3);
+
+        let text = |comments: &[Comment]| -> Vec<String> {
+            comments.iter().map(|c| c.text.clone()).collect()
+        };
+        let instructions = &program.instructions;
+        assert_eq!(text(&instructions[0].leading_comments), vec!["; header"]);
+        assert_eq!(text(&instructions[0].trailing_comments), vec!["; one"]);
+        assert_eq!(
+            text(&instructions[1].leading_comments),
+            vec!["// before hlt"]
+        );
+        assert_eq!(text(&instructions[1].trailing_comments), vec!["/* two */"]);
+        assert_eq!(
+            text(&instructions[2].trailing_comments),
+            vec!["/* inner */"]
+        );
+        assert_eq!(text(&program.trailing_comments), vec!["; end"]);
+
+        let span = program.trailing_comments[0].span;
+        assert_eq!(&source[span.start..span.end], "; end");
+    }
+
+    #[test]
+    fn test_parse_source_comments() {
+        let source = "load $0 #1 /* block\ncomment */ hlt\n.code ; directive\n/* open";
+        let (program, errors) = parse_source(source);
+        assert_eq!(program.instructions.len(), 2);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].message, "unexpected `hlt`");
+        assert_eq!(errors[1].message, "unterminated block comment");
+        assert_eq!(
+            program.instructions[1].trailing_comments[0].text,
+            "; directive"
+        );
+    }
+
+    #[test]
+    fn test_parse_source_collects_errors() {
+        let source = "load $0 #1\nload $1 #1+\n!!!\nhlt\n";
+        let (program, errors) = parse_source(source);
+        assert_eq!(program.instructions.len(), 3);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].kind, ErrorKind::Syntax);
+        assert_eq!(&source[errors[0].span.start..errors[0].span.end], "#1+");
+        assert_eq!(&source[errors[1].span.start..errors[1].span.end], "!!!");
+    }
 }
@@ -1,22 +1,62 @@
+//! Registers are written `$0` to `$31`, by the conventional name of their
+//! role in the calling convention below, or by an alias declared with
+//! `.alias name $N`. The VM itself treats every register alike: the roles are
+//! an agreement between the pieces of a program.
+//!
+//! | register   | name          | role                                        |
+//! |------------|---------------|---------------------------------------------|
+//! | `$0`       | `$zero`       | zero, though the VM doesn't enforce it      |
+//! | `$1`       | `$at`         | scratch for the assembler                   |
+//! | `$2`-`$3`  | `$v0`-`$v1`   | return values                               |
+//! | `$4`-`$7`  | `$a0`-`$a3`   | arguments                                   |
+//! | `$8`-`$15` | `$t0`-`$t7`   | temporaries, not preserved across calls     |
+//! | `$16`-`$23`| `$s0`-`$s7`   | saved, preserved across calls by the callee |
+//! | `$24`-`$25`| `$t8`-`$t9`   | more temporaries                            |
+//! | `$26`-`$27`| `$k0`-`$k1`   | reserved for the VM                         |
+//! | `$28`      | `$gp`         | global pointer                              |
+//! | `$29`      | `$sp`         | stack pointer                               |
+//! | `$30`      | `$fp`         | frame pointer                               |
+//! | `$31`      | `$ra`         | return address                              |
+
 use crate::assembler::Token;
-use nom::digit;
+use crate::vm::REGISTER_COUNT;
 use nom::types::CompleteStr;
+use nom::{alphanumeric, digit};
+
+/// Conventional name of every register, without the `$`.
+pub const REGISTER_NAMES: [&str; REGISTER_COUNT] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6",
+    "t7", "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "fp",
+    "ra",
+];
+
+/// The register with the conventional name `name`, given without the `$`.
+pub fn register_number(name: &str) -> Option<u8> {
+    REGISTER_NAMES
+        .iter()
+        .position(|register| *register == name)
+        .map(|number| number as u8)
+}
 
+// Any other name is taken for an alias, looked up once the `.alias` directives
+// before it are known.
 named!(pub parse_register<CompleteStr, Token>,
-    ws!(
-        do_parse!(
-            tag!("$") >>
-            reg_num: digit >>
-            (
-                Token::Register{
-                    reg_num: reg_num.parse::<u8>().unwrap()
-                }
+    token!(
+        preceded!(
+            tag!("$"),
+            alt!(
+                map_res!(digit, |d: CompleteStr| d.parse::<u8>().map(|reg_num| Token::Register { reg_num })) |
+                map!(alphanumeric, |name: CompleteStr| match register_number(&name) {
+                    Some(reg_num) => Token::Register { reg_num },
+                    None => Token::RegisterAlias { name: name.to_string() },
+                })
             )
         )
     )
 );
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     #[allow(unused_imports)]
     use super::*;
@@ -27,7 +67,27 @@ mod tests {
         assert_eq!(result.is_ok(), true);
         let result = parse_register(CompleteStr("0"));
         assert_eq!(result.is_ok(), false);
-        let result = parse_register(CompleteStr("$a"));
+        let result = parse_register(CompleteStr("$"));
         assert_eq!(result.is_ok(), false);
     }
+
+    #[test]
+    fn test_parse_register_names() {
+        let register = |source: &'static str| {
+            parse_register(CompleteStr(source))
+                .map(|(_, token)| token)
+                .ok()
+        };
+
+        assert_eq!(register("$zero"), Some(Token::Register { reg_num: 0 }));
+        assert_eq!(register("$a3"), Some(Token::Register { reg_num: 7 }));
+        assert_eq!(register("$sp"), Some(Token::Register { reg_num: 29 }));
+        assert_eq!(register("$ra"), Some(Token::Register { reg_num: 31 }));
+        assert_eq!(
+            register("$counter"),
+            Some(Token::RegisterAlias {
+                name: "counter".to_string()
+            })
+        );
+    }
 }
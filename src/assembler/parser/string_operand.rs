@@ -0,0 +1,56 @@
+use crate::assembler::Token;
+use nom::anychar;
+use nom::types::CompleteStr;
+
+named!(pub parse_string_operand<CompleteStr, Token>,
+    token!(
+        do_parse!(
+            tag!("\"") >>
+            chars: many0!(
+                alt!(
+                    preceded!(tag!("\\"), map_opt!(anychar, unescape)) |
+                    none_of!("\\\"\n")
+                )
+            ) >>
+            tag!("\"") >>
+            (
+                Token::StringOperand { value: chars.into_iter().collect() }
+            )
+        )
+    )
+);
+
+/// The character an escape sequence such as `\n` stands for, shared by string
+/// and character literals.
+pub fn unescape(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        '\\' | '\'' | '"' => Some(c),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_string_operand() {
+        let result = parse_string_operand(CompleteStr(r#" "Hello,\t\"world\"\n" "#));
+        assert_eq!(
+            result,
+            Ok((
+                CompleteStr(" "),
+                Token::StringOperand {
+                    value: "Hello,\t\"world\"\n".to_string()
+                }
+            ))
+        );
+
+        assert!(parse_string_operand(CompleteStr(r#""\q""#)).is_err());
+        assert!(parse_string_operand(CompleteStr("\"open\nline\"")).is_err());
+    }
+}
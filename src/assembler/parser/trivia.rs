@@ -0,0 +1,96 @@
+use crate::assembler::span::Span;
+use nom::types::CompleteStr;
+use nom::{ErrorKind, IResult};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommentKind {
+    /// `; comment` or `// comment`, running to the end of the line.
+    Line,
+    /// `/* comment */`, possibly spanning several lines.
+    Block,
+}
+
+/// A comment kept alongside the instructions so that tools can reproduce the
+/// source, `text` includes the comment delimiters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub kind: CommentKind,
+    pub text: String,
+    pub span: Span,
+}
+
+/// Consumes whitespace and comments, returning the comments in source order.
+/// An unterminated block comment is left in the input for the caller to report.
+pub fn parse_trivia(input: CompleteStr) -> IResult<CompleteStr, Vec<Comment>> {
+    let mut comments = vec![];
+    let mut rest = CompleteStr(input.trim_start());
+
+    loop {
+        let (kind, len) = if rest.starts_with(';') || rest.starts_with("//") {
+            (CommentKind::Line, rest.find('\n').unwrap_or(rest.len()))
+        } else if let Some(body) = rest.strip_prefix("/*") {
+            match body.find("*/") {
+                Some(end) => (CommentKind::Block, end + 4),
+                None => break,
+            }
+        } else {
+            break;
+        };
+
+        comments.push(Comment {
+            kind,
+            text: rest[..len].trim_end().to_string(),
+            span: Span::from_remaining(rest.len(), rest.len() - rest[..len].trim_end().len()),
+        });
+        rest = CompleteStr(rest[len..].trim_start());
+    }
+
+    Ok((rest, comments))
+}
+
+/// `parse_trivia` for separators, where the comments themselves are not needed.
+pub fn skip_trivia(input: CompleteStr) -> IResult<CompleteStr, ()> {
+    let (rest, _) = parse_trivia(input)?;
+
+    Ok((rest, ()))
+}
+
+/// Succeeds, consuming nothing, when the next token is on the current line.
+pub fn same_line(input: CompleteStr) -> IResult<CompleteStr, ()> {
+    let (rest, _) = skip_trivia(input)?;
+
+    if input[..input.len() - rest.len()].contains('\n') {
+        return Err(nom::Err::Error(error_position!(
+            input,
+            ErrorKind::Custom(0)
+        )));
+    }
+
+    Ok((input, ()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_trivia() {
+        let source = "  ; one\n // two\n/* three\n */ hlt";
+        let (rest, comments) = parse_trivia(CompleteStr(source)).unwrap();
+        assert_eq!(rest, CompleteStr("hlt"));
+        let text: Vec<&str> = comments.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(text, vec!["; one", "// two", "/* three\n */"]);
+        assert_eq!(comments[2].kind, CommentKind::Block);
+
+        let mut span = comments[0].span;
+        span.resolve(source.len());
+        assert_eq!(span, Span::new(2, 7));
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let (rest, comments) = parse_trivia(CompleteStr(" /* open")).unwrap();
+        assert_eq!(rest, CompleteStr("/* open"));
+        assert!(comments.is_empty());
+    }
+}
@@ -0,0 +1,346 @@
+//! Pseudo-instructions: mnemonics the VM has no opcode for, which the
+//! assembler replaces with a short sequence of real instructions.
+//!
+//! | pseudo-instruction  | expands to                                 |
+//! |---------------------|--------------------------------------------|
+//! | `nop`               | `load $at #0`                              |
+//! | `mov $d $s`         | `load $at #0`, `add $s $at $d`             |
+//! | `inc $r`            | `load $at #1`, `add $r $at $r`             |
+//! | `dec $r`            | `load $at #1`, `sub $r $at $r`             |
+//! | `jmp @label`        | `load $at @label`, `jmp $at`               |
+//! | `beq $a $b @label`  | `eq $a $b`, `load $at @label`, `jeq $at`   |
+//! | `bne $a $b @label`  | `eq $a $b`, `load $at @label`, `jneq $at`  |
+//! | `call $r`           | `callr $r`                                 |
+//!
+//! Expansions overwrite `$at`, as the calling convention in `register` has it,
+//! so `mov`, `inc` and `dec` can't read `$at` itself. `jmp` with a register
+//! operand and `call` with a label or an integer are the real opcodes. The
+//! instructions of an expansion keep the spans of the pseudo-instruction, so
+//! errors, listings and debug information point at it.
+
+use crate::assembler::assembler_errors::{AssemblerError, ErrorKind};
+use crate::assembler::parser::instruction::{
+    describe_operands, AssemblerInstruction, InstructionSpans,
+};
+use crate::assembler::parser::program::Program;
+use crate::assembler::parser::register::register_number;
+use crate::assembler::Token;
+use crate::instruction::Opcode::{self, *};
+use crate::instruction::OperandKind::{self, Immediate, Register};
+use std::mem;
+
+/// An operand of an instruction in an expansion.
+enum Arg {
+    /// The operand of the pseudo-instruction at this index.
+    Operand(usize),
+    /// A fixed register, by its conventional name.
+    Fixed(&'static str),
+    Integer(i64),
+}
+
+use self::Arg::*;
+
+struct PseudoInstruction {
+    name: &'static str,
+    operands: &'static [OperandKind],
+    expansion: &'static [(Opcode, &'static [Arg])],
+}
+
+const PSEUDO_INSTRUCTIONS: &[PseudoInstruction] = &[
+    PseudoInstruction {
+        name: "nop",
+        operands: &[],
+        expansion: &[(LOAD, &[Fixed("at"), Integer(0)])],
+    },
+    PseudoInstruction {
+        name: "mov",
+        operands: &[Register, Register],
+        expansion: &[
+            (LOAD, &[Fixed("at"), Integer(0)]),
+            (ADD, &[Operand(1), Fixed("at"), Operand(0)]),
+        ],
+    },
+    PseudoInstruction {
+        name: "inc",
+        operands: &[Register],
+        expansion: &[
+            (LOAD, &[Fixed("at"), Integer(1)]),
+            (ADD, &[Operand(0), Fixed("at"), Operand(0)]),
+        ],
+    },
+    PseudoInstruction {
+        name: "dec",
+        operands: &[Register],
+        expansion: &[
+            (LOAD, &[Fixed("at"), Integer(1)]),
+            (SUB, &[Operand(0), Fixed("at"), Operand(0)]),
+        ],
+    },
+    PseudoInstruction {
+        name: "jmp",
+        operands: &[Immediate],
+        expansion: &[(LOAD, &[Fixed("at"), Operand(0)]), (JMP, &[Fixed("at")])],
+    },
+    PseudoInstruction {
+        name: "beq",
+        operands: &[Register, Register, Immediate],
+        expansion: &[
+            (EQ, &[Operand(0), Operand(1)]),
+            (LOAD, &[Fixed("at"), Operand(2)]),
+            (JEQ, &[Fixed("at")]),
+        ],
+    },
+    PseudoInstruction {
+        name: "bne",
+        operands: &[Register, Register, Immediate],
+        expansion: &[
+            (EQ, &[Operand(0), Operand(1)]),
+            (LOAD, &[Fixed("at"), Operand(2)]),
+            (JNEQ, &[Fixed("at")]),
+        ],
+    },
+    PseudoInstruction {
+        name: "call",
+        operands: &[Register],
+        expansion: &[(CALLR, &[Operand(0)])],
+    },
+];
+
+/// Replaces every pseudo-instruction in `program` with its expansion.
+pub fn expand_pseudo_instructions(program: &mut Program) -> Vec<AssemblerError> {
+    let mut errors = vec![];
+    let mut result = vec![];
+
+    for instruction in mem::take(&mut program.instructions) {
+        let pseudo = match pseudo_instruction(&instruction) {
+            Some(pseudo) => pseudo,
+            None => {
+                result.push(instruction);
+                continue;
+            }
+        };
+
+        if instruction.operands.len() != pseudo.operands.len() {
+            let span = match instruction.spans.operands.get(pseudo.operands.len()) {
+                Some(span) => *span,
+                None => instruction.spans.statement,
+            };
+            let error = AssemblerError::new(
+                ErrorKind::OperandCount,
+                span,
+                format!(
+                    "`{}` takes {}, found {}",
+                    pseudo.name,
+                    describe_operands(pseudo.operands),
+                    instruction.operands.len()
+                ),
+            );
+
+            if instruction.reports(&error) {
+                errors.push(instruction.in_context(error));
+            }
+            continue;
+        }
+
+        if let Some(e) = overwritten_operand(&instruction, pseudo) {
+            errors.push(instruction.in_context(e));
+            continue;
+        }
+
+        result.extend(expand(&instruction, pseudo));
+    }
+
+    program.instructions = result;
+    errors
+}
+
+fn pseudo_instruction(instruction: &AssemblerInstruction) -> Option<&'static PseudoInstruction> {
+    let name = match &instruction.opcode {
+        Some(Token::Mnemonic { name }) => name.as_str(),
+        // `jmp $r` is the real thing.
+        Some(Token::Op { code: JMP }) => match instruction.operands.first() {
+            Some(Token::Register { .. }) | None => return None,
+            Some(_) => "jmp",
+        },
+        // So is `call @label`.
+        Some(Token::Op { code: CALL }) => match instruction.operands.first() {
+            Some(Token::Register { .. }) => "call",
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    PSEUDO_INSTRUCTIONS
+        .iter()
+        .find(|pseudo| pseudo.name == name)
+}
+
+/// An error for an operand that is `$at` when the expansion reads it after
+/// overwriting `$at`.
+fn overwritten_operand(
+    instruction: &AssemblerInstruction,
+    pseudo: &PseudoInstruction,
+) -> Option<AssemblerError> {
+    let at = Token::Register {
+        reg_num: register_number("at").unwrap_or_default(),
+    };
+    let first_write = pseudo.expansion.iter().position(|(code, args)| {
+        matches!(
+            code.destination().map(|index| &args[index]),
+            Some(Fixed("at"))
+        )
+    })?;
+
+    pseudo.expansion[first_write + 1..]
+        .iter()
+        .flat_map(|(code, args)| {
+            args.iter()
+                .enumerate()
+                .filter(move |(i, _)| code.destination() != Some(*i))
+        })
+        .find_map(|(_, arg)| match arg {
+            Operand(index) if instruction.operands[*index] == at => Some(*index),
+            _ => None,
+        })
+        .map(|index| {
+            AssemblerError::new(
+                ErrorKind::InvalidOperand,
+                instruction.spans.operands[index],
+                format!(
+                    "`{}` overwrites `$at`, so it can't take `$at` as an operand",
+                    pseudo.name
+                ),
+            )
+        })
+}
+
+fn expand(
+    instruction: &AssemblerInstruction,
+    pseudo: &PseudoInstruction,
+) -> Vec<AssemblerInstruction> {
+    let last = pseudo.expansion.len() - 1;
+
+    pseudo
+        .expansion
+        .iter()
+        .enumerate()
+        .map(|(i, (code, args))| {
+            let (operands, spans) = args
+                .iter()
+                .map(|arg| match arg {
+                    Operand(index) => (
+                        instruction.operands[*index].clone(),
+                        instruction.spans.operands[*index],
+                    ),
+                    Fixed(name) => (
+                        Token::Register {
+                            reg_num: register_number(name).unwrap_or_default(),
+                        },
+                        instruction.spans.head,
+                    ),
+                    Integer(value) => (
+                        Token::IntegerOperand { value: *value },
+                        instruction.spans.head,
+                    ),
+                })
+                .unzip();
+
+            AssemblerInstruction {
+                opcode: Some(Token::Op { code: code.clone() }),
+                label: if i == 0 {
+                    instruction.label.clone()
+                } else {
+                    None
+                },
+                directive: None,
+                operands,
+                spans: InstructionSpans {
+                    operands: spans,
+                    ..instruction.spans.clone()
+                },
+                leading_comments: if i == 0 {
+                    instruction.leading_comments.clone()
+                } else {
+                    vec![]
+                },
+                trailing_comments: if i == last {
+                    instruction.trailing_comments.clone()
+                } else {
+                    vec![]
+                },
+                expansion: instruction.expansion.clone(),
+                unparsed_operands: false,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assembler_errors::error_messages;
+    use crate::assembler::Assembler;
+    use crate::vm::VM;
+
+    #[test]
+    fn test_expansions_run() {
+        let mut test_vm = VM::new();
+        test_vm.program = Assembler::new()
+            .assemble("load $0 #5\nload $2 #7\nmov $3 $2\nmov $at $2\nnop\nload $4 #1\n")
+            .unwrap();
+        test_vm.run();
+        assert_eq!(test_vm.registers[3], 7);
+        assert_eq!(test_vm.registers[4], 1);
+    }
+
+    #[test]
+    fn test_pseudo_instructions() {
+        let mut assembler = Assembler::new();
+        let bytes = assembler
+            .assemble(
+                "start: nop\nmov $2 $3\ninc $4\ndec $4\njmp @start\nbeq $1 $2 @start\nbne $1 $2 @end\njmp $5\nend: hlt\n",
+            )
+            .unwrap();
+        assert_eq!(
+            bytes,
+            vec![
+                0x01, 0x01, 0x00, 0x00, // nop
+                0x01, 0x01, 0x00, 0x00, 0x02, 0x03, 0x01, 0x02, // mov
+                0x01, 0x01, 0x00, 0x01, 0x02, 0x04, 0x01, 0x04, // inc
+                0x01, 0x01, 0x00, 0x01, 0x03, 0x04, 0x01, 0x04, // dec
+                0x01, 0x01, 0x00, 0x00, 0x06, 0x01, // jmp @start
+                0x09, 0x01, 0x02, 0x00, 0x01, 0x01, 0x00, 0x00, 0x0F, 0x01, // beq
+                0x09, 0x01, 0x02, 0x00, 0x01, 0x01, 0x00, 0x38, 0x10, 0x01, // bne
+                0x06, 0x05, // jmp $5
+                0x00,
+            ]
+        );
+        assert_eq!(assembler.symbols.symbol_value("end"), Some(56));
+        assert_eq!(
+            assembler.assemble("call $5\ncall @f\nf: ret\n"),
+            Ok(vec![0x1F, 0x05, 0x1E, 0x00, 0x05, 0x20])
+        );
+
+        let debug = assembler
+            .assemble_binary("hlt\ninc $2\n")
+            .unwrap()
+            .debug
+            .unwrap();
+        assert_eq!(debug.describe(1), Some("<input>:2".to_string()));
+        assert_eq!(debug.describe(5), Some("<input>:2".to_string()));
+
+        let errors = assembler
+            .assemble("mov $1\ninc #1\ninc $at\ndec $1\nmov $2 $at\nbeq $at $2 #0\n")
+            .unwrap_err();
+        let messages = error_messages(&errors);
+        assert_eq!(
+            messages,
+            vec![
+                "`mov` takes 2 operands (register, register), found 1",
+                "`inc` overwrites `$at`, so it can't take `$at` as an operand",
+                "`dec` overwrites `$at`, so it can't take `$at` as an operand",
+                "`mov` overwrites `$at`, so it can't take `$at` as an operand",
+                "expected a register, found `#1`",
+            ]
+        );
+    }
+}
@@ -0,0 +1,52 @@
+/// Index of a file in a `SourceMap`.
+pub type FileId = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    /// Path of the file as it is shown in diagnostics.
+    pub name: String,
+    pub text: String,
+}
+
+/// Every file taking part in an assembly: the file being assembled, with id
+/// `0`, and the files it includes.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap { files: vec![] }
+    }
+
+    pub fn add(&mut self, name: String, text: String) -> FileId {
+        self.files.push(SourceFile { name, text });
+        self.files.len() - 1
+    }
+
+    pub fn get(&self, file: FileId) -> Option<&SourceFile> {
+        self.files.get(file)
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_map() {
+        let mut sources = SourceMap::new();
+        assert_eq!(sources.add("main.iasm".to_string(), "hlt".to_string()), 0);
+        assert_eq!(sources.add("lib.iasm".to_string(), "".to_string()), 1);
+        assert_eq!(
+            sources.get(1).map(|file| file.name.as_str()),
+            Some("lib.iasm")
+        );
+        assert_eq!(sources.get(2), None);
+    }
+}
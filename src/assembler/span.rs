@@ -0,0 +1,69 @@
+use crate::assembler::sources::FileId;
+
+/// Byte range of a construct in the assembly source.
+///
+/// While parsing, parsers only see the remaining input, so spans are first
+/// recorded as the number of bytes left in the input (see `parser::spanned`)
+/// and turned into offsets from the start of the source with `resolve` once
+/// the length of the whole source is known.
+///
+/// `file` is the `SourceMap` id of the file the span is in, `0` for the file
+/// being assembled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub file: FileId,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span {
+            start,
+            end,
+            file: 0,
+        }
+    }
+
+    /// Builds a span out of the input lengths left before and after a construct.
+    pub fn from_remaining(before: usize, after: usize) -> Self {
+        Span::new(before, after)
+    }
+
+    pub fn resolve(&mut self, source_len: usize) {
+        self.start = source_len - self.start;
+        self.end = source_len - self.end;
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// One-based line and column of the start of the span.
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+        (line, before[line_start..].chars().count() + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_span() {
+        let source = "hlt\nload $0 #1";
+        let mut span = Span::from_remaining(10, 8);
+        span.resolve(source.len());
+        assert_eq!(span, Span::new(4, 6));
+        assert_eq!(&source[span.start..span.end], "lo");
+        assert_eq!(span.line_col(source), (2, 1));
+    }
+}
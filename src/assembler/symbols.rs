@@ -0,0 +1,140 @@
+use crate::assembler::Section;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SymbolType {
+    Label,
+    /// Declared with `.extern`: defined by another object file and resolved
+    /// by the linker, its offset is zero until then.
+    Extern,
+    /// Declared with `.equ`: a name for a number, with no place in any section.
+    Constant(i64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    /// Offset from the start of `section`.
+    pub offset: u32,
+    pub symbol_type: SymbolType,
+    pub section: Section,
+    /// Exported with `.global`, so other object files can refer to it.
+    pub global: bool,
+    /// Length in bytes of the function the label starts, for labels declared
+    /// with `.func`.
+    pub size: Option<u32>,
+}
+
+impl Symbol {
+    pub fn new(name: String, symbol_type: SymbolType, section: Section, offset: u32) -> Self {
+        Symbol {
+            name,
+            offset,
+            symbol_type,
+            section,
+            global: false,
+            size: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable { symbols: vec![] }
+    }
+
+    pub fn add_symbol(&mut self, symbol: Symbol) {
+        self.symbols.push(symbol);
+    }
+
+    pub fn has_symbol(&self, name: &str) -> bool {
+        self.symbols.iter().any(|symbol| symbol.name == name)
+    }
+
+    /// Offset of the label `name`, constants have none.
+    pub fn symbol_value(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .filter(|symbol| !matches!(symbol.symbol_type, SymbolType::Constant(_)))
+            .map(|symbol| symbol.offset)
+    }
+
+    /// What `name` stands for in an expression: the value of a constant or
+    /// the offset of a label.
+    pub fn value(&self, name: &str) -> Option<i64> {
+        self.symbol(name).map(|symbol| match symbol.symbol_type {
+            SymbolType::Constant(value) => value,
+            SymbolType::Label | SymbolType::Extern => i64::from(symbol.offset),
+        })
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    /// Marks the label `name` as exported, returns false if there is no such
+    /// label.
+    pub fn export(&mut self, name: &str) -> bool {
+        match self
+            .symbols
+            .iter_mut()
+            .find(|symbol| symbol.name == name && symbol.symbol_type == SymbolType::Label)
+        {
+            Some(symbol) => {
+                symbol.global = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Records that the label `name` starts a function `size` bytes long.
+    pub fn set_size(&mut self, name: &str, size: u32) {
+        if let Some(symbol) = self.symbols.iter_mut().find(|symbol| symbol.name == name) {
+            symbol.size = Some(size);
+        }
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_table() {
+        let mut table = SymbolTable::new();
+        table.add_symbol(Symbol::new(
+            "test".to_string(),
+            SymbolType::Label,
+            Section::Code,
+            12,
+        ));
+        assert_eq!(table.symbols().len(), 1);
+        assert!(table.has_symbol("test"));
+        assert_eq!(table.symbol_value("test"), Some(12));
+        assert_eq!(table.symbol_value("does_not_exist"), None);
+        assert!(table.export("test"));
+        assert!(table.symbol("test").unwrap().global);
+        assert!(!table.export("does_not_exist"));
+
+        table.add_symbol(Symbol::new(
+            "SIZE".to_string(),
+            SymbolType::Constant(-3),
+            Section::Code,
+            0,
+        ));
+        assert_eq!(table.value("SIZE"), Some(-3));
+        assert_eq!(table.value("test"), Some(12));
+        assert_eq!(table.symbol_value("SIZE"), None);
+        assert!(!table.export("SIZE"));
+    }
+}
@@ -0,0 +1,448 @@
+//! On-disk format for assembled programs.
+//!
+//! All integers are big-endian, like the immediates in the bytecode itself.
+//!
+//! | offset | size | field                                      |
+//! |--------|------|--------------------------------------------|
+//! | 0      | 4    | magic, `IRID`                              |
+//! | 4      | 2    | format version                             |
+//! | 6      | 2    | flags, see below                           |
+//! | 8      | 4    | entry offset into the code section         |
+//! | 12     | 4    | code section length                        |
+//! | 16     | 4    | read-only data section length              |
+//! | 20     | 4    | CRC-32 of every other byte of the file     |
+//! | 24     |      | code section, then read-only data section  |
+//!
+//! With the `FLAG_DEBUG_INFO` flag set, the read-only data section is followed
+//! by the 4-byte length of the `DebugInfo` of the program and the debug
+//! information itself. Every other flag is reserved and always zero.
+
+use crate::debug_info::DebugInfo;
+use std::fmt;
+
+pub const MAGIC: [u8; 4] = *b"IRID";
+pub const VERSION: u16 = 1;
+pub const HEADER_LEN: usize = 24;
+pub const FLAG_DEBUG_INFO: u16 = 1;
+
+const CHECKSUM_OFFSET: usize = 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinaryError {
+    Truncated {
+        expected: usize,
+        found: usize,
+    },
+    TrailingData {
+        expected: usize,
+        found: usize,
+    },
+    BadMagic,
+    UnsupportedVersion {
+        version: u16,
+    },
+    UnsupportedFlags {
+        flags: u16,
+    },
+    ChecksumMismatch {
+        expected: u32,
+        found: u32,
+    },
+    EntryOutOfBounds {
+        entry: u32,
+        code_len: u32,
+    },
+    /// A field of an object file holds an impossible value.
+    Malformed {
+        reason: String,
+    },
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::BinaryError::*;
+
+        match self {
+            Truncated { expected, found } => write!(
+                f,
+                "file is truncated: expected {} bytes, found {}",
+                expected, found
+            ),
+            TrailingData { expected, found } => write!(
+                f,
+                "file has trailing data: expected {} bytes, found {}",
+                expected, found
+            ),
+            BadMagic => write!(f, "not an Iridium binary"),
+            UnsupportedVersion { version } => write!(
+                f,
+                "unsupported format version {}, expected {}",
+                version, VERSION
+            ),
+            UnsupportedFlags { flags } => write!(f, "unsupported flags 0x{:04X}", flags),
+            ChecksumMismatch { expected, found } => write!(
+                f,
+                "file is corrupted: checksum is 0x{:08X}, expected 0x{:08X}",
+                found, expected
+            ),
+            EntryOutOfBounds { entry, code_len } => write!(
+                f,
+                "entry offset {} is outside of the {} byte code section",
+                entry, code_len
+            ),
+            Malformed { reason } => write!(f, "file is malformed: {}", reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Binary {
+    pub entry: u32,
+    pub code: Vec<u8>,
+    pub ro_data: Vec<u8>,
+    pub debug: Option<DebugInfo>,
+}
+
+impl Binary {
+    pub fn new(code: Vec<u8>) -> Self {
+        Binary {
+            entry: 0,
+            code,
+            ro_data: vec![],
+            debug: None,
+        }
+    }
+
+    pub fn is_binary(bytes: &[u8]) -> bool {
+        bytes.starts_with(&MAGIC)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(HEADER_LEN + self.code.len() + self.ro_data.len());
+
+        let flags = if self.debug.is_some() {
+            FLAG_DEBUG_INFO
+        } else {
+            0
+        };
+
+        result.extend_from_slice(&MAGIC);
+        result.extend_from_slice(&VERSION.to_be_bytes());
+        result.extend_from_slice(&flags.to_be_bytes());
+        result.extend_from_slice(&self.entry.to_be_bytes());
+        result.extend_from_slice(&(self.code.len() as u32).to_be_bytes());
+        result.extend_from_slice(&(self.ro_data.len() as u32).to_be_bytes());
+        result.extend_from_slice(&[0; 4]);
+        result.extend_from_slice(&self.code);
+        result.extend_from_slice(&self.ro_data);
+
+        if let Some(debug) = &self.debug {
+            let debug = debug.to_bytes();
+
+            result.extend_from_slice(&(debug.len() as u32).to_be_bytes());
+            result.extend_from_slice(&debug);
+        }
+
+        let checksum = checksum(&result, CHECKSUM_OFFSET);
+        result[CHECKSUM_OFFSET..HEADER_LEN].copy_from_slice(&checksum.to_be_bytes());
+
+        result
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Binary, BinaryError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(BinaryError::Truncated {
+                expected: HEADER_LEN,
+                found: bytes.len(),
+            });
+        }
+
+        if bytes[0..4] != MAGIC {
+            return Err(BinaryError::BadMagic);
+        }
+
+        let version = read_u16(bytes, 4);
+
+        if version != VERSION {
+            return Err(BinaryError::UnsupportedVersion { version });
+        }
+
+        let flags = read_u16(bytes, 6);
+
+        if flags & !FLAG_DEBUG_INFO != 0 {
+            return Err(BinaryError::UnsupportedFlags { flags });
+        }
+
+        let entry = read_u32(bytes, 8);
+        let code_len = read_u32(bytes, 12);
+        let ro_data_len = read_u32(bytes, 16);
+        let sections_end = HEADER_LEN + code_len as usize + ro_data_len as usize;
+        let mut expected = sections_end;
+
+        if flags & FLAG_DEBUG_INFO != 0 {
+            expected += 4;
+
+            if bytes.len() >= expected {
+                expected += read_u32(bytes, sections_end) as usize;
+            }
+        }
+
+        if bytes.len() < expected {
+            return Err(BinaryError::Truncated {
+                expected,
+                found: bytes.len(),
+            });
+        }
+
+        if bytes.len() > expected {
+            return Err(BinaryError::TrailingData {
+                expected,
+                found: bytes.len(),
+            });
+        }
+
+        let found = read_u32(bytes, CHECKSUM_OFFSET);
+        let expected_checksum = checksum(bytes, CHECKSUM_OFFSET);
+
+        if found != expected_checksum {
+            return Err(BinaryError::ChecksumMismatch {
+                expected: expected_checksum,
+                found,
+            });
+        }
+
+        if entry > code_len || (entry == code_len && code_len != 0) {
+            return Err(BinaryError::EntryOutOfBounds { entry, code_len });
+        }
+
+        let code_end = HEADER_LEN + code_len as usize;
+        let debug = if flags & FLAG_DEBUG_INFO != 0 {
+            Some(DebugInfo::from_bytes(&bytes[sections_end + 4..])?)
+        } else {
+            None
+        };
+
+        Ok(Binary {
+            entry,
+            code: bytes[HEADER_LEN..code_end].to_vec(),
+            ro_data: bytes[code_end..sections_end].to_vec(),
+            debug,
+        })
+    }
+}
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    (u16::from(bytes[offset]) << 8) | u16::from(bytes[offset + 1])
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    (u32::from(read_u16(bytes, offset)) << 16) | u32::from(read_u16(bytes, offset + 2))
+}
+
+/// Reads the variable-length parts of a file front to back.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8], position: usize) -> Self {
+        Reader { bytes, position }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], BinaryError> {
+        let end = self.position + len;
+
+        if end > self.bytes.len() {
+            return Err(BinaryError::Truncated {
+                expected: end,
+                found: self.bytes.len(),
+            });
+        }
+
+        let result = &self.bytes[self.position..end];
+        self.position = end;
+
+        Ok(result)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, BinaryError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, BinaryError> {
+        Ok(read_u16(self.bytes(2)?, 0))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, BinaryError> {
+        Ok(read_u32(self.bytes(4)?, 0))
+    }
+
+    pub fn string(&mut self, len: usize) -> Result<String, BinaryError> {
+        String::from_utf8(self.bytes(len)?.to_vec())
+            .map_err(|_| malformed("name is not valid UTF-8"))
+    }
+
+    /// Fails if anything is left to read.
+    pub fn finish(&self) -> Result<(), BinaryError> {
+        if self.position < self.bytes.len() {
+            return Err(BinaryError::TrailingData {
+                expected: self.position,
+                found: self.bytes.len(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) fn malformed(reason: &str) -> BinaryError {
+    BinaryError::Malformed {
+        reason: reason.to_string(),
+    }
+}
+
+/// CRC-32 (IEEE) of the whole file with the 4-byte checksum field itself, at
+/// `field`, skipped.
+pub(crate) fn checksum(bytes: &[u8], field: usize) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+
+    for (i, byte) in bytes.iter().enumerate() {
+        if (field..field + 4).contains(&i) {
+            continue;
+        }
+
+        crc ^= u32::from(*byte);
+
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug_info::LineEntry;
+
+    fn test_binary() -> Binary {
+        Binary {
+            entry: 4,
+            code: vec![0x01, 0x00, 0x00, 0x64, 0x01, 0x01, 0x00, 0x02, 0x00],
+            ro_data: vec![0x68, 0x69, 0x00],
+            debug: None,
+        }
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let bytes = test_binary().to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 12);
+        assert!(Binary::is_binary(&bytes));
+        assert_eq!(Binary::from_bytes(&bytes), Ok(test_binary()));
+    }
+
+    #[test]
+    fn test_debug_info_round_trip() {
+        let binary = Binary {
+            debug: Some(DebugInfo {
+                files: vec!["main.iasm".to_string()],
+                ..DebugInfo::default()
+            }),
+            ..test_binary()
+        };
+        let bytes = binary.to_bytes();
+        assert_eq!(read_u16(&bytes, 6), FLAG_DEBUG_INFO);
+        assert_eq!(Binary::from_bytes(&bytes), Ok(binary));
+
+        let mut bytes = bytes;
+        bytes.truncate(HEADER_LEN + 14);
+        assert_eq!(
+            Binary::from_bytes(&bytes),
+            Err(BinaryError::Truncated {
+                expected: HEADER_LEN + 16,
+                found: HEADER_LEN + 14
+            })
+        );
+    }
+
+    #[test]
+    fn test_reject_overflowing_lines() {
+        let line = |offset, len| LineEntry {
+            offset,
+            len,
+            file: 0,
+            line: 1,
+            column: 1,
+        };
+        let binary = |lines| Binary {
+            debug: Some(DebugInfo {
+                files: vec!["main.iasm".to_string()],
+                lines,
+                ..DebugInfo::default()
+            }),
+            ..test_binary()
+        };
+
+        for lines in [
+            vec![line(1, u32::MAX)],
+            vec![line(0, 4), line(u32::MAX - 1, 4)],
+        ] {
+            assert_eq!(
+                Binary::from_bytes(&binary(lines).to_bytes()),
+                Err(malformed("line ends past the largest offset"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum(b"123456789", 9), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_reject_truncated() {
+        let bytes = test_binary().to_bytes();
+        assert_eq!(
+            Binary::from_bytes(&bytes[..10]),
+            Err(BinaryError::Truncated {
+                expected: HEADER_LEN,
+                found: 10
+            })
+        );
+        assert_eq!(
+            Binary::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(BinaryError::Truncated {
+                expected: bytes.len(),
+                found: bytes.len() - 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_reject_wrong_version() {
+        let mut bytes = test_binary().to_bytes();
+        bytes[5] = 2;
+        assert_eq!(
+            Binary::from_bytes(&bytes),
+            Err(BinaryError::UnsupportedVersion { version: 2 })
+        );
+    }
+
+    #[test]
+    fn test_reject_corrupted() {
+        let mut bytes = test_binary().to_bytes();
+        bytes[HEADER_LEN + 3] ^= 0xFF;
+        match Binary::from_bytes(&bytes) {
+            Err(BinaryError::ChecksumMismatch { .. }) => {}
+            result => panic!("expected a checksum mismatch, got {:?}", result),
+        }
+
+        let mut bytes = test_binary().to_bytes();
+        bytes[0] = b'X';
+        assert_eq!(Binary::from_bytes(&bytes), Err(BinaryError::BadMagic));
+    }
+}
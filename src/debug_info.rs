@@ -0,0 +1,426 @@
+//! Debug information: where each instruction of the code section came from
+//! and which label it belongs to, so a program counter can be reported as
+//! `foo.iasm:42 (in loop_start)`, and the register aliases each instruction
+//! was written with.
+//!
+//! It is written into the binary, after the read-only data section, or to a
+//! file of its own with `DebugInfo::to_bytes`. All integers are big-endian:
+//!
+//! | size | field                                                            |
+//! |------|------------------------------------------------------------------|
+//! | 4    | magic, `IRDB`                                                    |
+//! | 2    | format version                                                   |
+//! | 4    | number of files, then each file name                             |
+//! | 4    | number of lines, then each offset, length, file, line and column |
+//! | 4    | number of labels, then each start, end and name                  |
+//! | 4    | number of aliases, then each file, line, register and name       |
+//!
+//! Names are a 2-byte length followed by UTF-8, every other field takes 4 bytes.
+
+use crate::assembler::alias::alias_declaration;
+use crate::assembler::local_labels::is_numeric;
+use crate::assembler::parser::program::Program;
+use crate::assembler::sources::SourceMap;
+use crate::assembler::symbols::{SymbolTable, SymbolType};
+use crate::assembler::Section;
+use crate::binary::{malformed, BinaryError, Reader};
+use crate::vm::REGISTER_COUNT;
+
+pub const MAGIC: [u8; 4] = *b"IRDB";
+pub const VERSION: u16 = 2;
+
+/// Bytes of the code section that came from one source statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineEntry {
+    pub offset: u32,
+    pub len: u32,
+    /// Index into `DebugInfo::files`.
+    pub file: u32,
+    /// One-based line and column of the statement.
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Code from a label up to the next one, or to the end of the code section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelRange {
+    pub name: String,
+    pub start: u32,
+    pub end: u32,
+}
+
+/// An `.alias`, in effect from its line to the end of its file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AliasEntry {
+    pub name: String,
+    pub register: u8,
+    pub file: u32,
+    pub line: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebugInfo {
+    pub files: Vec<String>,
+    /// Sorted by offset, without overlaps.
+    pub lines: Vec<LineEntry>,
+    /// Sorted by start, without overlaps.
+    pub labels: Vec<LabelRange>,
+    /// In the order they were declared.
+    pub aliases: Vec<AliasEntry>,
+}
+
+impl DebugInfo {
+    /// Debug information for `program`, assembled from `sources` with the
+    /// labels in `symbols`. Instructions expanded from a macro are attributed
+    /// to the line that invokes it.
+    pub fn new(program: &Program, symbols: &SymbolTable, sources: &SourceMap) -> DebugInfo {
+        let mut info = DebugInfo {
+            files: sources
+                .files()
+                .iter()
+                .map(|file| file.name.clone())
+                .collect(),
+            ..DebugInfo::default()
+        };
+        let mut section = Section::Code;
+        let mut offset = 0;
+
+        for instruction in &program.instructions {
+            section = instruction.section().unwrap_or(section);
+
+            let statement = instruction.spans.statement;
+
+            if let (Some((name, register)), Some(file)) =
+                (alias_declaration(instruction), sources.get(statement.file))
+            {
+                info.aliases.push(AliasEntry {
+                    name: name.to_string(),
+                    register,
+                    file: statement.file as u32,
+                    line: statement.line_col(&file.text).0 as u32,
+                });
+            }
+
+            if section != Section::Code {
+                continue;
+            }
+
+            let len = instruction.encoded_len(symbols, offset);
+            let span = match &instruction.expansion {
+                Some(expansion) => expansion.invocation,
+                None => instruction.spans.statement,
+            };
+
+            if let (true, Some(file)) = (len > 0, sources.get(span.file)) {
+                let (line, column) = span.line_col(&file.text);
+
+                info.lines.push(LineEntry {
+                    offset,
+                    len,
+                    file: span.file as u32,
+                    line: line as u32,
+                    column: column as u32,
+                });
+            }
+
+            offset += len;
+        }
+
+        // Numeric labels are too many to tell the code apart, the label
+        // before them covers them instead.
+        let mut labels: Vec<(u32, &str)> = symbols
+            .symbols()
+            .iter()
+            .filter(|s| s.symbol_type == SymbolType::Label && s.section == Section::Code)
+            .filter(|s| !s.name.split('.').next().is_some_and(is_numeric))
+            .map(|s| (s.offset, s.name.as_str()))
+            .collect();
+
+        labels.sort_by_key(|(offset, _)| *offset);
+
+        for (i, (start, name)) in labels.iter().enumerate() {
+            let end = labels.get(i + 1).map_or(offset, |(next, _)| *next);
+
+            // Several labels on one spot: the last one declared covers it.
+            if end > *start {
+                info.labels.push(LabelRange {
+                    name: name.to_string(),
+                    start: *start,
+                    end,
+                });
+            }
+        }
+
+        info
+    }
+
+    pub fn line_at(&self, pc: usize) -> Option<&LineEntry> {
+        let index = self
+            .lines
+            .partition_point(|entry| entry.offset as usize <= pc);
+
+        self.lines[..index]
+            .last()
+            .filter(|entry| pc < entry.offset as usize + entry.len as usize)
+    }
+
+    pub fn label_at(&self, pc: usize) -> Option<&LabelRange> {
+        let index = self
+            .labels
+            .partition_point(|label| label.start as usize <= pc);
+
+        self.labels[..index]
+            .last()
+            .filter(|label| pc < label.end as usize)
+    }
+
+    /// The alias register `register` had where the instruction at `pc` was
+    /// written, if any.
+    pub fn register_alias(&self, pc: usize, register: u8) -> Option<&str> {
+        let entry = self.line_at(pc)?;
+        let mut aliases: Vec<&AliasEntry> = vec![];
+
+        // A later `.alias` of the same name points it somewhere else.
+        for alias in &self.aliases {
+            if alias.file == entry.file && alias.line < entry.line {
+                aliases.retain(|other| other.name != alias.name);
+                aliases.push(alias);
+            }
+        }
+
+        aliases
+            .iter()
+            .rev()
+            .find(|alias| alias.register == register)
+            .map(|alias| alias.name.as_str())
+    }
+
+    /// `file:line (in label)` for the instruction at `pc`, or as much of it as
+    /// is known.
+    pub fn describe(&self, pc: usize) -> Option<String> {
+        let line = self.line_at(pc).map(|entry| {
+            let file = self
+                .files
+                .get(entry.file as usize)
+                .map_or("?", |f| f.as_str());
+
+            format!("{}:{}", file, entry.line)
+        });
+        let label = self.label_at(pc).map(|label| label.name.as_str());
+
+        match (line, label) {
+            (Some(line), Some(label)) => Some(format!("{} (in {})", line, label)),
+            (Some(line), None) => Some(line),
+            (None, Some(label)) => Some(format!("offset {} (in {})", pc, label)),
+            (None, None) => None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = vec![];
+
+        result.extend_from_slice(&MAGIC);
+        result.extend_from_slice(&VERSION.to_be_bytes());
+        result.extend_from_slice(&(self.files.len() as u32).to_be_bytes());
+
+        for file in &self.files {
+            push_name(&mut result, file);
+        }
+
+        result.extend_from_slice(&(self.lines.len() as u32).to_be_bytes());
+
+        for entry in &self.lines {
+            for field in &[
+                entry.offset,
+                entry.len,
+                entry.file,
+                entry.line,
+                entry.column,
+            ] {
+                result.extend_from_slice(&field.to_be_bytes());
+            }
+        }
+
+        result.extend_from_slice(&(self.labels.len() as u32).to_be_bytes());
+
+        for label in &self.labels {
+            result.extend_from_slice(&label.start.to_be_bytes());
+            result.extend_from_slice(&label.end.to_be_bytes());
+            push_name(&mut result, &label.name);
+        }
+
+        result.extend_from_slice(&(self.aliases.len() as u32).to_be_bytes());
+
+        for alias in &self.aliases {
+            result.extend_from_slice(&alias.file.to_be_bytes());
+            result.extend_from_slice(&alias.line.to_be_bytes());
+            result.extend_from_slice(&u32::from(alias.register).to_be_bytes());
+            push_name(&mut result, &alias.name);
+        }
+
+        result
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<DebugInfo, BinaryError> {
+        let mut reader = Reader::new(bytes, 0);
+
+        if reader.bytes(4).map_or(true, |magic| magic != MAGIC) {
+            return Err(BinaryError::BadMagic);
+        }
+
+        let version = reader.u16()?;
+
+        if version != VERSION {
+            return Err(BinaryError::UnsupportedVersion { version });
+        }
+
+        let mut info = DebugInfo::default();
+
+        for _ in 0..reader.u32()? {
+            let len = reader.u16()?;
+            info.files.push(reader.string(len as usize)?);
+        }
+
+        for _ in 0..reader.u32()? {
+            let entry = LineEntry {
+                offset: reader.u32()?,
+                len: reader.u32()?,
+                file: reader.u32()?,
+                line: reader.u32()?,
+                column: reader.u32()?,
+            };
+
+            if entry.file as usize >= info.files.len() {
+                return Err(malformed("line refers to a file that does not exist"));
+            }
+
+            if entry.offset.checked_add(entry.len).is_none() {
+                return Err(malformed("line ends past the largest offset"));
+            }
+
+            if info.lines.last().is_some_and(|last| {
+                last.offset
+                    .checked_add(last.len)
+                    .is_none_or(|end| end > entry.offset)
+            }) {
+                return Err(malformed("lines are not sorted"));
+            }
+
+            info.lines.push(entry);
+        }
+
+        for _ in 0..reader.u32()? {
+            let start = reader.u32()?;
+            let end = reader.u32()?;
+            let len = reader.u16()?;
+            let name = reader.string(len as usize)?;
+
+            if info.labels.last().is_some_and(|last| last.end > start) || start > end {
+                return Err(malformed("labels are not sorted"));
+            }
+
+            info.labels.push(LabelRange { name, start, end });
+        }
+
+        for _ in 0..reader.u32()? {
+            let file = reader.u32()?;
+            let line = reader.u32()?;
+            let register = reader.u32()?;
+            let len = reader.u16()?;
+            let name = reader.string(len as usize)?;
+
+            if file as usize >= info.files.len() {
+                return Err(malformed("alias refers to a file that does not exist"));
+            }
+
+            if register as usize >= REGISTER_COUNT {
+                return Err(malformed("alias refers to a register that does not exist"));
+            }
+
+            info.aliases.push(AliasEntry {
+                name,
+                register: register as u8,
+                file,
+                line,
+            });
+        }
+
+        reader.finish()?;
+
+        Ok(info)
+    }
+}
+
+fn push_name(result: &mut Vec<u8>, name: &str) {
+    result.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    result.extend_from_slice(name.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use std::path::Path;
+
+    #[test]
+    fn test_debug_info() {
+        let source = ".macro twice\nhlt\nhlt\n.endm\nload $0 #1\nloop: add $0 $0 $0\n  twice\n.data\n.byte 1\n.code\nend: hlt\n";
+        let binary = Assembler::new()
+            .assemble_file(Path::new("foo.iasm"), source)
+            .unwrap();
+        let info = binary.debug.unwrap();
+
+        assert_eq!(info.files, vec!["foo.iasm".to_string()]);
+        let lines: Vec<(u32, u32, u32, u32)> = info
+            .lines
+            .iter()
+            .map(|e| (e.offset, e.len, e.line, e.column))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (0, 4, 5, 1),
+                (4, 4, 6, 1),
+                (8, 1, 7, 3),
+                (9, 1, 7, 3),
+                (10, 1, 11, 1)
+            ]
+        );
+        assert_eq!(info.describe(0), Some("foo.iasm:5".to_string()));
+        assert_eq!(info.describe(9), Some("foo.iasm:7 (in loop)".to_string()));
+        assert_eq!(info.describe(10), Some("foo.iasm:11 (in end)".to_string()));
+        assert_eq!(info.describe(11), None);
+        assert_eq!(DebugInfo::from_bytes(&info.to_bytes()), Ok(info));
+    }
+
+    #[test]
+    fn test_shared_labels() {
+        let binary = Assembler::new()
+            .assemble_binary("zeta: .code\nalpha: hlt\n")
+            .unwrap();
+        let info = binary.debug.unwrap();
+
+        assert_eq!(info.describe(0), Some("<input>:2 (in alpha)".to_string()));
+    }
+
+    #[test]
+    fn test_register_aliases() {
+        let source = ".alias count $5
+load $count #1
+.alias count $6
+.alias limit $5
+load $5 #2
+";
+        let binary = Assembler::new()
+            .assemble_file(Path::new("foo.iasm"), source)
+            .unwrap();
+        let info = binary.debug.unwrap();
+
+        assert_eq!(info.register_alias(0, 5), Some("count"));
+        assert_eq!(info.register_alias(0, 6), None);
+        assert_eq!(info.register_alias(4, 5), Some("limit"));
+        assert_eq!(info.register_alias(4, 6), Some("count"));
+        assert_eq!(info.register_alias(8, 5), None);
+        assert_eq!(DebugInfo::from_bytes(&info.to_bytes()), Ok(info));
+    }
+}
@@ -0,0 +1,204 @@
+use crate::debug_info::DebugInfo;
+use crate::instruction::{Opcode, OperandKind};
+use crate::vm::REGISTER_COUNT;
+use std::fmt;
+
+/// One line of disassembly: either a decoded instruction or a single byte
+/// that could not be decoded, shown as a `.byte` directive.
+#[derive(Debug, PartialEq)]
+pub struct DisassembledInstruction {
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl fmt::Display for DisassembledInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+
+        write!(
+            f,
+            "{:04X}: {:<12} {}",
+            self.offset,
+            bytes.join(" "),
+            self.text
+        )
+    }
+}
+
+/// Walks `program` the same way `VM::execute_instruction` decodes it.
+///
+/// Bytes that don't form an instruction the assembler could have produced
+/// (illegal opcodes, truncated instructions, registers out of range or non-zero
+/// padding) are emitted one at a time as `.byte 0xNN` rather than guessed at.
+pub fn disassemble(program: &[u8]) -> Vec<DisassembledInstruction> {
+    disassemble_with(program, None)
+}
+
+/// Like `disassemble`, but registers are shown by the alias they had where
+/// the instruction was written, when `debug` knows about one.
+pub fn disassemble_with(program: &[u8], debug: Option<&DebugInfo>) -> Vec<DisassembledInstruction> {
+    let mut result = vec![];
+    let mut offset = 0;
+
+    while offset < program.len() {
+        let register_name =
+            |register: u8| match debug.and_then(|d| d.register_alias(offset, register)) {
+                Some(alias) => format!("${}", alias),
+                None => format!("${}", register),
+            };
+
+        match decode(&program[offset..], register_name) {
+            Some((text, len)) => {
+                result.push(DisassembledInstruction {
+                    offset,
+                    bytes: program[offset..offset + len].to_vec(),
+                    text,
+                });
+                offset += len;
+            }
+            None => {
+                result.push(DisassembledInstruction {
+                    offset,
+                    bytes: vec![program[offset]],
+                    text: format!(".byte 0x{:02X}", program[offset]),
+                });
+                offset += 1;
+            }
+        }
+    }
+
+    result
+}
+
+/// Disassembles `program` into assembly source, one instruction per line.
+/// Decoded instructions reassemble with `parse_program` to the same bytes.
+pub fn to_source(program: &[u8]) -> String {
+    disassemble(program)
+        .iter()
+        .map(|instruction| format!("{}\n", instruction.text))
+        .collect()
+}
+
+fn decode(bytes: &[u8], register_name: impl Fn(u8) -> String) -> Option<(String, usize)> {
+    let opcode = Opcode::from(bytes[0]);
+
+    if opcode.is_illegal() || bytes.len() < opcode.encoded_len() {
+        return None;
+    }
+
+    let mut text = opcode.to_string();
+    let mut position = 1;
+
+    for kind in opcode.operands() {
+        match kind {
+            OperandKind::Register => {
+                let register = bytes[position];
+
+                if usize::from(register) >= REGISTER_COUNT {
+                    return None;
+                }
+
+                text.push_str(&format!(" {}", register_name(register)));
+            }
+            OperandKind::Immediate => {
+                text.push_str(&format!(" #{}", read_u16(bytes, position)));
+            }
+            OperandKind::SignedImmediate => {
+                text.push_str(&format!(" #{}", read_u16(bytes, position) as i16));
+            }
+            OperandKind::Padding => {
+                if bytes[position] != 0 {
+                    return None;
+                }
+            }
+        }
+
+        position += kind.size();
+    }
+
+    Some((text, position))
+}
+
+fn read_u16(bytes: &[u8], position: usize) -> u16 {
+    (u16::from(bytes[position]) << 8) | u16::from(bytes[position + 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::parser::program::parse_program;
+    use crate::assembler::symbols::SymbolTable;
+    use crate::assembler::Assembler;
+    use nom::types::CompleteStr;
+
+    #[test]
+    fn test_disassemble() {
+        let program = vec![0x01, 0x00, 0x00, 0x64, 0x09, 0x00, 0x01, 0x00, 0x00];
+        let result = disassemble(&program);
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0].text, "load $0 #100");
+        assert_eq!(result[1].offset, 4);
+        assert_eq!(result[1].text, "eq $0 $1");
+        assert_eq!(result[2].text, "hlt");
+        assert_eq!(result[0].to_string(), "0000: 01 00 00 64  load $0 #100");
+    }
+
+    #[test]
+    fn test_disassemble_aliases() {
+        let binary = Assembler::new()
+            .assemble_binary(".alias count $1\nload $count #3\nadd $count $count $2\n")
+            .unwrap();
+        let text: Vec<String> = disassemble_with(&binary.code, binary.debug.as_ref())
+            .into_iter()
+            .map(|i| i.text)
+            .collect();
+        assert_eq!(text, vec!["load $count #3", "add $count $count $2"]);
+        assert_eq!(disassemble(&binary.code)[0].text, "load $1 #3");
+    }
+
+    #[test]
+    fn test_disassemble_illegal_bytes() {
+        let program = vec![0xFF, 0x01, 0x40, 0x00, 0x01];
+        let text: Vec<String> = disassemble(&program).into_iter().map(|i| i.text).collect();
+        assert_eq!(
+            text,
+            vec![
+                ".byte 0xFF",
+                ".byte 0x01",
+                ".byte 0x40",
+                "hlt",
+                ".byte 0x01"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disassembly_reassembles() {
+        let program = vec![
+            0x01, 0x00, 0x01, 0xF4, 0x02, 0x00, 0x01, 0x02, 0x0B, 0x02, 0x03, 0x00, 0x0F, 0x04,
+            0x11, 0x1F, 0x12, 0x05, 0xFF, 0x9C, 0x13, 0x01, 0x02, 0xFF, 0xFC, 0x15, 0x03, 0x02,
+            0x00, 0x10, 0x1B, 0x01, 0x02, 0xFF, 0xFC, 0x16, 0x03, 0x02, 0x00, 0x10, 0x26, 0x01,
+            0x02, 0x29, 0x01, 0x02, 0x03, 0x2B, 0x04, 0x00,
+        ];
+        let source = to_source(&program);
+        assert!(source.contains("lbd $1 $2 #-4\n"));
+        assert!(source.contains("lwd $3 $2 #16\n"));
+        assert!(source.contains("sw $1 $2 #-4\n"));
+        assert!(source.contains("lb $3 $2 #16\n"));
+        assert!(source.contains("not $1 $2\n"));
+        assert!(source.contains("mfrem $4\n"));
+        let (rest, parsed) = parse_program(CompleteStr(&source)).unwrap();
+        assert_eq!(rest, CompleteStr(""));
+        assert_eq!(parsed.to_bytes(&SymbolTable::new()), Ok(program));
+    }
+
+    #[test]
+    fn test_undecoded_bytes_reassemble() {
+        let program = vec![0xFF, 0x01, 0x20, 0x00, 0x01];
+        let source = to_source(&program);
+        let (rest, parsed) = parse_program(CompleteStr(&source)).unwrap();
+        assert_eq!(rest, CompleteStr(""));
+        assert_eq!(parsed.to_bytes(&SymbolTable::new()), Ok(program));
+    }
+}
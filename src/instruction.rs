@@ -1,7 +1,7 @@
 use nom::types::CompleteStr;
 use std::fmt;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Opcode {
     HLT,
     LOAD,
@@ -21,10 +21,90 @@ pub enum Opcode {
     JEQ,
     JNEQ,
     ALOC,
+    LOADS,
+    /// Loads of bytes, halfwords and words from the read-only data segment,
+    /// which data labels address. Big-endian, like immediates.
+    LBD,
+    LHD,
+    LWD,
+    /// Heap loads and stores of bytes, halfwords and words, big-endian.
+    LB,
+    LH,
+    LW,
+    SB,
+    SH,
+    SW,
+    /// Stack pushes and pops of a register, and calls to a label or the
+    /// address in a register along with returns from them.
+    PUSH,
+    POP,
+    CALL,
+    CALLR,
+    RET,
+    /// Loads and stores of words on the stack, for the frames of functions.
+    LWS,
+    SWS,
+    /// Bitwise operations and shifts. `shr` shifts zeros in, `sar` copies of
+    /// the sign bit.
+    AND,
+    OR,
+    XOR,
+    NOT,
+    SHL,
+    SHR,
+    SAR,
+    MOD,
+    /// Moves the remainder of the last `div` into a register.
+    MFREM,
 
     IGL(u8),
 }
 
+/// Kind of operand an opcode is encoded with, in encoding order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperandKind {
+    /// Register number, one byte.
+    Register,
+    /// 16-bit immediate, written as an integer or a label address. Encoded as
+    /// two big-endian bytes.
+    Immediate,
+    /// Like `Immediate`, but read back as a two's complement number.
+    SignedImmediate,
+    /// Zero byte the VM skips over; it is never written in the source.
+    Padding,
+}
+
+impl OperandKind {
+    pub fn size(self) -> usize {
+        match self {
+            OperandKind::Register | OperandKind::Padding => 1,
+            OperandKind::Immediate | OperandKind::SignedImmediate => 2,
+        }
+    }
+
+    /// Smallest and largest value an immediate of this kind can hold.
+    pub fn range(self) -> Option<(i64, i64)> {
+        match self {
+            OperandKind::Immediate => Some((0, i64::from(u16::MAX))),
+            OperandKind::SignedImmediate => Some((i64::from(i16::MIN), i64::from(i16::MAX))),
+            OperandKind::Register | OperandKind::Padding => None,
+        }
+    }
+}
+
+impl fmt::Display for OperandKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self {
+            OperandKind::Register => "register",
+            OperandKind::Immediate => "16-bit immediate",
+            OperandKind::SignedImmediate => "signed 16-bit immediate",
+            OperandKind::Padding => "padding",
+        };
+
+        write!(f, "{}", kind)
+    }
+}
+
 impl fmt::Display for Opcode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::Opcode::*;
@@ -51,6 +131,32 @@ impl fmt::Display for Opcode {
             JEQ => "jeq",
             JNEQ => "jneq",
             ALOC => "aloc",
+            LOADS => "loads",
+            LBD => "lbd",
+            LHD => "lhd",
+            LWD => "lwd",
+            LB => "lb",
+            LH => "lh",
+            LW => "lw",
+            SB => "sb",
+            SH => "sh",
+            SW => "sw",
+            PUSH => "push",
+            POP => "pop",
+            CALL => "call",
+            CALLR => "callr",
+            RET => "ret",
+            LWS => "lws",
+            SWS => "sws",
+            AND => "and",
+            OR => "or",
+            XOR => "xor",
+            NOT => "not",
+            SHL => "shl",
+            SHR => "shr",
+            SAR => "sar",
+            MOD => "mod",
+            MFREM => "mfrem",
         };
 
         write!(f, "{}", opcode)
@@ -80,6 +186,32 @@ impl From<u8> for Opcode {
             0x0F => JEQ,
             0x10 => JNEQ,
             0x11 => ALOC,
+            0x12 => LOADS,
+            0x13 => LBD,
+            0x14 => LHD,
+            0x15 => LWD,
+            0x16 => LB,
+            0x17 => LH,
+            0x18 => LW,
+            0x19 => SB,
+            0x1A => SH,
+            0x1B => SW,
+            0x1C => PUSH,
+            0x1D => POP,
+            0x1E => CALL,
+            0x1F => CALLR,
+            0x20 => RET,
+            0x21 => LWS,
+            0x22 => SWS,
+            0x23 => AND,
+            0x24 => OR,
+            0x25 => XOR,
+            0x26 => NOT,
+            0x27 => SHL,
+            0x28 => SHR,
+            0x29 => SAR,
+            0x2A => MOD,
+            0x2B => MFREM,
             code => IGL(code),
         }
     }
@@ -105,8 +237,35 @@ impl<'a> From<CompleteStr<'a>> for Opcode {
             CompleteStr("gt") => GT,
             CompleteStr("ltq") => LTQ,
             CompleteStr("lt") => LT,
+            CompleteStr("jeq") => JEQ,
             CompleteStr("jneq") => JNEQ,
             CompleteStr("aloc") => ALOC,
+            CompleteStr("loads") => LOADS,
+            CompleteStr("lbd") => LBD,
+            CompleteStr("lhd") => LHD,
+            CompleteStr("lwd") => LWD,
+            CompleteStr("lb") => LB,
+            CompleteStr("lh") => LH,
+            CompleteStr("lw") => LW,
+            CompleteStr("sb") => SB,
+            CompleteStr("sh") => SH,
+            CompleteStr("sw") => SW,
+            CompleteStr("push") => PUSH,
+            CompleteStr("pop") => POP,
+            CompleteStr("callr") => CALLR,
+            CompleteStr("call") => CALL,
+            CompleteStr("ret") => RET,
+            CompleteStr("lws") => LWS,
+            CompleteStr("sws") => SWS,
+            CompleteStr("and") => AND,
+            CompleteStr("or") => OR,
+            CompleteStr("xor") => XOR,
+            CompleteStr("not") => NOT,
+            CompleteStr("shl") => SHL,
+            CompleteStr("shr") => SHR,
+            CompleteStr("sar") => SAR,
+            CompleteStr("mod") => MOD,
+            CompleteStr("mfrem") => MFREM,
             CompleteStr(_) => IGL(0xFF),
         }
     }
@@ -135,9 +294,139 @@ impl Opcode {
             JEQ => 0x0F,
             JNEQ => 0x10,
             ALOC => 0x11,
+            LOADS => 0x12,
+            LBD => 0x13,
+            LHD => 0x14,
+            LWD => 0x15,
+            LB => 0x16,
+            LH => 0x17,
+            LW => 0x18,
+            SB => 0x19,
+            SH => 0x1A,
+            SW => 0x1B,
+            PUSH => 0x1C,
+            POP => 0x1D,
+            CALL => 0x1E,
+            CALLR => 0x1F,
+            RET => 0x20,
+            LWS => 0x21,
+            SWS => 0x22,
+            AND => 0x23,
+            OR => 0x24,
+            XOR => 0x25,
+            NOT => 0x26,
+            SHL => 0x27,
+            SHR => 0x28,
+            SAR => 0x29,
+            MOD => 0x2A,
+            MFREM => 0x2B,
             IGL(code) => *code,
         }
     }
+
+    /// Operands the VM decodes after this opcode, in order.
+    pub fn operands(&self) -> &'static [OperandKind] {
+        use self::Opcode::*;
+        use self::OperandKind::*;
+
+        match self {
+            HLT | RET | IGL(_) => &[],
+            LOAD => &[Register, Immediate],
+            LOADS => &[Register, SignedImmediate],
+            ADD | SUB | MUL | DIV | AND | OR | XOR | SHL | SHR | SAR | MOD => {
+                &[Register, Register, Register]
+            }
+            NOT => &[Register, Register],
+            JMP | JMPF | JMPB | JEQ | JNEQ | ALOC | PUSH | POP | CALLR | MFREM => &[Register],
+            CALL => &[Immediate],
+            EQ | NEQ | GT | LT | GTQ | LTQ => &[Register, Register, Padding],
+            // The value register, then the base register and the offset of
+            // the address.
+            LBD | LHD | LWD | LB | LH | LW | SB | SH | SW | LWS | SWS => {
+                &[Register, Register, SignedImmediate]
+            }
+        }
+    }
+
+    /// Index among the source operands of the register the opcode writes its
+    /// result to, if it writes one.
+    pub fn destination(&self) -> Option<usize> {
+        use self::Opcode::*;
+
+        match self {
+            LOAD | LOADS | LBD | LHD | LWD | LB | LH | LW | POP | LWS | MFREM => Some(0),
+            NOT => Some(1),
+            ADD | SUB | MUL | DIV | AND | OR | XOR | SHL | SHR | SAR | MOD => Some(2),
+            _ => None,
+        }
+    }
+
+    /// Operands written in the source, that is `operands` without padding.
+    pub fn source_operands(&self) -> impl Iterator<Item = OperandKind> {
+        self.operands()
+            .iter()
+            .cloned()
+            .filter(|kind| *kind != OperandKind::Padding)
+    }
+
+    /// Size of the whole instruction in bytes, opcode included.
+    pub fn encoded_len(&self) -> usize {
+        1 + self
+            .operands()
+            .iter()
+            .map(|kind| kind.size())
+            .sum::<usize>()
+    }
+
+    /// Finds the opcode whose mnemonic is closest to `mnemonic`, if any is
+    /// close enough to be worth suggesting.
+    pub fn closest(mnemonic: &str) -> Option<Opcode> {
+        let threshold = mnemonic.len() / 3 + 1;
+
+        (0..=u8::MAX)
+            .map(Opcode::from)
+            .filter(|opcode| !opcode.is_illegal())
+            .map(|opcode| (edit_distance(mnemonic, &opcode.to_string()), opcode))
+            .filter(|(distance, _)| *distance <= threshold)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, opcode)| opcode)
+    }
+
+    pub fn is_illegal(&self) -> bool {
+        matches!(self, Opcode::IGL(_))
+    }
+}
+
+/// Optimal string alignment distance: insertions, deletions, substitutions
+/// and transpositions of adjacent characters all count as one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
 }
 
 #[allow(dead_code)]
@@ -174,4 +463,33 @@ mod tests {
         let opcode = Opcode::from(CompleteStr("illegal"));
         assert_eq!(opcode, Opcode::IGL(0xFF));
     }
+
+    #[test]
+    fn test_opcode_operands() {
+        assert_eq!(Opcode::HLT.encoded_len(), 1);
+        assert_eq!(Opcode::LOAD.encoded_len(), 4);
+        assert_eq!(Opcode::JMP.encoded_len(), 2);
+        assert_eq!(Opcode::EQ.encoded_len(), 4);
+        assert_eq!(Opcode::EQ.source_operands().count(), 2);
+        assert_eq!(Opcode::LOADS.encoded_len(), 4);
+        assert_eq!(Opcode::SW.encoded_len(), 5);
+        assert_eq!(Opcode::from(CompleteStr("lh")), Opcode::LH);
+        assert_eq!(Opcode::from(Opcode::SB.to_u8()), Opcode::SB);
+        assert_eq!(Opcode::CALL.encoded_len(), 3);
+        assert_eq!(Opcode::RET.encoded_len(), 1);
+        assert_eq!(Opcode::from(CompleteStr("callr")), Opcode::CALLR);
+        assert_eq!(Opcode::POP.destination(), Some(0));
+        assert_eq!(Opcode::NOT.encoded_len(), 3);
+        assert_eq!(Opcode::NOT.destination(), Some(1));
+        assert_eq!(Opcode::from(CompleteStr("sar")), Opcode::SAR);
+        assert_eq!(Opcode::from(Opcode::MFREM.to_u8()), Opcode::MFREM);
+        assert_eq!(OperandKind::SignedImmediate.range(), Some((-32768, 32767)));
+    }
+
+    #[test]
+    fn test_closest_opcode() {
+        assert_eq!(Opcode::closest("loda"), Some(Opcode::LOAD));
+        assert_eq!(Opcode::closest("jeqq"), Some(Opcode::JEQ));
+        assert_eq!(Opcode::closest("frobnicate"), None);
+    }
 }
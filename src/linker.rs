@@ -0,0 +1,357 @@
+//! Combines object files into one program.
+//!
+//! Code sections are placed one after the other in the order the objects were
+//! added, and so are read-only data sections, each one aligned as its object
+//! asks. The program starts at the code of the first object. Every relocated
+//! field then gets the distance its symbol moved added to it, which for a
+//! symbol declared `.extern` is its final address: those are looked up among
+//! the `.global` symbols of all the objects.
+
+use crate::assembler::Section;
+use crate::binary::Binary;
+use crate::object::{Binding, ObjectFile, ObjectSymbol};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    UndefinedSymbol {
+        name: String,
+        module: String,
+    },
+    DuplicateSymbol {
+        name: String,
+        first: String,
+        second: String,
+    },
+    OutOfRange {
+        name: String,
+        module: String,
+        address: u32,
+        size: u8,
+    },
+    InvalidRelocation {
+        module: String,
+        section: Section,
+        offset: u32,
+    },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::LinkError::*;
+
+        match self {
+            UndefinedSymbol { name, module } => write!(
+                f,
+                "`{}` is declared `.extern` in `{}` but no object exports it",
+                name, module
+            ),
+            DuplicateSymbol {
+                name,
+                first,
+                second,
+            } => write!(f, "`{}` is exported by both `{}` and `{}`", name, first, second),
+            OutOfRange {
+                name,
+                module,
+                address,
+                size,
+            } => write!(
+                f,
+                "`{}` is linked at {}, which does not fit in the {}-bit field that `{}` refers to it with",
+                name,
+                address,
+                u32::from(*size) * 8,
+                module
+            ),
+            InvalidRelocation {
+                module,
+                section,
+                offset,
+            } => {
+                let section = match section {
+                    Section::Code => "code",
+                    Section::Data => "read-only data",
+                };
+
+                write!(
+                    f,
+                    "`{}` has a relocation at offset {} of its {} section, which is not there",
+                    module, offset, section
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Linker {
+    /// Objects to link, with the names their errors refer to them by.
+    objects: Vec<(String, ObjectFile)>,
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Linker { objects: vec![] }
+    }
+
+    pub fn add(&mut self, name: String, object: ObjectFile) {
+        self.objects.push((name, object));
+    }
+
+    /// Links every object added so far. Errors from every object are collected
+    /// and returned together.
+    pub fn link(&self) -> Result<Binary, Vec<LinkError>> {
+        let mut binary = Binary::default();
+        let mut bases = vec![];
+        let mut errors = vec![];
+
+        for (_, object) in &self.objects {
+            let code = place(&mut binary.code, &object.code, object.code_align);
+            let data = place(&mut binary.ro_data, &object.ro_data, object.ro_data_align);
+
+            bases.push((code, data));
+        }
+
+        let address = |module: usize, section: Section| match section {
+            Section::Code => bases[module].0,
+            Section::Data => bases[module].1,
+        };
+        let mut globals: HashMap<&str, (usize, &ObjectSymbol)> = HashMap::new();
+
+        for (module, (module_name, object)) in self.objects.iter().enumerate() {
+            for symbol in &object.symbols {
+                if symbol.binding != Binding::Global {
+                    continue;
+                }
+
+                match globals.get(symbol.name.as_str()) {
+                    Some((first, _)) => errors.push(LinkError::DuplicateSymbol {
+                        name: symbol.name.clone(),
+                        first: self.objects[*first].0.clone(),
+                        second: module_name.clone(),
+                    }),
+                    None => {
+                        globals.insert(&symbol.name, (module, symbol));
+                    }
+                }
+            }
+        }
+
+        for (module, (module_name, object)) in self.objects.iter().enumerate() {
+            let mut undefined = vec![];
+
+            for relocation in &object.relocations {
+                let invalid = || LinkError::InvalidRelocation {
+                    module: module_name.clone(),
+                    section: relocation.section,
+                    offset: relocation.offset,
+                };
+                let symbol = match object.symbols.get(relocation.symbol as usize) {
+                    Some(symbol) => symbol,
+                    None => {
+                        errors.push(invalid());
+                        continue;
+                    }
+                };
+                // The field holds the value the symbol had in the object,
+                // plus or minus a constant.
+                let (before, after) = match symbol.binding {
+                    Binding::Extern => match globals.get(symbol.name.as_str()) {
+                        Some((defining, global)) => {
+                            (0, address(*defining, global.section) + global.offset)
+                        }
+                        None => {
+                            if !undefined.contains(&&symbol.name) {
+                                undefined.push(&symbol.name);
+                                errors.push(LinkError::UndefinedSymbol {
+                                    name: symbol.name.clone(),
+                                    module: module_name.clone(),
+                                });
+                            }
+
+                            continue;
+                        }
+                    },
+                    Binding::Local | Binding::Global => (
+                        symbol.offset,
+                        address(module, symbol.section) + symbol.offset,
+                    ),
+                };
+                let size = usize::from(relocation.size);
+
+                if ![1, 2, 4].contains(&size)
+                    || relocation.offset as usize + size > object.section(relocation.section).len()
+                {
+                    errors.push(invalid());
+                    continue;
+                }
+
+                let output = match relocation.section {
+                    Section::Code => &mut binary.code,
+                    Section::Data => &mut binary.ro_data,
+                };
+                let start = (address(module, relocation.section) + relocation.offset) as usize;
+                let field = &mut output[start..start + size];
+                // Big-endian, like the immediates in the bytecode.
+                let mut bytes = [0; 4];
+                bytes[4 - size..].copy_from_slice(field);
+
+                let value =
+                    i64::from(u32::from_be_bytes(bytes)) + i64::from(after) - i64::from(before);
+
+                if value < 0 || value >> (size * 8) != 0 {
+                    errors.push(LinkError::OutOfRange {
+                        name: symbol.name.clone(),
+                        module: module_name.clone(),
+                        address: value as u32,
+                        size: relocation.size,
+                    });
+                    continue;
+                }
+
+                field.copy_from_slice(&(value as u32).to_be_bytes()[4 - size..]);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(binary)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Appends `section` to `output`, padded with zeroes to `align`, and returns
+/// the offset it starts at.
+fn place(output: &mut Vec<u8>, section: &[u8], align: u32) -> u32 {
+    let start = output.len().next_multiple_of(align.max(1) as usize);
+
+    output.resize(start, 0);
+    output.extend_from_slice(section);
+
+    start as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::object::Relocation;
+    use std::path::Path;
+
+    fn object(source: &str) -> ObjectFile {
+        Assembler::new()
+            .assemble_object(Path::new("test.iasm"), source)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_link() {
+        let mut linker = Linker::new();
+        linker.add(
+            "main".to_string(),
+            object(".extern double, two\nload $0 @double\nload $1 @two\njmp $0\n"),
+        );
+        linker.add(
+            "lib".to_string(),
+            object(
+                ".global double two\nhlt\ndouble: add $1 $1 $1\n.data\n.byte 9\n.align 2\ntwo: .half 2, @two\n",
+            ),
+        );
+
+        let binary = linker.link().unwrap();
+        assert_eq!(
+            binary.code,
+            vec![
+                0x01, 0x00, 0x00, 0x0B, 0x01, 0x01, 0x00, 0x02, 0x06, 0x00, 0x00, 0x02, 0x01, 0x01,
+                0x01
+            ]
+        );
+        assert_eq!(binary.ro_data, vec![0x09, 0x00, 0x00, 0x02, 0x00, 0x02]);
+        assert_eq!(binary.entry, 0);
+    }
+
+    #[test]
+    fn test_link_expressions() {
+        let mut linker = Linker::new();
+        linker.add("pad".to_string(), object("hlt\nhlt\n.data\n.byte 1\n"));
+        linker.add(
+            "main".to_string(),
+            object(
+                ".extern far\nstart: load $0 #@far+2\nload $1 #@table+1\nload $2 #end-start\n\
+                 end: hlt\n.data\ntable: .byte 0 0\n",
+            ),
+        );
+        linker.add("lib".to_string(), object(".global far\nfar: hlt\n"));
+
+        let binary = linker.link().unwrap();
+        assert_eq!(
+            &binary.code[2..14],
+            &[0x01, 0x00, 0x00, 0x11, 0x01, 0x01, 0x00, 0x02, 0x01, 0x02, 0x00, 0x0C]
+        );
+        assert!(object("a: .byte 0\nb: .half b-a\n").relocations.is_empty());
+        assert_eq!(
+            Assembler::new()
+                .assemble_object(Path::new("test.iasm"), "a: .half a*2\n")
+                .unwrap_err()[0]
+                .message,
+            "`#a*2` can't be relocated, use a label plus or minus a constant, \
+             or the distance between two labels"
+        );
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let mut linker = Linker::new();
+        linker.add(
+            "a".to_string(),
+            object(
+                ".global start\n.extern missing\nstart: load $0 @missing\n.word @missing\n.space 300\n",
+            ),
+        );
+        linker.add("b".to_string(), object(".global start\nstart: hlt\n"));
+        linker.add("big".to_string(), object("big: hlt\n.byte @big\n"));
+
+        // A relocation past the end of its (empty) code section.
+        let broken = ObjectFile {
+            symbols: object("here: hlt\n").symbols,
+            relocations: vec![Relocation {
+                section: Section::Code,
+                offset: 0,
+                size: 2,
+                symbol: 0,
+            }],
+            ..ObjectFile::default()
+        };
+        linker.add("broken".to_string(), broken);
+
+        let errors = linker.link().unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                LinkError::DuplicateSymbol {
+                    name: "start".to_string(),
+                    first: "a".to_string(),
+                    second: "b".to_string(),
+                },
+                LinkError::UndefinedSymbol {
+                    name: "missing".to_string(),
+                    module: "a".to_string(),
+                },
+                LinkError::OutOfRange {
+                    name: "big".to_string(),
+                    module: "big".to_string(),
+                    address: 309,
+                    size: 1,
+                },
+                LinkError::InvalidRelocation {
+                    module: "broken".to_string(),
+                    section: Section::Code,
+                    offset: 0,
+                },
+            ]
+        );
+    }
+}
@@ -2,11 +2,77 @@
 extern crate nom;
 
 pub mod assembler;
+pub mod binary;
+pub mod debug_info;
+pub mod disassembler;
 pub mod instruction;
+pub mod linker;
+pub mod object;
 pub mod repl;
 pub mod vm;
 
 fn main() {
     let mut repl = repl::Repl::new();
-    repl.run();
+    let mut args = std::env::args().skip(1);
+    let mut program = None;
+
+    while let Some(arg) = args.next() {
+        let define = match arg.as_str() {
+            "--stack-size" => {
+                match args.next().and_then(|size| size.parse().ok()) {
+                    Some(size) if size <= vm::STACK_SIZE_LIMIT => repl.set_stack_size(size),
+                    _ => {
+                        eprintln!(
+                            "`--stack-size` takes a number of bytes, at most {}",
+                            vm::STACK_SIZE_LIMIT
+                        );
+                        std::process::exit(1);
+                    }
+                }
+                continue;
+            }
+            "-D" => args.next(),
+            _ if arg.starts_with("-D") => Some(arg[2..].to_string()),
+            _ if !arg.starts_with('-') && program.is_none() => {
+                program = Some(arg);
+                continue;
+            }
+            _ => {
+                eprintln!(
+                    "Unknown argument `{}`, usage: iridium [-D NAME[=VALUE]]... [--stack-size BYTES] [PROGRAM]",
+                    arg
+                );
+                std::process::exit(1);
+            }
+        };
+
+        match define.as_deref().map(parse_define) {
+            Some(Some((name, value))) => repl.define(name, value),
+            _ => {
+                eprintln!("`-D` takes NAME or NAME=VALUE, where VALUE is an integer");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // With a program, run it and exit with its exit code instead of
+    // starting the REPL.
+    match program {
+        Some(program) => std::process::exit(repl.run_file(&program)),
+        None => repl.run(),
+    }
+}
+
+/// `NAME` or `NAME=VALUE` of a `-D` argument, `NAME` alone defining it as 1.
+fn parse_define(define: &str) -> Option<(&str, i64)> {
+    let (name, value) = match define.find('=') {
+        Some(index) => (&define[..index], define[index + 1..].parse().ok()?),
+        None => (define, 1),
+    };
+
+    if name.is_empty() {
+        None
+    } else {
+        Some((name, value))
+    }
 }
@@ -0,0 +1,331 @@
+//! Relocatable object files, the output of assembling one module on its own,
+//! combined into a program by the `linker`.
+//!
+//! All integers are big-endian, like in `binary`.
+//!
+//! | offset | size | field                                         |
+//! |--------|------|-----------------------------------------------|
+//! | 0      | 4    | magic, `IROB`                                 |
+//! | 4      | 2    | format version                                |
+//! | 6      | 2    | flags, reserved and always zero               |
+//! | 8      | 4    | code section length                           |
+//! | 12     | 4    | read-only data section length                 |
+//! | 16     | 4    | code section alignment                        |
+//! | 20     | 4    | read-only data section alignment              |
+//! | 24     | 4    | number of symbols                             |
+//! | 28     | 4    | number of relocations                         |
+//! | 32     | 4    | CRC-32 of every other byte of the file        |
+//! | 36     |      | code section, then read-only data section     |
+//!
+//! The sections are followed by the symbols, each one a binding byte (0 for
+//! local, 1 for global, 2 for extern), a section byte (0 for code, 1 for data),
+//! a 4-byte offset, a 2-byte name length and the UTF-8 name. Then come the
+//! relocations: a section byte, a size byte, a 4-byte offset and the 4-byte
+//! index of the symbol whose address the field depends on.
+
+use crate::assembler::Section;
+use crate::binary::{checksum, malformed, read_u16, read_u32, BinaryError, Reader};
+
+pub const MAGIC: [u8; 4] = *b"IROB";
+pub const VERSION: u16 = 1;
+pub const HEADER_LEN: usize = 36;
+
+const CHECKSUM_OFFSET: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binding {
+    /// Only visible inside the object file.
+    Local,
+    /// Exported with `.global`.
+    Global,
+    /// Imported with `.extern`, defined by another object file.
+    Extern,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectSymbol {
+    pub name: String,
+    pub binding: Binding,
+    pub section: Section,
+    /// Offset from the start of `section`, zero for `Extern` symbols.
+    pub offset: u32,
+}
+
+/// A field holding the address of `symbol`, an index into
+/// `ObjectFile::symbols`, plus or minus a constant, that the linker patches
+/// once sections are placed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub section: Section,
+    /// Offset of the field from the start of `section`.
+    pub offset: u32,
+    /// Size of the field in bytes: 1, 2 or 4.
+    pub size: u8,
+    pub symbol: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectFile {
+    pub code: Vec<u8>,
+    pub ro_data: Vec<u8>,
+    /// Largest `.align` of each section, which the linker keeps when placing it.
+    pub code_align: u32,
+    pub ro_data_align: u32,
+    pub symbols: Vec<ObjectSymbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl Default for ObjectFile {
+    fn default() -> Self {
+        ObjectFile {
+            code: vec![],
+            ro_data: vec![],
+            code_align: 1,
+            ro_data_align: 1,
+            symbols: vec![],
+            relocations: vec![],
+        }
+    }
+}
+
+impl ObjectFile {
+    pub fn is_object(bytes: &[u8]) -> bool {
+        bytes.starts_with(&MAGIC)
+    }
+
+    pub fn section(&self, section: Section) -> &[u8] {
+        match section {
+            Section::Code => &self.code,
+            Section::Data => &self.ro_data,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(HEADER_LEN + self.code.len() + self.ro_data.len());
+
+        result.extend_from_slice(&MAGIC);
+        result.extend_from_slice(&VERSION.to_be_bytes());
+        result.extend_from_slice(&0u16.to_be_bytes());
+        result.extend_from_slice(&(self.code.len() as u32).to_be_bytes());
+        result.extend_from_slice(&(self.ro_data.len() as u32).to_be_bytes());
+        result.extend_from_slice(&self.code_align.to_be_bytes());
+        result.extend_from_slice(&self.ro_data_align.to_be_bytes());
+        result.extend_from_slice(&(self.symbols.len() as u32).to_be_bytes());
+        result.extend_from_slice(&(self.relocations.len() as u32).to_be_bytes());
+        result.extend_from_slice(&[0; 4]);
+        result.extend_from_slice(&self.code);
+        result.extend_from_slice(&self.ro_data);
+
+        for symbol in &self.symbols {
+            let binding = match symbol.binding {
+                Binding::Local => 0,
+                Binding::Global => 1,
+                Binding::Extern => 2,
+            };
+
+            result.push(binding);
+            result.push(section_byte(symbol.section));
+            result.extend_from_slice(&symbol.offset.to_be_bytes());
+            result.extend_from_slice(&(symbol.name.len() as u16).to_be_bytes());
+            result.extend_from_slice(symbol.name.as_bytes());
+        }
+
+        for relocation in &self.relocations {
+            result.push(section_byte(relocation.section));
+            result.push(relocation.size);
+            result.extend_from_slice(&relocation.offset.to_be_bytes());
+            result.extend_from_slice(&relocation.symbol.to_be_bytes());
+        }
+
+        let checksum = checksum(&result, CHECKSUM_OFFSET);
+        result[CHECKSUM_OFFSET..HEADER_LEN].copy_from_slice(&checksum.to_be_bytes());
+
+        result
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<ObjectFile, BinaryError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(BinaryError::Truncated {
+                expected: HEADER_LEN,
+                found: bytes.len(),
+            });
+        }
+
+        if bytes[0..4] != MAGIC {
+            return Err(BinaryError::BadMagic);
+        }
+
+        let version = read_u16(bytes, 4);
+
+        if version != VERSION {
+            return Err(BinaryError::UnsupportedVersion { version });
+        }
+
+        let flags = read_u16(bytes, 6);
+
+        if flags != 0 {
+            return Err(BinaryError::UnsupportedFlags { flags });
+        }
+
+        let found = read_u32(bytes, CHECKSUM_OFFSET);
+        let expected_checksum = checksum(bytes, CHECKSUM_OFFSET);
+
+        if found != expected_checksum {
+            return Err(BinaryError::ChecksumMismatch {
+                expected: expected_checksum,
+                found,
+            });
+        }
+
+        let mut reader = Reader::new(bytes, HEADER_LEN);
+        let mut object = ObjectFile {
+            code: reader.bytes(read_u32(bytes, 8) as usize)?.to_vec(),
+            ro_data: reader.bytes(read_u32(bytes, 12) as usize)?.to_vec(),
+            code_align: read_u32(bytes, 16),
+            ro_data_align: read_u32(bytes, 20),
+            ..ObjectFile::default()
+        };
+
+        if !object.code_align.is_power_of_two() || !object.ro_data_align.is_power_of_two() {
+            return Err(malformed("section alignment is not a power of two"));
+        }
+
+        for _ in 0..read_u32(bytes, 24) {
+            let binding = match reader.u8()? {
+                0 => Binding::Local,
+                1 => Binding::Global,
+                2 => Binding::Extern,
+                _ => return Err(malformed("unknown symbol binding")),
+            };
+            let section = section_from_byte(reader.u8()?)?;
+            let offset = reader.u32()?;
+            let len = reader.u16()? as usize;
+            let name = reader.string(len)?;
+
+            if offset as usize > object.section(section).len() {
+                return Err(malformed("symbol is outside of its section"));
+            }
+
+            object.symbols.push(ObjectSymbol {
+                name,
+                binding,
+                section,
+                offset,
+            });
+        }
+
+        for _ in 0..read_u32(bytes, 28) {
+            let section = section_from_byte(reader.u8()?)?;
+            let size = reader.u8()?;
+            let offset = reader.u32()?;
+            let symbol = reader.u32()?;
+
+            if ![1, 2, 4].contains(&size) {
+                return Err(malformed("relocation size is not 1, 2 or 4"));
+            }
+
+            if offset as usize + size as usize > object.section(section).len() {
+                return Err(malformed("relocation is outside of its section"));
+            }
+
+            if symbol as usize >= object.symbols.len() {
+                return Err(malformed(
+                    "relocation refers to a symbol that does not exist",
+                ));
+            }
+
+            object.relocations.push(Relocation {
+                section,
+                offset,
+                size,
+                symbol,
+            });
+        }
+
+        reader.finish()?;
+
+        Ok(object)
+    }
+}
+
+fn section_byte(section: Section) -> u8 {
+    match section {
+        Section::Code => 0,
+        Section::Data => 1,
+    }
+}
+
+fn section_from_byte(byte: u8) -> Result<Section, BinaryError> {
+    match byte {
+        0 => Ok(Section::Code),
+        1 => Ok(Section::Data),
+        _ => Err(malformed("unknown section")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_object() -> ObjectFile {
+        ObjectFile {
+            code: vec![0x01, 0x00, 0x00, 0x00, 0x00],
+            ro_data: vec![0x68, 0x69, 0x00],
+            code_align: 1,
+            ro_data_align: 4,
+            symbols: vec![
+                ObjectSymbol {
+                    name: "msg".to_string(),
+                    binding: Binding::Global,
+                    section: Section::Data,
+                    offset: 0,
+                },
+                ObjectSymbol {
+                    name: "print".to_string(),
+                    binding: Binding::Extern,
+                    section: Section::Code,
+                    offset: 0,
+                },
+            ],
+            relocations: vec![Relocation {
+                section: Section::Code,
+                offset: 2,
+                size: 2,
+                symbol: 1,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_object_round_trip() {
+        let bytes = test_object().to_bytes();
+        assert!(ObjectFile::is_object(&bytes));
+        assert_eq!(ObjectFile::from_bytes(&bytes), Ok(test_object()));
+        assert_eq!(
+            ObjectFile::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(BinaryError::ChecksumMismatch {
+                expected: checksum(&bytes[..bytes.len() - 1], CHECKSUM_OFFSET),
+                found: read_u32(&bytes, CHECKSUM_OFFSET),
+            })
+        );
+    }
+
+    #[test]
+    fn test_reject_malformed_object() {
+        let mut object = test_object();
+        object.relocations[0].offset = 4;
+        assert_eq!(
+            ObjectFile::from_bytes(&object.to_bytes()),
+            Err(malformed("relocation is outside of its section"))
+        );
+
+        let mut object = test_object();
+        object.relocations[0].symbol = 2;
+        assert_eq!(
+            ObjectFile::from_bytes(&object.to_bytes()),
+            Err(malformed(
+                "relocation refers to a symbol that does not exist"
+            ))
+        );
+    }
+}
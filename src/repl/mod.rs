@@ -1,17 +1,27 @@
-use crate::assembler::parser::program::parse_program;
-use crate::vm::VM;
-use nom::types::CompleteStr;
+use crate::assembler::assembler_errors::AssemblerError;
+use crate::assembler::sources::SourceMap;
+use crate::assembler::Assembler;
+use crate::binary::Binary;
+use crate::disassembler::disassemble_with;
+use crate::linker::Linker;
+use crate::object::ObjectFile;
+use crate::vm::{ExitStatus, VmError, VM};
 use std;
-use std::fs::File;
+use std::fs;
 use std::io;
-use std::io::{Read, Write};
+use std::io::Write;
 use std::path::Path;
 
+/// Calls of the stack printed along with a fault, innermost first.
+const MAX_CALLS_SHOWN: usize = 16;
+
 #[derive(Default)]
 #[allow(dead_code)]
 pub struct Repl {
     command_buffer: Vec<String>,
     vm: VM,
+    /// Constants every assembly starts with, from `-D` on the command line.
+    defines: Vec<(String, i64)>,
 }
 
 impl Repl {
@@ -19,9 +29,18 @@ impl Repl {
         Repl {
             vm: VM::new(),
             command_buffer: vec![],
+            defines: vec![],
         }
     }
 
+    pub fn define(&mut self, name: &str, value: i64) {
+        self.defines.push((name.to_string(), value));
+    }
+
+    pub fn set_stack_size(&mut self, size: usize) {
+        self.vm.set_stack_size(size);
+    }
+
     pub fn run(&mut self) {
         println!("Welcome to Iridium");
 
@@ -49,23 +68,37 @@ impl Repl {
                 ".program" => self.handle_program(),
                 ".register" => self.handle_registers(),
                 ".load_file" => self.handle_load_file(),
+                ".save_file" => self.handle_save_file(),
+                ".assemble_object" => self.handle_assemble_object(),
+                ".link" => self.handle_link(),
+                ".list_file" => self.handle_list_file(),
                 ".clear" => self.handle_clear(),
+                ".where" => println!("At {}", self.vm.describe(self.vm.pc())),
                 _ => {
-                    let parsed_program = parse_program(CompleteStr(buffer));
+                    let mut assembler = self.assembler();
+                    let result = assembler.assemble(buffer);
 
-                    if parsed_program.is_err() {
-                        println!("Unable to parse input");
-                        continue;
-                    }
+                    Repl::print_warnings(&assembler);
 
-                    let (_, result) = parsed_program.unwrap();
-                    let bytecode = result.to_bytes();
+                    let bytecode = match result {
+                        Ok(bytecode) => bytecode,
+                        Err(errors) => {
+                            Repl::print_errors(&errors, &assembler.sources);
+                            continue;
+                        }
+                    };
 
                     for byte in bytecode {
                         self.vm.add_byte(byte);
                     }
 
-                    self.vm.run_once();
+                    match self.vm.run_once() {
+                        Ok(Some(ExitStatus::Halted { code })) => {
+                            println!("Program halted with exit code {}", code)
+                        }
+                        Ok(_) => {}
+                        Err(e) => self.print_fault(&e),
+                    }
                 }
             }
         }
@@ -80,7 +113,7 @@ impl Repl {
     fn handle_program(&self) {
         println!("Listing instructions currently in VM's program vector:");
 
-        for instruction in &self.vm.program {
+        for instruction in disassemble_with(&self.vm.program, self.vm.debug_info()) {
             println!("{}", instruction);
         }
 
@@ -99,7 +132,194 @@ impl Repl {
     }
 
     fn handle_load_file(&mut self) {
-        print!("Please enter the path to the file you wish to load: ");
+        let filename = Repl::prompt("Please enter the path to the file you wish to load: ");
+
+        self.load_file(&filename);
+    }
+
+    /// Loads the program at `filename` and runs it, without a prompt. Returns
+    /// the exit code to pass on to the shell.
+    pub fn run_file(&mut self, filename: &str) -> i32 {
+        if !self.load_file(filename) {
+            return 1;
+        }
+
+        let status = self.vm.run();
+
+        if let ExitStatus::Faulted(e) = &status {
+            self.print_fault(e);
+        }
+
+        status.code()
+    }
+
+    /// Loads a binary into the VM, or assembles source and appends it to the
+    /// program. Returns whether it could.
+    fn load_file(&mut self, filename: &str) -> bool {
+        let contents = match fs::read(Path::new(filename)) {
+            Ok(contents) => contents,
+            Err(e) => {
+                println!("Unable to read file: {}", e);
+
+                return false;
+            }
+        };
+
+        if Binary::is_binary(&contents) {
+            if let Err(e) = self.vm.load_binary(&contents) {
+                println!("Unable to load binary: {}", e);
+
+                return false;
+            }
+
+            return true;
+        }
+
+        let contents = match String::from_utf8(contents) {
+            Ok(contents) => contents,
+            Err(_) => {
+                println!("File is neither an Iridium binary nor assembly source");
+
+                return false;
+            }
+        };
+
+        let mut assembler = self.assembler();
+        let result = assembler.assemble_file(Path::new(filename), &contents);
+
+        Repl::print_warnings(&assembler);
+
+        match result {
+            Ok(binary) => {
+                self.vm.append(binary);
+                true
+            }
+            Err(errors) => {
+                Repl::print_errors(&errors, &assembler.sources);
+                false
+            }
+        }
+    }
+
+    fn assembler(&self) -> Assembler {
+        let mut assembler = Assembler::new();
+
+        for (name, value) in &self.defines {
+            assembler.define(name, *value);
+        }
+
+        assembler
+    }
+
+    fn handle_save_file(&self) {
+        let filename = Repl::prompt("Please enter the path to save the program to: ");
+        let binary = Binary {
+            ro_data: self.vm.ro_data().to_vec(),
+            debug: self.vm.debug_info().cloned(),
+            ..Binary::new(self.vm.program.clone())
+        };
+
+        match fs::write(Path::new(&filename), binary.to_bytes()) {
+            Ok(()) => println!("Saved {} bytes of bytecode", binary.code.len()),
+            Err(e) => println!("Unable to write file: {}", e),
+        }
+    }
+
+    fn handle_list_file(&self) {
+        let filename = Repl::prompt("Please enter the path to the source file to list: ");
+        let contents = match fs::read_to_string(Path::new(&filename)) {
+            Ok(contents) => contents,
+            Err(e) => {
+                println!("Unable to read file: {}", e);
+
+                return;
+            }
+        };
+
+        let mut assembler = self.assembler();
+        assembler.emit_listing = true;
+
+        if let Err(errors) = assembler.assemble_file(Path::new(&filename), &contents) {
+            Repl::print_errors(&errors, &assembler.sources);
+        }
+
+        Repl::print_warnings(&assembler);
+
+        if let Some(listing) = assembler.listing {
+            print!("{}", listing);
+        }
+    }
+
+    fn handle_assemble_object(&self) {
+        let filename = Repl::prompt("Please enter the path to the source file to assemble: ");
+        let contents = match fs::read_to_string(Path::new(&filename)) {
+            Ok(contents) => contents,
+            Err(e) => {
+                println!("Unable to read file: {}", e);
+
+                return;
+            }
+        };
+
+        let mut assembler = self.assembler();
+        let result = assembler.assemble_object(Path::new(&filename), &contents);
+
+        Repl::print_warnings(&assembler);
+
+        let object = match result {
+            Ok(object) => object,
+            Err(errors) => {
+                Repl::print_errors(&errors, &assembler.sources);
+
+                return;
+            }
+        };
+
+        let output = Repl::prompt("Please enter the path to save the object file to: ");
+
+        match fs::write(Path::new(&output), object.to_bytes()) {
+            Ok(()) => println!(
+                "Saved object file with {} symbols and {} relocations",
+                object.symbols.len(),
+                object.relocations.len()
+            ),
+            Err(e) => println!("Unable to write file: {}", e),
+        }
+    }
+
+    fn handle_link(&mut self) {
+        let filenames = Repl::prompt(
+            "Please enter the paths of the object files to link, separated by spaces: ",
+        );
+        let mut linker = Linker::new();
+
+        for filename in filenames.split_whitespace() {
+            let object = fs::read(Path::new(filename))
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| ObjectFile::from_bytes(&bytes).map_err(|e| e.to_string()));
+
+            match object {
+                Ok(object) => linker.add(filename.to_string(), object),
+                Err(e) => {
+                    println!("Unable to load object file `{}`: {}", filename, e);
+
+                    return;
+                }
+            }
+        }
+
+        match linker.link() {
+            Ok(binary) => self.vm.load(binary),
+            Err(errors) => {
+                for error in errors {
+                    println!("error: {}", error);
+                }
+            }
+        }
+    }
+
+    fn prompt(message: &str) -> String {
+        print!("{}", message);
         io::stdout().flush().expect("Unable to flush stdout");
 
         let mut tmp = String::new();
@@ -108,24 +328,38 @@ impl Repl {
             .read_line(&mut tmp)
             .expect("Unable to read line from user");
 
-        let tmp = tmp.trim();
-        let filename = Path::new(&tmp);
-        let mut f = File::open(Path::new(&filename)).expect("File not found");
-        let mut contents = String::new();
+        tmp.trim().to_string()
+    }
+
+    fn print_errors(errors: &[AssemblerError], sources: &SourceMap) {
+        for error in errors {
+            println!("{}\n", error.render_in(sources));
+        }
+    }
 
-        f.read_to_string(&mut contents)
-            .expect("There was an error reading from the file");
-        
-        let program = match parse_program(CompleteStr(&contents)) {
-            Ok((_, program)) => program,
-            Err(e) => {
-                println!("Unable to parse input: {:?}", e);
+    fn print_fault(&self, error: &VmError) {
+        println!("Error: {}, at {}", error, self.vm.describe(error.pc()));
 
-                return;
-            }
-        };
+        let call_stack = self.vm.call_stack();
 
-        self.vm.program.append(&mut program.to_bytes());
+        // A return address is right after its call, so the byte before it is
+        // part of the call.
+        for return_address in call_stack.iter().take(MAX_CALLS_SHOWN) {
+            println!(
+                "  called from {}",
+                self.vm.describe(return_address.saturating_sub(1))
+            );
+        }
+
+        if call_stack.len() > MAX_CALLS_SHOWN {
+            println!("  and {} more calls", call_stack.len() - MAX_CALLS_SHOWN);
+        }
+    }
+
+    fn print_warnings(assembler: &Assembler) {
+        for warning in &assembler.warnings {
+            println!("{}\n", warning.render_warning_in(&assembler.sources));
+        }
     }
 
     #[allow(dead_code)]
@@ -134,7 +368,7 @@ impl Repl {
         let mut results: Vec<u8> = vec![];
 
         for hex_string in split {
-            let result = u8::from_str_radix(&hex_string, 16)?;
+            let result = u8::from_str_radix(hex_string, 16)?;
 
             results.push(result);
         }
@@ -1,218 +1,904 @@
+use super::binary::{Binary, BinaryError};
+use super::debug_info::DebugInfo;
 use super::instruction::Opcode;
+use std::fmt;
+
+pub const REGISTER_COUNT: usize = 32;
+/// Most bytes `aloc` lets the heap grow to.
+pub const HEAP_LIMIT: usize = 16 * 1024 * 1024;
+/// `hlt` exits with the value of this register, `$v0`.
+pub const EXIT_CODE_REGISTER: usize = 2;
+/// Bytes of stack a VM has unless it is given another size.
+pub const DEFAULT_STACK_SIZE: usize = 64 * 1024;
+/// Most bytes of stack a VM can be given.
+pub const STACK_SIZE_LIMIT: usize = HEAP_LIMIT;
+/// `$sp`, the offset of the top of the stack.
+pub const STACK_POINTER: usize = 29;
+/// `$fp`, the offset of the frame of the innermost call.
+pub const FRAME_POINTER: usize = 30;
+
+/// How a run of the VM ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExitStatus {
+    /// Stopped at a `hlt`, with the value of `EXIT_CODE_REGISTER`.
+    Halted { code: i32 },
+    /// Ran past the last instruction of the program.
+    RanOffEnd,
+    /// Stopped at an instruction that couldn't run.
+    Faulted(VmError),
+    /// Executed as many instructions as it was allowed to without stopping.
+    OutOfBudget,
+}
+
+impl ExitStatus {
+    /// The exit code to hand to the shell: the program's own when it halts,
+    /// zero when it runs off its end and one when it doesn't finish.
+    pub fn code(&self) -> i32 {
+        match self {
+            ExitStatus::Halted { code } => *code,
+            ExitStatus::RanOffEnd => 0,
+            ExitStatus::Faulted(_) | ExitStatus::OutOfBudget => 1,
+        }
+    }
+}
 
-#[derive(Debug, Default)]
+/// Why the VM stopped a program. Every error carries `pc`, the offset of the
+/// instruction at fault.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmError {
+    IllegalOpcode {
+        pc: usize,
+        opcode: u8,
+    },
+    /// The program ends in the middle of the instruction.
+    TruncatedInstruction {
+        pc: usize,
+    },
+    InvalidRegister {
+        pc: usize,
+        register: u8,
+    },
+    DivisionByZero {
+        pc: usize,
+    },
+    JumpOutOfBounds {
+        pc: usize,
+        target: i64,
+    },
+    NegativeAllocation {
+        pc: usize,
+        bytes: i32,
+    },
+    /// `aloc` would grow the heap past `HEAP_LIMIT`.
+    OutOfMemory {
+        pc: usize,
+        bytes: i32,
+    },
+    /// A load of `size` bytes at `address` reaches outside of the read-only
+    /// data segment.
+    DataOutOfBounds {
+        pc: usize,
+        address: i64,
+        size: usize,
+        data_len: usize,
+    },
+    /// A load or store of `size` bytes at `address` reaches outside of the heap.
+    HeapOutOfBounds {
+        pc: usize,
+        address: i64,
+        size: usize,
+        heap_len: usize,
+    },
+    /// A load or store of `size` bytes at `address` reaches outside of the
+    /// stack.
+    StackOutOfBounds {
+        pc: usize,
+        address: i64,
+        size: usize,
+        stack_len: usize,
+    },
+    /// A push or a call would grow the stack past its size.
+    StackOverflow {
+        pc: usize,
+        size: usize,
+    },
+    /// A pop or a return found nothing on the stack, or `$sp` points above it.
+    StackUnderflow {
+        pc: usize,
+    },
+}
+
+impl VmError {
+    pub fn pc(&self) -> usize {
+        use self::VmError::*;
+
+        match self {
+            IllegalOpcode { pc, .. }
+            | TruncatedInstruction { pc }
+            | InvalidRegister { pc, .. }
+            | DivisionByZero { pc }
+            | JumpOutOfBounds { pc, .. }
+            | NegativeAllocation { pc, .. }
+            | OutOfMemory { pc, .. }
+            | DataOutOfBounds { pc, .. }
+            | HeapOutOfBounds { pc, .. }
+            | StackOutOfBounds { pc, .. }
+            | StackOverflow { pc, .. }
+            | StackUnderflow { pc } => *pc,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::VmError::*;
+
+        match self {
+            IllegalOpcode { opcode, .. } => write!(f, "illegal opcode 0x{:02X}", opcode),
+            TruncatedInstruction { .. } => {
+                write!(f, "the program ends in the middle of an instruction")
+            }
+            InvalidRegister { register, .. } => write!(
+                f,
+                "register ${} does not exist, registers go from $0 to ${}",
+                register,
+                REGISTER_COUNT - 1
+            ),
+            DivisionByZero { .. } => write!(f, "division by zero"),
+            JumpOutOfBounds { target, .. } => {
+                write!(f, "jump to offset {}, outside of the program", target)
+            }
+            NegativeAllocation { bytes, .. } => write!(f, "allocation of {} bytes", bytes),
+            OutOfMemory { bytes, .. } => write!(
+                f,
+                "allocation of {} bytes would grow the heap past {} bytes",
+                bytes, HEAP_LIMIT
+            ),
+            DataOutOfBounds {
+                address,
+                size,
+                data_len,
+                ..
+            } => write!(
+                f,
+                "read of {} bytes at address {}, outside of the {} byte data segment",
+                size, address, data_len
+            ),
+            HeapOutOfBounds {
+                address,
+                size,
+                heap_len,
+                ..
+            } => write!(
+                f,
+                "access of {} bytes at address {}, outside of the {} byte heap",
+                size, address, heap_len
+            ),
+            StackOutOfBounds {
+                address,
+                size,
+                stack_len,
+                ..
+            } => write!(
+                f,
+                "access of {} bytes at address {}, outside of the {} byte stack",
+                size, address, stack_len
+            ),
+            StackOverflow { size, .. } => {
+                write!(f, "stack overflow, the stack holds {} bytes", size)
+            }
+            StackUnderflow { .. } => write!(f, "stack underflow, there is nothing to pop"),
+        }
+    }
+}
+
+/// Memory that loads and stores address, each from zero.
+#[derive(Debug, Clone, Copy)]
+enum Segment {
+    /// The read-only data segment.
+    Data,
+    Heap,
+    Stack,
+}
+
+/// The stack is memory of its own, apart from the heap. It grows down from
+/// its size, and `$sp` and `$fp` start there, at the top of an empty stack.
+/// `call` pushes the address to return to and `$fp`, then points `$fp` at the
+/// top of the stack, where the frame of the callee starts. `ret` moves `$sp`
+/// back to `$fp`, dropping whatever the callee left on the stack, and pops
+/// both. Values on the stack are words, big-endian like everywhere else, and
+/// `lws` and `sws` reach them at an offset from a register, such as a word of
+/// the frame at an offset from `$sp` or `$fp`.
+#[derive(Debug)]
 pub struct VM {
-    pub registers: [i32; 32],
+    pub registers: [i32; REGISTER_COUNT],
     pc: usize,
     pub program: Vec<u8>,
     remainder: u32,
     equal_flag: bool,
     heap: Vec<u8>,
+    stack: Vec<u8>,
+    ro_data: Vec<u8>,
+    debug: Option<DebugInfo>,
+    /// Offset of the instruction being executed, which errors point at.
+    instruction_pc: usize,
 }
 
 impl VM {
     pub fn new() -> Self {
-        VM {
-            registers: [0; 32],
+        let mut vm = VM {
+            registers: [0; REGISTER_COUNT],
             pc: 0,
             program: vec![],
             remainder: 0,
             equal_flag: false,
             heap: vec![],
+            stack: vec![],
+            ro_data: vec![],
+            debug: None,
+            instruction_pc: 0,
+        };
+
+        vm.set_stack_size(DEFAULT_STACK_SIZE);
+        vm
+    }
+
+    /// Replaces the stack with an empty one of `size` bytes, at most
+    /// `STACK_SIZE_LIMIT`, and moves `$sp` and `$fp` to its top.
+    pub fn set_stack_size(&mut self, size: usize) {
+        assert!(size <= STACK_SIZE_LIMIT, "stack of {} bytes", size);
+
+        self.stack = vec![0; size];
+        self.registers[STACK_POINTER] = size as i32;
+        self.registers[FRAME_POINTER] = size as i32;
+    }
+
+    /// The address every call on the stack returns to, innermost first, found
+    /// by following `$fp` from frame to frame.
+    pub fn call_stack(&self) -> Vec<usize> {
+        let mut result = vec![];
+        let mut frame = self.registers[FRAME_POINTER];
+
+        // Outer frames are higher up. Anything else isn't a frame `call`
+        // made, so the walk stops there.
+        while let (Some(outer), Some(return_address)) = (
+            self.stack_word(frame),
+            self.stack_word(frame.wrapping_add(4)),
+        ) {
+            if outer <= frame {
+                break;
+            }
+
+            result.push(return_address as usize);
+            frame = outer;
         }
+
+        result
+    }
+
+    /// Replaces the program with the one in `bytes`, a file written by
+    /// `Binary::to_bytes`, and moves the program counter to its entry point.
+    pub fn load_binary(&mut self, bytes: &[u8]) -> Result<(), BinaryError> {
+        self.load(Binary::from_bytes(bytes)?);
+
+        Ok(())
     }
 
-    pub fn run(&mut self) {
-        while self.execute_instruction() {}
+    /// Replaces the program with the code of `binary` and the read-only data
+    /// segment with its data section, and moves the program counter to its
+    /// entry point.
+    pub fn load(&mut self, binary: Binary) {
+        self.program = binary.code;
+        self.ro_data = binary.ro_data;
+        self.debug = binary.debug;
+        self.pc = binary.entry as usize;
     }
 
-    pub fn run_once(&mut self) {
-        self.execute_instruction();
+    /// Appends the code of `binary` to the program, like typed instructions,
+    /// and replaces the read-only data segment with its data section. The
+    /// program counter stays where it is, and the debug information is only
+    /// kept when nothing was loaded before, since its offsets start at zero.
+    pub fn append(&mut self, mut binary: Binary) {
+        self.debug = if self.program.is_empty() {
+            binary.debug
+        } else {
+            None
+        };
+        self.program.append(&mut binary.code);
+        self.ro_data = binary.ro_data;
     }
 
-    fn execute_instruction(&mut self) -> bool {
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Debug information of the loaded program, if it came with any.
+    pub fn debug_info(&self) -> Option<&DebugInfo> {
+        self.debug.as_ref()
+    }
+
+    /// Where `pc` is in the source of the program, as `foo.iasm:42 (in label)`,
+    /// or just the offset when there is no debug information for it.
+    pub fn describe(&self, pc: usize) -> String {
+        self.debug
+            .as_ref()
+            .and_then(|debug| debug.describe(pc))
+            .unwrap_or_else(|| format!("offset {}", pc))
+    }
+
+    /// The read-only data segment, addressed from zero by data labels.
+    pub fn ro_data(&self) -> &[u8] {
+        &self.ro_data
+    }
+
+    /// Runs the program until it halts, runs past its end or faults.
+    pub fn run(&mut self) -> ExitStatus {
+        loop {
+            match self.run_once() {
+                Ok(Some(status)) => return status,
+                Ok(None) => {}
+                Err(e) => return ExitStatus::Faulted(e),
+            }
+        }
+    }
+
+    /// Like `run`, but gives up after executing `budget` instructions.
+    pub fn run_with_budget(&mut self, budget: u64) -> ExitStatus {
+        for _ in 0..budget {
+            match self.run_once() {
+                Ok(Some(status)) => return status,
+                Ok(None) => {}
+                Err(e) => return ExitStatus::Faulted(e),
+            }
+        }
+
+        if self.pc >= self.program.len() {
+            ExitStatus::RanOffEnd
+        } else {
+            ExitStatus::OutOfBudget
+        }
+    }
+
+    /// Executes the instruction at `pc`. Returns how the program ended if it
+    /// did, by halting or because there is no instruction left.
+    pub fn run_once(&mut self) -> Result<Option<ExitStatus>, VmError> {
         if self.pc >= self.program.len() {
-            return false;
+            return Ok(Some(ExitStatus::RanOffEnd));
         }
 
         use super::instruction::Opcode::*;
 
+        self.instruction_pc = self.pc;
+
         match self.decode_opcode() {
-            HLT => return self.handle_hlt(),
-            LOAD => self.handle_load(),
-            ADD => self.handle_add(),
-            SUB => self.handle_sub(),
-            MUL => self.handle_mul(),
-            DIV => self.handle_div(),
-            JMP => self.handle_jmp(),
-            JMPF => self.handle_jmpf(),
-            JMPB => self.handle_jmpb(),
-            EQ => self.handle_eq(),
-            NEQ => self.handle_neq(),
-            GT => self.handle_gt(),
-            LT => self.handle_lt(),
-            GTQ => self.handle_gtq(),
-            LTQ => self.handle_ltq(),
-            JEQ => self.handle_jeq(),
-            JNEQ => self.handle_jneq(),
-            ALOC => self.handle_aloc(),
-            op => {
-                println!("Unexpected {} opcode at {}", op, self.pc);
-
-                return false;
+            HLT => return Ok(Some(self.handle_hlt())),
+            LOAD => self.handle_load()?,
+            ADD => self.handle_add()?,
+            SUB => self.handle_sub()?,
+            MUL => self.handle_mul()?,
+            DIV => self.handle_div()?,
+            JMP => self.handle_jmp()?,
+            JMPF => self.handle_jmpf()?,
+            JMPB => self.handle_jmpb()?,
+            EQ => self.handle_eq()?,
+            NEQ => self.handle_neq()?,
+            GT => self.handle_gt()?,
+            LT => self.handle_lt()?,
+            GTQ => self.handle_gtq()?,
+            LTQ => self.handle_ltq()?,
+            JEQ => self.handle_jeq()?,
+            JNEQ => self.handle_jneq()?,
+            ALOC => self.handle_aloc()?,
+            LOADS => self.handle_loads()?,
+            LBD => self.handle_lbd()?,
+            LHD => self.handle_lhd()?,
+            LWD => self.handle_lwd()?,
+            LB => self.handle_lb()?,
+            LH => self.handle_lh()?,
+            LW => self.handle_lw()?,
+            SB => self.handle_sb()?,
+            SH => self.handle_sh()?,
+            SW => self.handle_sw()?,
+            PUSH => self.handle_push()?,
+            POP => self.handle_pop()?,
+            CALL => self.handle_call()?,
+            CALLR => self.handle_callr()?,
+            RET => self.handle_ret()?,
+            LWS => self.handle_lws()?,
+            SWS => self.handle_sws()?,
+            AND => self.handle_and()?,
+            OR => self.handle_or()?,
+            XOR => self.handle_xor()?,
+            NOT => self.handle_not()?,
+            SHL => self.handle_shl()?,
+            SHR => self.handle_shr()?,
+            SAR => self.handle_sar()?,
+            MOD => self.handle_mod()?,
+            MFREM => self.handle_mfrem()?,
+            IGL(opcode) => {
+                return Err(VmError::IllegalOpcode {
+                    pc: self.instruction_pc,
+                    opcode,
+                })
             }
         }
 
-        true
+        Ok(None)
     }
 
-    fn handle_hlt(&self) -> bool {
-        println!("HLT encountered");
+    fn handle_hlt(&self) -> ExitStatus {
+        ExitStatus::Halted {
+            code: self.registers[EXIT_CODE_REGISTER],
+        }
+    }
+
+    /// Loads an unsigned immediate, zero-extended: `#0` to `#65535`.
+    fn handle_load(&mut self) -> Result<(), VmError> {
+        let register = self.next_register()?;
+        let number = self.next_16_bits()?;
+
+        self.registers[register] = i32::from(number);
 
-        false
+        Ok(())
     }
 
-    fn handle_load(&mut self) {
-        let register = self.next_8_bits() as usize;
-        let number = self.next_16_bits() as u16;
+    /// Loads a signed immediate, sign-extended: `#-32768` to `#32767`.
+    fn handle_loads(&mut self) -> Result<(), VmError> {
+        let register = self.next_register()?;
+        let number = self.next_16_bits()? as i16;
 
         self.registers[register] = i32::from(number);
+
+        Ok(())
+    }
+
+    // Arithmetic wraps around on overflow, as in two's complement hardware.
+    fn handle_add(&mut self) -> Result<(), VmError> {
+        self.arithmetic(i32::wrapping_add)
     }
 
-    // TODO (xeqlol): keep DRY
-    fn handle_add(&mut self) {
-        let (register1, register2) = self.read_next_2_registers();
+    fn handle_sub(&mut self) -> Result<(), VmError> {
+        self.arithmetic(i32::wrapping_sub)
+    }
 
-        self.registers[self.next_8_bits() as usize] = register1 + register2;
+    fn handle_mul(&mut self) -> Result<(), VmError> {
+        self.arithmetic(i32::wrapping_mul)
     }
 
-    fn handle_sub(&mut self) {
-        let (register1, register2) = self.read_next_2_registers();
+    fn handle_div(&mut self) -> Result<(), VmError> {
+        let (register1, register2) = self.read_next_2_registers()?;
+        let destination = self.next_register()?;
+
+        if register2 == 0 {
+            return Err(VmError::DivisionByZero {
+                pc: self.instruction_pc,
+            });
+        }
+
+        self.registers[destination] = register1.wrapping_div(register2);
+        self.remainder = register1.wrapping_rem(register2) as u32;
 
-        self.registers[self.next_8_bits() as usize] = register1 - register2;
+        Ok(())
     }
 
-    fn handle_mul(&mut self) {
-        let (register1, register2) = self.read_next_2_registers();
+    fn handle_and(&mut self) -> Result<(), VmError> {
+        self.arithmetic(|a, b| a & b)
+    }
+
+    fn handle_or(&mut self) -> Result<(), VmError> {
+        self.arithmetic(|a, b| a | b)
+    }
 
-        self.registers[self.next_8_bits() as usize] = register1 * register2;
+    fn handle_xor(&mut self) -> Result<(), VmError> {
+        self.arithmetic(|a, b| a ^ b)
     }
 
-    fn handle_div(&mut self) {
-        let (register1, register2) = self.read_next_2_registers();
+    fn handle_not(&mut self) -> Result<(), VmError> {
+        let value = self.registers[self.next_register()?];
+
+        self.registers[self.next_register()?] = !value;
 
-        self.registers[self.next_8_bits() as usize] = register1 / register2;
-        self.remainder = (register1 % register2) as u32;
+        Ok(())
     }
 
-    fn handle_jmp(&mut self) {
-        let target = self.registers[self.next_8_bits() as usize];
+    // Shifts only use the low five bits of the amount, so they shift by 0 to
+    // 31 bits.
+    fn handle_shl(&mut self) -> Result<(), VmError> {
+        self.arithmetic(|a, b| a.wrapping_shl(b as u32))
+    }
 
-        self.pc = target as usize;
+    fn handle_shr(&mut self) -> Result<(), VmError> {
+        self.arithmetic(|a, b| (a as u32).wrapping_shr(b as u32) as i32)
+    }
+
+    fn handle_sar(&mut self) -> Result<(), VmError> {
+        self.arithmetic(|a, b| a.wrapping_shr(b as u32))
     }
 
-    fn handle_jmpf(&mut self) {
-        let target = self.registers[self.next_8_bits() as usize];
+    /// Unlike the remainder of `div`, which has the sign of the dividend, the
+    /// result of `mod` is never negative: `-7 mod 3` is 2.
+    fn handle_mod(&mut self) -> Result<(), VmError> {
+        let (register1, register2) = self.read_next_2_registers()?;
+        let destination = self.next_register()?;
 
-        self.pc += target as usize;
+        if register2 == 0 {
+            return Err(VmError::DivisionByZero {
+                pc: self.instruction_pc,
+            });
+        }
+
+        self.registers[destination] = register1.wrapping_rem_euclid(register2);
+
+        Ok(())
+    }
+
+    fn handle_mfrem(&mut self) -> Result<(), VmError> {
+        self.registers[self.next_register()?] = self.remainder as i32;
+
+        Ok(())
     }
 
-    fn handle_jmpb(&mut self) {
-        let target = self.registers[self.next_8_bits() as usize];
+    fn handle_jmp(&mut self) -> Result<(), VmError> {
+        let target = self.registers[self.next_register()?];
 
-        self.pc -= target as usize;
+        self.jump(i64::from(target))
     }
 
-    fn handle_eq(&mut self) {
-        let (register1, register2) = self.read_next_2_registers();
+    fn handle_jmpf(&mut self) -> Result<(), VmError> {
+        let target = self.registers[self.next_register()?];
+
+        self.jump(self.pc as i64 + i64::from(target))
+    }
 
-        self.equal_flag = register1 == register2;
+    fn handle_jmpb(&mut self) -> Result<(), VmError> {
+        let target = self.registers[self.next_register()?];
 
-        self.next_8_bits();
+        self.jump(self.pc as i64 - i64::from(target))
     }
 
-    fn handle_neq(&mut self) {
-        let (register1, register2) = self.read_next_2_registers();
+    fn handle_eq(&mut self) -> Result<(), VmError> {
+        self.comparison(|a, b| a == b)
+    }
 
-        self.equal_flag = register1 != register2;
+    fn handle_neq(&mut self) -> Result<(), VmError> {
+        self.comparison(|a, b| a != b)
+    }
 
-        self.next_8_bits();
+    fn handle_gt(&mut self) -> Result<(), VmError> {
+        self.comparison(|a, b| a > b)
     }
 
-    fn handle_gt(&mut self) {
-        let (register1, register2) = self.read_next_2_registers();
+    fn handle_lt(&mut self) -> Result<(), VmError> {
+        self.comparison(|a, b| a < b)
+    }
 
-        self.equal_flag = register1 > register2;
+    fn handle_gtq(&mut self) -> Result<(), VmError> {
+        self.comparison(|a, b| a >= b)
+    }
 
-        self.next_8_bits();
+    fn handle_ltq(&mut self) -> Result<(), VmError> {
+        self.comparison(|a, b| a <= b)
     }
 
-    fn handle_lt(&mut self) {
-        let (register1, register2) = self.read_next_2_registers();
+    fn handle_jeq(&mut self) -> Result<(), VmError> {
+        let target = self.registers[self.next_register()?];
 
-        self.equal_flag = register1 < register2;
+        if self.equal_flag {
+            self.jump(i64::from(target))?;
+        }
 
-        self.next_8_bits();
+        Ok(())
     }
 
-    fn handle_gtq(&mut self) {
-        let (register1, register2) = self.read_next_2_registers();
+    fn handle_jneq(&mut self) -> Result<(), VmError> {
+        let target = self.registers[self.next_register()?];
 
-        self.equal_flag = register1 >= register2;
+        if !self.equal_flag {
+            self.jump(i64::from(target))?;
+        }
 
-        self.next_8_bits();
+        Ok(())
     }
 
-    fn handle_ltq(&mut self) {
-        let (register1, register2) = self.read_next_2_registers();
+    fn handle_aloc(&mut self) -> Result<(), VmError> {
+        let bytes = self.registers[self.next_register()?];
+        let pc = self.instruction_pc;
 
-        self.equal_flag = register1 <= register2;
+        if bytes < 0 {
+            return Err(VmError::NegativeAllocation { pc, bytes });
+        }
+
+        let new_end = self.heap.len() + bytes as usize;
+
+        if new_end > HEAP_LIMIT {
+            return Err(VmError::OutOfMemory { pc, bytes });
+        }
 
-        self.next_8_bits();
+        self.heap.resize(new_end, 0);
+
+        Ok(())
     }
 
-    fn handle_jeq(&mut self) {
-        let target = self.registers[self.next_8_bits() as usize];
+    // Memory accesses are big-endian, like immediates, and loads of bytes and
+    // halfwords are sign-extended.
+    fn handle_lbd(&mut self) -> Result<(), VmError> {
+        self.load_memory(Segment::Data, 1)
+    }
 
-        if self.equal_flag {
-            self.pc = target as usize;
+    fn handle_lhd(&mut self) -> Result<(), VmError> {
+        self.load_memory(Segment::Data, 2)
+    }
+
+    fn handle_lwd(&mut self) -> Result<(), VmError> {
+        self.load_memory(Segment::Data, 4)
+    }
+
+    fn handle_lb(&mut self) -> Result<(), VmError> {
+        self.load_memory(Segment::Heap, 1)
+    }
+
+    fn handle_lh(&mut self) -> Result<(), VmError> {
+        self.load_memory(Segment::Heap, 2)
+    }
+
+    fn handle_lw(&mut self) -> Result<(), VmError> {
+        self.load_memory(Segment::Heap, 4)
+    }
+
+    fn handle_sb(&mut self) -> Result<(), VmError> {
+        self.store_memory(Segment::Heap, 1)
+    }
+
+    fn handle_sh(&mut self) -> Result<(), VmError> {
+        self.store_memory(Segment::Heap, 2)
+    }
+
+    fn handle_sw(&mut self) -> Result<(), VmError> {
+        self.store_memory(Segment::Heap, 4)
+    }
+
+    fn handle_lws(&mut self) -> Result<(), VmError> {
+        self.load_memory(Segment::Stack, 4)
+    }
+
+    fn handle_sws(&mut self) -> Result<(), VmError> {
+        self.store_memory(Segment::Stack, 4)
+    }
+
+    /// Loads `size` bytes of `segment` into the value register, from the
+    /// address in the base register plus the offset.
+    fn load_memory(&mut self, segment: Segment, size: usize) -> Result<(), VmError> {
+        let register = self.next_register()?;
+        let address = self.address(segment, size)?;
+        let value = self.memory(segment)[address..address + size]
+            .iter()
+            .fold(0, |value, byte| (value << 8) | i32::from(*byte));
+        let unused_bits = 32 - 8 * size;
+
+        self.registers[register] = (value << unused_bits) >> unused_bits;
+
+        Ok(())
+    }
+
+    fn store_memory(&mut self, segment: Segment, size: usize) -> Result<(), VmError> {
+        let value = self.registers[self.next_register()?];
+        let address = self.address(segment, size)?;
+        let memory = match segment {
+            Segment::Data => unreachable!("the data segment is read-only"),
+            Segment::Heap => &mut self.heap,
+            Segment::Stack => &mut self.stack,
+        };
+
+        memory[address..address + size].copy_from_slice(&value.to_be_bytes()[4 - size..]);
+
+        Ok(())
+    }
+
+    fn memory(&self, segment: Segment) -> &[u8] {
+        match segment {
+            Segment::Data => &self.ro_data,
+            Segment::Heap => &self.heap,
+            Segment::Stack => &self.stack,
+        }
+    }
+
+    /// Reads the base register and the offset of an access of `size` bytes to
+    /// `segment`, and checks that it stays within it.
+    fn address(&mut self, segment: Segment, size: usize) -> Result<usize, VmError> {
+        let base = self.registers[self.next_register()?];
+        let offset = self.next_16_bits()? as i16;
+        let address = i64::from(base) + i64::from(offset);
+        let pc = self.instruction_pc;
+        let len = self.memory(segment).len();
+
+        if address < 0 || address + size as i64 > len as i64 {
+            return Err(match segment {
+                Segment::Data => VmError::DataOutOfBounds {
+                    pc,
+                    address,
+                    size,
+                    data_len: len,
+                },
+                Segment::Heap => VmError::HeapOutOfBounds {
+                    pc,
+                    address,
+                    size,
+                    heap_len: len,
+                },
+                Segment::Stack => VmError::StackOutOfBounds {
+                    pc,
+                    address,
+                    size,
+                    stack_len: len,
+                },
+            });
         }
+
+        Ok(address as usize)
     }
 
-    fn handle_jneq(&mut self) {
-        let target = self.registers[self.next_8_bits() as usize];
+    fn handle_push(&mut self) -> Result<(), VmError> {
+        let value = self.registers[self.next_register()?];
 
-        if !self.equal_flag {
-            self.pc = target as usize;
+        self.push(value)
+    }
+
+    fn handle_pop(&mut self) -> Result<(), VmError> {
+        let register = self.next_register()?;
+
+        self.registers[register] = self.pop()?;
+
+        Ok(())
+    }
+
+    fn handle_call(&mut self) -> Result<(), VmError> {
+        let target = self.next_16_bits()?;
+
+        self.call(i64::from(target))
+    }
+
+    fn handle_callr(&mut self) -> Result<(), VmError> {
+        let target = self.registers[self.next_register()?];
+
+        self.call(i64::from(target))
+    }
+
+    fn handle_ret(&mut self) -> Result<(), VmError> {
+        self.registers[STACK_POINTER] = self.registers[FRAME_POINTER];
+        self.registers[FRAME_POINTER] = self.pop()?;
+        let return_address = self.pop()?;
+
+        self.jump(i64::from(return_address))
+    }
+
+    fn call(&mut self, target: i64) -> Result<(), VmError> {
+        self.push(self.pc as i32)?;
+        self.push(self.registers[FRAME_POINTER])?;
+        self.registers[FRAME_POINTER] = self.registers[STACK_POINTER];
+
+        self.jump(target)
+    }
+
+    fn push(&mut self, value: i32) -> Result<(), VmError> {
+        let address = i64::from(self.registers[STACK_POINTER]) - 4;
+
+        if address < 0 {
+            return Err(VmError::StackOverflow {
+                pc: self.instruction_pc,
+                size: self.stack.len(),
+            });
+        }
+
+        let address = self.stack_address(address)?;
+
+        self.stack[address..address + 4].copy_from_slice(&value.to_be_bytes());
+        self.registers[STACK_POINTER] = address as i32;
+
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<i32, VmError> {
+        let address = self.stack_address(i64::from(self.registers[STACK_POINTER]))?;
+        let mut value = [0; 4];
+
+        value.copy_from_slice(&self.stack[address..address + 4]);
+        self.registers[STACK_POINTER] = address as i32 + 4;
+
+        Ok(i32::from_be_bytes(value))
+    }
+
+    /// Checks that a word at `address` is on the stack.
+    fn stack_address(&self, address: i64) -> Result<usize, VmError> {
+        if address < 0 || address + 4 > self.stack.len() as i64 {
+            return Err(VmError::StackUnderflow {
+                pc: self.instruction_pc,
+            });
+        }
+
+        Ok(address as usize)
+    }
+
+    fn stack_word(&self, address: i32) -> Option<i32> {
+        if address < 0 {
+            return None;
+        }
+
+        let bytes = self.stack.get(address as usize..address as usize + 4)?;
+
+        Some(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Operations of two source registers, then the destination.
+    fn arithmetic(&mut self, op: fn(i32, i32) -> i32) -> Result<(), VmError> {
+        let (register1, register2) = self.read_next_2_registers()?;
+
+        self.registers[self.next_register()?] = op(register1, register2);
+
+        Ok(())
+    }
+
+    /// Comparisons: two registers, then a padding byte.
+    fn comparison(&mut self, op: fn(&i32, &i32) -> bool) -> Result<(), VmError> {
+        let (register1, register2) = self.read_next_2_registers()?;
+
+        self.equal_flag = op(&register1, &register2);
+        self.next_8_bits()?;
+
+        Ok(())
+    }
+
+    /// Moves `pc` to `target`. The end of the program is a valid target, where
+    /// it stops as if it had run to it.
+    fn jump(&mut self, target: i64) -> Result<(), VmError> {
+        if target < 0 || target > self.program.len() as i64 {
+            return Err(VmError::JumpOutOfBounds {
+                pc: self.instruction_pc,
+                target,
+            });
         }
+
+        self.pc = target as usize;
+
+        Ok(())
     }
 
-    fn handle_aloc(&mut self) {
-        let bytes = self.registers[self.next_8_bits() as usize];
-        let new_end = self.heap.len() as i32 + bytes;
+    fn read_next_2_registers(&mut self) -> Result<(i32, i32), VmError> {
+        let register1 = self.registers[self.next_register()?];
+        let register2 = self.registers[self.next_register()?];
 
-        self.heap.resize(new_end as usize, 0);
+        Ok((register1, register2))
     }
 
-    fn read_next_2_registers(&mut self) -> (i32, i32) {
-        let register1 = self.registers[self.next_8_bits() as usize];
-        let register2 = self.registers[self.next_8_bits() as usize];
+    fn next_register(&mut self) -> Result<usize, VmError> {
+        let register = self.next_8_bits()?;
+
+        if usize::from(register) >= REGISTER_COUNT {
+            return Err(VmError::InvalidRegister {
+                pc: self.instruction_pc,
+                register,
+            });
+        }
 
-        (register1, register2)
+        Ok(usize::from(register))
     }
 
-    fn next_8_bits(&mut self) -> u8 {
-        let result = self.program[self.pc];
+    fn next_8_bits(&mut self) -> Result<u8, VmError> {
+        let result = *self
+            .program
+            .get(self.pc)
+            .ok_or(VmError::TruncatedInstruction {
+                pc: self.instruction_pc,
+            })?;
         self.pc += 1;
 
-        result
+        Ok(result)
     }
 
-    fn next_16_bits(&mut self) -> u16 {
-        let result = (u16::from(self.program[self.pc]) << 8) | u16::from(self.program[self.pc + 1]);
-        self.pc += 2;
+    fn next_16_bits(&mut self) -> Result<u16, VmError> {
+        let high = self.next_8_bits()?;
+        let low = self.next_8_bits()?;
 
-        result
+        Ok((u16::from(high) << 8) | u16::from(low))
     }
 
     fn decode_opcode(&mut self) -> Opcode {
@@ -227,9 +913,17 @@ impl VM {
     }
 }
 
+impl Default for VM {
+    fn default() -> Self {
+        VM::new()
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
+    use crate::assembler::Assembler;
 
     #[test]
     fn test_create_vm() {
@@ -238,19 +932,94 @@ mod tests {
     }
 
     #[test]
-    fn test_opcode_hlt() {
+    fn test_load_binary() {
+        let mut test_vm = VM::new();
+        let binary = Binary {
+            entry: 4,
+            code: vec![0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0xF4],
+            ro_data: vec![0x01],
+            debug: None,
+        };
+        test_vm.load_binary(&binary.to_bytes()).unwrap();
+        assert_eq!(test_vm.pc, 4);
+        assert_eq!(test_vm.ro_data, vec![0x01]);
+        test_vm.run();
+        assert_eq!(test_vm.registers[0], 500);
+
+        let mut bytes = binary.to_bytes();
+        bytes.pop();
+        assert!(test_vm.load_binary(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_load_sections() {
+        let mut test_vm = VM::new();
+        let binary = Assembler::new()
+            .assemble_binary(".data\nzero: .byte 0\nmsg: .asciiz \"hi\"\n.code\nload $0 @msg\nhlt")
+            .unwrap();
+        test_vm.load(binary);
+        test_vm.run();
+
+        assert_eq!(test_vm.ro_data(), &[0x00, b'h', b'i', 0x00]);
+        assert_eq!(test_vm.program.len(), 5);
+        assert_eq!(test_vm.registers[0], 1);
+        assert_eq!(test_vm.describe(4), "<input>:6");
+        assert_eq!(test_vm.describe(5), "offset 5");
+    }
+
+    #[test]
+    fn test_append() {
+        let mut test_vm = VM::new();
+        test_vm.append(Assembler::new().assemble_binary("load $0 #1").unwrap());
+        assert!(test_vm.debug_info().is_some());
+        test_vm.run_once().unwrap();
+        test_vm.append(Assembler::new().assemble_binary("load $1 #2\nhlt").unwrap());
+        assert_eq!(test_vm.pc, 4);
+        assert_eq!(test_vm.program.len(), 9);
+        assert!(test_vm.debug_info().is_none());
+        test_vm.run();
+        assert_eq!(test_vm.registers[0], 1);
+        assert_eq!(test_vm.registers[1], 2);
+    }
+
+    #[test]
+    fn test_load_data() {
         let mut test_vm = VM::new();
-        test_vm.program = vec![0x00, 0x00, 0x00, 0x00];;
+        let binary = Assembler::new()
+            .assemble_binary(
+                ".data\nmsg: .asciiz \"hi\"\n.byte 0xFF\n.code\n\
+                 load $1 @msg\nlbd $2 $1 #1\nlbd $3 $1 #3\nlhd $4 $1 #0\nlwd $5 $1 #0\nhlt\n",
+            )
+            .unwrap();
+        test_vm.load(binary);
         test_vm.run();
+        assert_eq!(
+            test_vm.registers[2..6],
+            [i32::from(b'i'), -1, 0x6869, 0x6869_00FF]
+        );
+    }
+
+    #[test]
+    fn test_opcode_hlt() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![0x00, 0x00, 0x00, 0x00];
+        test_vm.registers[EXIT_CODE_REGISTER] = 3;
 
+        assert_eq!(test_vm.run(), ExitStatus::Halted { code: 3 });
         assert_eq!(test_vm.pc, 1);
     }
 
     #[test]
     fn test_opcode_igl() {
         let mut test_vm = VM::new();
-        test_vm.program = vec![0xFF, 0x00, 0x00, 0x00];;
-        test_vm.run();
+        test_vm.program = vec![0xFF, 0x00, 0x00, 0x00];
+        assert_eq!(
+            test_vm.run(),
+            ExitStatus::Faulted(VmError::IllegalOpcode {
+                pc: 0,
+                opcode: 0xFF
+            })
+        );
 
         assert_eq!(test_vm.pc, 1);
     }
@@ -264,6 +1033,16 @@ mod tests {
         assert_eq!(test_vm.registers[0], 500);
     }
 
+    #[test]
+    fn test_opcode_loads() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![0x12, 0x00, 0xFF, 0xFF, 0x12, 0x01, 0x7F, 0xFF];
+        test_vm.run();
+
+        assert_eq!(test_vm.registers[0], -1);
+        assert_eq!(test_vm.registers[1], 32767);
+    }
+
     #[test]
     fn test_opcode_add() {
         let mut test_vm = VM::new();
@@ -317,12 +1096,62 @@ mod tests {
         assert_eq!(test_vm.remainder, 1);
     }
 
+    #[test]
+    fn test_opcode_bitwise() {
+        let mut test_vm = VM::new();
+        test_vm.registers[1] = 0b1100;
+        test_vm.registers[2] = 0b1010;
+        test_vm.registers[3] = -16;
+        test_vm.registers[4] = 2;
+        test_vm.program = vec![
+            0x23, 0x01, 0x02, 0x05, // and $1 $2 $5
+            0x24, 0x01, 0x02, 0x06, // or $1 $2 $6
+            0x25, 0x01, 0x02, 0x07, // xor $1 $2 $7
+            0x26, 0x01, 0x08, // not $1 $8
+            0x27, 0x03, 0x04, 0x09, // shl $3 $4 $9
+            0x28, 0x03, 0x04, 0x0A, // shr $3 $4 $10
+            0x29, 0x03, 0x04, 0x0B, // sar $3 $4 $11
+        ];
+        assert_eq!(test_vm.run(), ExitStatus::RanOffEnd);
+        assert_eq!(
+            test_vm.registers[5..12],
+            [0b1000, 0b1110, 0b0110, -13, -64, 0x3FFF_FFFC, -4]
+        );
+
+        test_vm.registers[4] = 33;
+        test_vm.pc = 0;
+        test_vm.program = vec![0x27, 0x01, 0x04, 0x09];
+        test_vm.run();
+        assert_eq!(test_vm.registers[9], 0b11000);
+    }
+
+    #[test]
+    fn test_opcode_mod() {
+        let mut test_vm = VM::new();
+        test_vm.registers[1] = -7;
+        test_vm.registers[2] = 3;
+        test_vm.program = vec![
+            0x2A, 0x01, 0x02, 0x03, // mod $1 $2 $3
+            0x05, 0x01, 0x02, 0x04, // div $1 $2 $4
+            0x2B, 0x05, // mfrem $5
+        ];
+        assert_eq!(test_vm.run(), ExitStatus::RanOffEnd);
+        assert_eq!(test_vm.registers[3..6], [2, -2, -1]);
+
+        test_vm.pc = 0;
+        test_vm.program = vec![0x2A, 0x01, 0x00, 0x03];
+        assert_eq!(
+            test_vm.run(),
+            ExitStatus::Faulted(VmError::DivisionByZero { pc: 0 })
+        );
+    }
+
     #[test]
     fn test_opcode_jmp() {
         let mut test_vm = VM::new();
         test_vm.program = vec![0x06, 0x00, 0x00, 0x00];
         test_vm.registers[0] = 1;
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.pc, 1);
     }
@@ -332,7 +1161,7 @@ mod tests {
         let mut test_vm = VM::new();
         test_vm.registers[0] = 2;
         test_vm.program = vec![0x07, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.pc, 4);
     }
@@ -342,7 +1171,7 @@ mod tests {
         let mut test_vm = VM::new();
         test_vm.registers[0] = 2;
         test_vm.program = vec![0x08, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
 
         assert_eq!(test_vm.pc, 0);
     }
@@ -353,10 +1182,10 @@ mod tests {
         test_vm.registers[0] = 10;
         test_vm.registers[1] = 10;
         test_vm.program = vec![0x09, 0x00, 0x01, 0x00, 0x09, 0x00, 0x01, 0x00];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.equal_flag, true);
         test_vm.registers[1] = 20;
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.equal_flag, false);
     }
 
@@ -366,10 +1195,10 @@ mod tests {
         test_vm.registers[0] = 10;
         test_vm.registers[1] = 11;
         test_vm.program = vec![0x0A, 0x00, 0x01, 0x00, 0x0A, 0x00, 0x01, 0x00];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.equal_flag, true);
         test_vm.registers[1] = 10;
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.equal_flag, false);
     }
 
@@ -379,10 +1208,10 @@ mod tests {
         test_vm.registers[0] = 11;
         test_vm.registers[1] = 10;
         test_vm.program = vec![0x0B, 0x00, 0x01, 0x00, 0x0B, 0x00, 0x01, 0x00];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.equal_flag, true);
         test_vm.registers[1] = 11;
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.equal_flag, false);
     }
 
@@ -392,10 +1221,10 @@ mod tests {
         test_vm.registers[0] = 10;
         test_vm.registers[1] = 11;
         test_vm.program = vec![0x0C, 0x00, 0x01, 0x00, 0x0C, 0x00, 0x01, 0x00];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.equal_flag, true);
         test_vm.registers[1] = 10;
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.equal_flag, false);
     }
 
@@ -407,13 +1236,13 @@ mod tests {
         test_vm.program = vec![
             0x0D, 0x00, 0x01, 0x00, 0x0D, 0x00, 0x01, 0x00, 0x0D, 0x00, 0x01, 0x00,
         ];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.equal_flag, true);
         test_vm.registers[0] = 11;
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.equal_flag, true);
         test_vm.registers[0] = 9;
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.equal_flag, false);
     }
 
@@ -425,13 +1254,13 @@ mod tests {
         test_vm.program = vec![
             0x0E, 0x00, 0x01, 0x00, 0x0E, 0x00, 0x01, 0x00, 0x0E, 0x00, 0x01, 0x00,
         ];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.equal_flag, true);
         test_vm.registers[0] = 10;
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.equal_flag, true);
         test_vm.registers[0] = 11;
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.equal_flag, false);
     }
 
@@ -440,8 +1269,8 @@ mod tests {
         let mut test_vm = VM::new();
         test_vm.registers[0] = 7;
         test_vm.equal_flag = true;
-        test_vm.program = vec![0x0F, 0x00, 0x00, 0x00];
-        test_vm.run_once();
+        test_vm.program = vec![0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.pc, 7);
     }
 
@@ -450,8 +1279,8 @@ mod tests {
         let mut test_vm = VM::new();
         test_vm.registers[0] = 7;
         test_vm.equal_flag = false;
-        test_vm.program = vec![0x10, 0x00, 0x00, 0x00];
-        test_vm.run_once();
+        test_vm.program = vec![0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.pc, 7);
     }
 
@@ -460,7 +1289,203 @@ mod tests {
         let mut test_vm = VM::new();
         test_vm.registers[0] = 1024;
         test_vm.program = vec![0x11, 0x00, 0x00, 0x00];
-        test_vm.run_once();
+        test_vm.run_once().unwrap();
         assert_eq!(test_vm.heap.len(), 1024);
     }
+
+    #[test]
+    fn test_heap_access() {
+        let mut test_vm = VM::new();
+        test_vm.heap = vec![0; 8];
+        test_vm.registers[1] = 4;
+        test_vm.registers[2] = -32_768;
+        test_vm.program = vec![
+            0x1B, 0x02, 0x01, 0xFF, 0xFC, // sw $2 $1 #-4
+            0x18, 0x03, 0x01, 0xFF, 0xFC, // lw $3 $1 #-4
+            0x17, 0x04, 0x01, 0xFF, 0xFE, // lh $4 $1 #-2
+            0x16, 0x05, 0x01, 0xFF, 0xFE, // lb $5 $1 #-2
+            0x19, 0x02, 0x01, 0x00, 0x03, // sb $2 $1 #3
+            0x1A, 0x02, 0x01, 0x00, 0x00, // sh $2 $1 #0
+        ];
+        assert_eq!(test_vm.run(), ExitStatus::RanOffEnd);
+        assert_eq!(
+            test_vm.heap,
+            vec![0xFF, 0xFF, 0x80, 0x00, 0x80, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(test_vm.registers[3..6], [-32_768, -32_768, -128]);
+
+        test_vm.pc = 0;
+        test_vm.registers[1] = 6;
+        test_vm.program = vec![0x18, 0x03, 0x01, 0x00, 0x00];
+        assert_eq!(
+            test_vm.run(),
+            ExitStatus::Faulted(VmError::HeapOutOfBounds {
+                pc: 0,
+                address: 6,
+                size: 4,
+                heap_len: 8
+            })
+        );
+
+        test_vm.pc = 0;
+        test_vm.program = vec![0x19, 0x03, 0x01, 0xFF, 0xF9];
+        assert_eq!(
+            test_vm.run(),
+            ExitStatus::Faulted(VmError::HeapOutOfBounds {
+                pc: 0,
+                address: -1,
+                size: 1,
+                heap_len: 8
+            })
+        );
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let mut test_vm = VM::new();
+        test_vm.program = Assembler::new()
+            .assemble(
+                "load $a0 #5\ncall @fact\nhlt\n\
+                 .func fact $s0\n\
+                 load $v0 #1\nload $t0 #1\nltq $a0 $t0\nload $t0 @.return\njeq $t0\n\
+                 mov $s0 $a0\ndec $a0\ncall @fact\nmul $v0 $s0 $v0\n\
+                 .endfunc\n",
+            )
+            .unwrap();
+        assert_eq!(test_vm.run(), ExitStatus::Halted { code: 120 });
+        assert_eq!(test_vm.registers[STACK_POINTER], DEFAULT_STACK_SIZE as i32);
+        assert_eq!(test_vm.registers[FRAME_POINTER], DEFAULT_STACK_SIZE as i32);
+        assert_eq!(test_vm.registers[16], 0);
+
+        test_vm.set_stack_size(64);
+        test_vm.pc = 0;
+        test_vm.program = vec![0x1C, 0x00, 0x1E, 0x00, 0x05, 0x1E, 0x00, 0x05];
+        assert_eq!(
+            test_vm.run(),
+            ExitStatus::Faulted(VmError::StackOverflow { pc: 5, size: 64 })
+        );
+        assert_eq!(test_vm.call_stack().len(), 7);
+        assert_eq!(test_vm.call_stack()[6], 5);
+        assert_eq!(test_vm.call_stack()[0], 8);
+
+        test_vm.set_stack_size(64);
+        test_vm.pc = 0;
+        test_vm.program = vec![0x1D, 0x00];
+        assert_eq!(
+            test_vm.run(),
+            ExitStatus::Faulted(VmError::StackUnderflow { pc: 0 })
+        );
+
+        test_vm.pc = 0;
+        test_vm.program = vec![0x20];
+        assert_eq!(
+            test_vm.run(),
+            ExitStatus::Faulted(VmError::StackUnderflow { pc: 0 })
+        );
+
+        test_vm.pc = 0;
+        test_vm.registers[1] = 7;
+        test_vm.program = vec![
+            0x22, 0x01, 0x1D, 0xFF, 0xFC, // sws $1 $sp #-4
+            0x21, 0x02, 0x1D, 0xFF, 0xFC, // lws $2 $sp #-4
+        ];
+        assert_eq!(test_vm.run(), ExitStatus::RanOffEnd);
+        assert_eq!(test_vm.registers[2], 7);
+        assert_eq!(test_vm.stack[60..], [0, 0, 0, 7]);
+
+        test_vm.pc = 0;
+        test_vm.program = vec![0x21, 0x01, 0x1D, 0x00, 0x00];
+        assert_eq!(
+            test_vm.run(),
+            ExitStatus::Faulted(VmError::StackOutOfBounds {
+                pc: 0,
+                address: 64,
+                size: 4,
+                stack_len: 64
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let run = |program: Vec<u8>, registers: &[(usize, i32)]| {
+            let mut test_vm = VM::new();
+            test_vm.program = program;
+
+            for (register, value) in registers {
+                test_vm.registers[*register] = *value;
+            }
+
+            test_vm.run()
+        };
+
+        assert_eq!(
+            run(vec![0x00, 0x01, 0x00], &[]),
+            ExitStatus::Halted { code: 0 }
+        );
+        assert_eq!(
+            run(vec![0x01, 0x00, 0x01, 0x01, 0x01, 0x00], &[]),
+            ExitStatus::Faulted(VmError::TruncatedInstruction { pc: 4 })
+        );
+        assert_eq!(
+            run(vec![0x01, 0x20, 0x00, 0x01], &[]),
+            ExitStatus::Faulted(VmError::InvalidRegister {
+                pc: 0,
+                register: 0x20
+            })
+        );
+        assert_eq!(
+            run(vec![0x01, 0x00, 0x00, 0x01, 0x05, 0x00, 0x01, 0x02], &[]),
+            ExitStatus::Faulted(VmError::DivisionByZero { pc: 4 })
+        );
+        assert_eq!(
+            run(vec![0x08, 0x00], &[(0, 3)]),
+            ExitStatus::Faulted(VmError::JumpOutOfBounds { pc: 0, target: -1 })
+        );
+        assert_eq!(
+            run(vec![0x06, 0x00], &[(0, 3)]),
+            ExitStatus::Faulted(VmError::JumpOutOfBounds { pc: 0, target: 3 })
+        );
+        assert_eq!(run(vec![0x06, 0x00], &[(0, 2)]), ExitStatus::RanOffEnd);
+        assert_eq!(
+            run(vec![0x11, 0x00], &[(0, -1)]),
+            ExitStatus::Faulted(VmError::NegativeAllocation { pc: 0, bytes: -1 })
+        );
+        assert_eq!(
+            run(vec![0x11, 0x00], &[(0, i32::MAX)]),
+            ExitStatus::Faulted(VmError::OutOfMemory {
+                pc: 0,
+                bytes: i32::MAX
+            })
+        );
+        assert_eq!(
+            run(vec![0x15, 0x00, 0x00, 0x00, 0x00], &[]),
+            ExitStatus::Faulted(VmError::DataOutOfBounds {
+                pc: 0,
+                address: 0,
+                size: 4,
+                data_len: 0
+            })
+        );
+        assert_eq!(
+            run(vec![0x02, 0x00, 0x00, 0x01], &[(0, i32::MAX)]),
+            ExitStatus::RanOffEnd
+        );
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let mut test_vm = VM::new();
+        test_vm.program = vec![0x06, 0x00];
+        assert_eq!(test_vm.run_with_budget(10), ExitStatus::OutOfBudget);
+        assert_eq!(test_vm.run_with_budget(10), ExitStatus::OutOfBudget);
+
+        test_vm.registers[0] = 2;
+        assert_eq!(test_vm.run_with_budget(10), ExitStatus::RanOffEnd);
+        assert_eq!(test_vm.run_once(), Ok(Some(ExitStatus::RanOffEnd)));
+
+        assert_eq!(ExitStatus::Halted { code: 7 }.code(), 7);
+        assert_eq!(ExitStatus::RanOffEnd.code(), 0);
+        assert_eq!(ExitStatus::OutOfBudget.code(), 1);
+    }
 }